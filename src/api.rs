@@ -0,0 +1,219 @@
+//! JSON-over-HTTP API for remote status queries
+//!
+//! Serves the [control socket](crate::control) commands over HTTP/1 for dashboards that can't
+//! reach a Unix socket:
+//!
+//! - `GET /status`: as the `status` command
+//! - `GET /interfaces`: the monitored interfaces, `{"ok":true,"interfaces":[...]}`
+//! - `POST /interfaces/<iface>/restart`: as the `restart <iface>` command
+//!
+//! With a token, every request must carry it as `Authorization: Bearer <token>`. Requests are
+//! delivered as [`ControlRequest`]s, to be answered by whoever owns the
+//! [`Monitor`](crate::Monitor) just like those from the control socket.
+
+use crate::{
+    control::{Command, ControlRequest, error_response},
+    http::{self, Request, Response},
+};
+use serde_json::Value;
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+use tracing::{info, warn};
+
+/// Requests queued for the owner of the monitor before clients have to wait
+const REQUEST_QUEUE: usize = 16;
+
+const CONTENT_TYPE: &str = "application/json";
+
+/// Serve the API on `addr` until the returned task is aborted
+///
+/// Without a `token` anyone who can reach the address can restart interfaces, which is warned
+/// about. Fails if the address can't be bound or `token` is empty. Must be called from within a
+/// tokio runtime.
+pub async fn serve(
+    addr: SocketAddr,
+    token: Option<String>,
+) -> io::Result<(JoinHandle<()>, mpsc::Receiver<ControlRequest>)> {
+    if token.as_deref().is_some_and(str::is_empty) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the API token is empty",
+        ));
+    }
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving the HTTP API on http://{}", listener.local_addr()?);
+    if token.is_none() {
+        warn!("The HTTP API has no token, anyone who can reach it can restart interfaces");
+    }
+
+    let token = token.map(Arc::<str>::from);
+    let (tx, requests) = mpsc::channel(REQUEST_QUEUE);
+    let task = http::serve(listener, "API", move |request| {
+        let tx = tx.clone();
+        let token = token.clone();
+        async move { respond(request, tx, token.as_deref()).await }
+    });
+    Ok((task, requests))
+}
+
+/// Answer a request, or nothing if monitoring is shutting down
+async fn respond(
+    request: Request,
+    tx: mpsc::Sender<ControlRequest>,
+    token: Option<&str>,
+) -> Option<Response> {
+    if !authorized(&request, token) {
+        let body = error_response("missing or wrong bearer token");
+        return Some(
+            Response::new("401 Unauthorized", CONTENT_TYPE, body.to_string())
+                .with_header("WWW-Authenticate", "Bearer"),
+        );
+    }
+    let command = match route(&request.method, &request.path) {
+        Ok(command) => command,
+        Err(status) => {
+            return Some(Response::new(
+                status,
+                CONTENT_TYPE,
+                error_response(status).to_string(),
+            ));
+        }
+    };
+    let (request, response) = ControlRequest::new(command.clone());
+    tx.send(request).await.ok()?;
+    let response = response.await.ok()?;
+    Some(Response::new(
+        response_status(&command, &response),
+        CONTENT_TYPE,
+        response.to_string(),
+    ))
+}
+
+/// Whether `request` carries `token`, if one is required
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    token.is_none_or(|token| {
+        request
+            .headers("authorization")
+            .filter_map(|value| value.strip_prefix("Bearer "))
+            .any(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+    })
+}
+
+/// Command for a request, or the status to answer it with if there is none
+fn route(method: &str, path: &str) -> Result<Command, &'static str> {
+    let segments: Vec<&str> = path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["status"]) => Ok(Command::Status),
+        ("GET", ["interfaces"]) => Ok(Command::Interfaces),
+        ("POST", ["interfaces", name, "restart"]) => Ok(Command::Restart(name.to_string())),
+        (_, ["status"] | ["interfaces"] | ["interfaces", _, "restart"]) => {
+            Err("405 Method Not Allowed")
+        }
+        _ => Err("404 Not Found"),
+    }
+}
+
+/// Status to answer `command` with given its `response`
+fn response_status(command: &Command, response: &Value) -> &'static str {
+    match (
+        command,
+        response["ok"].as_bool(),
+        response["restarted"].as_bool(),
+    ) {
+        (_, Some(true), Some(false)) => "500 Internal Server Error",
+        (_, Some(true), _) => "200 OK",
+        // A restart is only refused for interfaces that aren't monitored
+        (Command::Restart(_), _, _) => "404 Not Found",
+        _ => "500 Internal Server Error",
+    }
+}
+
+/// Compare secrets without returning early at the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(head: &str) -> Request {
+        Request::parse(&format!("{head}\r\n\r\n"))
+    }
+
+    #[test]
+    fn routes_requests_to_commands() {
+        assert_eq!(route("GET", "/status"), Ok(Command::Status));
+        assert_eq!(
+            route("GET", "/interfaces/?verbose"),
+            Ok(Command::Interfaces)
+        );
+        assert_eq!(
+            route("POST", "/interfaces/can0/restart"),
+            Ok(Command::Restart("can0".to_string()))
+        );
+        assert_eq!(
+            route("GET", "/interfaces/can0/restart"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(route("GET", "/metrics"), Err("404 Not Found"));
+    }
+
+    #[test]
+    fn requests_need_the_token_if_there_is_one() {
+        let with_token = request("GET /status HTTP/1.1\r\nAuthorization: Bearer secret");
+        let without = request("GET /status HTTP/1.1");
+        let wrong = request("GET /status HTTP/1.1\r\nauthorization: Bearer secrets");
+        assert!(authorized(&with_token, Some("secret")));
+        assert!(!authorized(&without, Some("secret")));
+        assert!(!authorized(&wrong, Some("secret")));
+        assert!(authorized(&without, None));
+    }
+
+    #[test]
+    fn only_unknown_interfaces_are_not_found() {
+        let restart = Command::Restart("can0".to_string());
+        assert_eq!(
+            response_status(&restart, &json!({ "ok": true, "restarted": true })),
+            "200 OK"
+        );
+        assert_eq!(
+            response_status(&restart, &json!({ "ok": true, "restarted": false })),
+            "500 Internal Server Error"
+        );
+        assert_eq!(
+            response_status(
+                &restart,
+                &error_response("interface 'can9' is not monitored")
+            ),
+            "404 Not Found"
+        );
+        assert_eq!(
+            response_status(&Command::Status, &error_response("broken")),
+            "500 Internal Server Error"
+        );
+    }
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn empty_tokens_are_rejected() {
+        let error = serve("127.0.0.1:0".parse().unwrap(), Some(String::new()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,364 @@
+//! Abstraction over the socketcan operations cansentinel uses
+//!
+//! Monitoring and restart logic are written against these traits so they can run against an
+//! in-memory fake (see [`fake`], behind the `testing` feature) instead of real CAN hardware.
+//!
+//! [`CanControl`] is also the extension point for other restart mechanisms, such as running
+//! `ip link` or a vendor tool: implement it and pass it to
+//! [`RestartManager::with_control`](crate::RestartManager::with_control) or
+//! [`MonitorOptions::control`](crate::MonitorOptions::control).
+
+use crate::{config::LinkSettings, events::ErrorCounters, monitoring::netlink};
+use neli::err::{NlError, WrappedError};
+use socketcan::{
+    CanAnyFrame, CanFrame, CanInterface, SocketOptions,
+    async_io::{CanFdSocket, CanSocket},
+    nl::{CanState, Mtu},
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    future::Future,
+    io,
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    time::{Duration, SystemTime},
+};
+use tracing::debug;
+
+/// What to do about an operation failing with [`io::ErrorKind::PermissionDenied`]
+pub const PERMISSION_HINT: &str = "cansentinel needs CAP_NET_ADMIN; run as root or grant the capability \
+     (e.g. AmbientCapabilities=CAP_NET_ADMIN in the systemd unit)";
+
+/// `SIOCGSTAMP` ioctl: timestamp of the last packet received on a socket
+const SIOCGSTAMP: u64 = 0x8906;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod fake;
+
+/// Convert a netlink error to an [`io::Error`], keeping the errno the kernel answered with
+///
+/// Callers tell failures such as missing privileges apart by their [`io::ErrorKind`], which the
+/// message alone would lose.
+pub(crate) fn nl_error<T, P>(e: NlError<T, P>) -> io::Error
+where
+    NlError<T, P>: fmt::Display,
+{
+    match e {
+        // Netlink acks carry the negated errno
+        NlError::Nlmsgerr(err) => io::Error::from_raw_os_error(err.error.abs()),
+        NlError::Wrapped(WrappedError::IOError(e)) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+/// Interface control operations, independent of any socket
+///
+/// Only [`CanControl::restart`] and [`CanControl::state`] are required; the other operations
+/// report [`io::ErrorKind::Unsupported`] unless implemented.
+pub trait CanControl: Debug + Send + Sync + 'static {
+    /// Restart an interface that is in bus-off state
+    fn restart(&self, idx: u32) -> io::Result<()>;
+
+    /// Current CAN state of an interface
+    fn state(&self, idx: u32) -> io::Result<Option<CanState>>;
+
+    /// Current CAN state of each of `idxs`, by interface index, leaving out those whose state
+    /// can't be read
+    fn states(&self, idxs: &[u32]) -> HashMap<u32, CanState> {
+        idxs.iter()
+            .filter_map(|&idx| Some((idx, self.state(idx).ok()??)))
+            .collect()
+    }
+
+    /// Delay after which the kernel restarts a bus-off interface on its own, 0 if disabled
+    fn restart_ms(&self, idx: u32) -> io::Result<Option<u32>> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Whether an interface is configured for CAN FD
+    fn is_fd(&self, idx: u32) -> io::Result<bool> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Bring up an interface that is administratively down
+    fn bring_up(&self, idx: u32) -> io::Result<()> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Take an interface administratively down
+    fn bring_down(&self, idx: u32) -> io::Result<()> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Apply link settings, taking the interface down and back up to do so
+    ///
+    /// The kernel only accepts these while the interface is down. Taking it down also clears
+    /// bus-off, so it comes back up error-active.
+    fn reconfigure(&self, idx: u32, settings: LinkSettings) -> io::Result<()> {
+        let _ = (idx, settings);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Transmit and receive error counters of an interface, `None` if the driver doesn't report
+    /// them
+    fn error_counters(&self, idx: u32) -> io::Result<Option<ErrorCounters>> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Clear the error counters of an interface without taking it down, where the driver can
+    ///
+    /// Unsupported by default, in which case the
+    /// [`RestartManager`](crate::RestartManager) cycles the interface instead, as opening it
+    /// reinitializes the controller.
+    fn reset_error_counters(&self, idx: u32) -> io::Result<()> {
+        let _ = idx;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// A socket used to receive error frames from a single interface
+pub trait ErrorSocket: Send + Sync + 'static {
+    /// Accept all error frame classes
+    fn set_error_filter_accept_all(&self) -> io::Result<()>;
+
+    /// Drop all regular data frames
+    fn set_filter_drop_all(&self) -> io::Result<()>;
+
+    /// Request a receive buffer size, returning the size the kernel actually applied
+    fn set_rx_buffer(&self, bytes: usize) -> io::Result<usize>;
+
+    /// Set `CAN_RAW_LOOPBACK`: whether frames sent by other sockets on this host are received
+    fn set_loopback(&self, enabled: bool) -> io::Result<()> {
+        let _ = enabled;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Set `CAN_RAW_RECV_OWN_MSGS`: whether frames sent on this socket are received back
+    fn set_recv_own_msgs(&self, enabled: bool) -> io::Result<()> {
+        let _ = enabled;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Ask the kernel to timestamp every received frame
+    fn enable_timestamps(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Kernel receive timestamp of the most recently read frame
+    fn last_rx_timestamp(&self) -> io::Result<SystemTime> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Wait for the next frame
+    fn read_frame(&self) -> impl Future<Output = io::Result<CanFrame>> + Send;
+}
+
+/// Full set of socketcan operations: interface control plus error frame sockets
+pub trait CanBackend: CanControl {
+    /// Socket type returned by [`CanBackend::open`]
+    type Socket: ErrorSocket;
+
+    /// Open an error frame socket on the named interface
+    fn open(&self, name: &str) -> io::Result<Self::Socket>;
+
+    /// Open an error frame socket with CAN FD frames enabled (`CAN_RAW_FD_FRAMES`)
+    ///
+    /// Error frames keep the classic layout on FD interfaces. FD mode only keeps the socket in
+    /// step with the interface; any FD data frames it receives are skipped by
+    /// [`ErrorSocket::read_frame`].
+    fn open_fd(&self, name: &str) -> io::Result<Self::Socket> {
+        let _ = name;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Backend that talks to the kernel through socketcan
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketCanBackend;
+
+impl CanControl for SocketCanBackend {
+    fn restart(&self, idx: u32) -> io::Result<()> {
+        CanInterface::open_iface(idx).restart().map_err(nl_error)
+    }
+
+    fn state(&self, idx: u32) -> io::Result<Option<CanState>> {
+        CanInterface::open_iface(idx).state().map_err(nl_error)
+    }
+
+    /// Read with a single netlink dump, or one interface at a time if that fails
+    fn states(&self, idxs: &[u32]) -> HashMap<u32, CanState> {
+        match netlink::dump_states() {
+            Ok(mut states) => {
+                states.retain(|idx, _| idxs.contains(idx));
+                states
+            }
+            Err(e) => {
+                debug!(
+                    "Netlink link dump failed, reading interface states one at a time: {}",
+                    e
+                );
+                idxs.iter()
+                    .filter_map(|&idx| Some((idx, self.state(idx).ok()??)))
+                    .collect()
+            }
+        }
+    }
+
+    fn restart_ms(&self, idx: u32) -> io::Result<Option<u32>> {
+        CanInterface::open_iface(idx).restart_ms().map_err(nl_error)
+    }
+
+    fn is_fd(&self, idx: u32) -> io::Result<bool> {
+        let details = CanInterface::open_iface(idx).details().map_err(nl_error)?;
+        Ok(details.mtu == Some(Mtu::Fd))
+    }
+
+    fn bring_up(&self, idx: u32) -> io::Result<()> {
+        CanInterface::open_iface(idx).bring_up().map_err(nl_error)
+    }
+
+    fn bring_down(&self, idx: u32) -> io::Result<()> {
+        CanInterface::open_iface(idx).bring_down().map_err(nl_error)
+    }
+
+    fn reconfigure(&self, idx: u32, settings: LinkSettings) -> io::Result<()> {
+        let iface = CanInterface::open_iface(idx);
+        iface.bring_down().map_err(nl_error)?;
+        let applied = settings
+            .bitrate
+            .map_or(Ok(()), |bitrate| iface.set_bitrate(bitrate, None))
+            .and_then(|_| {
+                settings
+                    .restart_ms
+                    .map_or(Ok(()), |restart_ms| iface.set_restart_ms(restart_ms))
+            })
+            .map_err(nl_error);
+        // Come back up even if the settings were rejected, rather than leaving the bus down
+        iface.bring_up().map_err(nl_error)?;
+        applied
+    }
+
+    fn error_counters(&self, idx: u32) -> io::Result<Option<ErrorCounters>> {
+        let details = CanInterface::open_iface(idx).details().map_err(nl_error)?;
+        Ok(details.can.berr_counter.map(|counter| ErrorCounters {
+            tx: u32::from(counter.txerr),
+            rx: u32::from(counter.rxerr),
+        }))
+    }
+}
+
+impl CanBackend for SocketCanBackend {
+    type Socket = SocketCanSocket;
+
+    fn open(&self, name: &str) -> io::Result<Self::Socket> {
+        CanSocket::open(name).map(SocketCanSocket::Classic)
+    }
+
+    fn open_fd(&self, name: &str) -> io::Result<Self::Socket> {
+        CanFdSocket::open(name).map(SocketCanSocket::Fd)
+    }
+}
+
+/// Error frame socket opened by [`SocketCanBackend`]
+pub enum SocketCanSocket {
+    /// Classic CAN socket
+    Classic(CanSocket),
+    /// Socket with CAN FD frames enabled, for FD interfaces
+    Fd(CanFdSocket),
+}
+
+impl AsRawFd for SocketCanSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Classic(socket) => socket.as_raw_fd(),
+            Self::Fd(socket) => socket.as_raw_fd(),
+        }
+    }
+}
+
+impl SocketOptions for SocketCanSocket {}
+
+impl ErrorSocket for SocketCanSocket {
+    fn set_error_filter_accept_all(&self) -> io::Result<()> {
+        SocketOptions::set_error_filter_accept_all(self)
+    }
+
+    fn set_filter_drop_all(&self) -> io::Result<()> {
+        SocketOptions::set_filter_drop_all(self)
+    }
+
+    fn set_rx_buffer(&self, bytes: usize) -> io::Result<usize> {
+        use nix::sys::socket::{getsockopt, setsockopt, sockopt::RcvBuf};
+
+        // SAFETY: the descriptor is owned by `self`, which outlives this borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) };
+        setsockopt(&fd, RcvBuf, &bytes)?;
+        Ok(getsockopt(&fd, RcvBuf)?)
+    }
+
+    fn set_loopback(&self, enabled: bool) -> io::Result<()> {
+        SocketOptions::set_loopback(self, enabled)
+    }
+
+    fn set_recv_own_msgs(&self, enabled: bool) -> io::Result<()> {
+        SocketOptions::set_recv_own_msgs(self, enabled)
+    }
+
+    fn enable_timestamps(&self) -> io::Result<()> {
+        use nix::sys::socket::{setsockopt, sockopt::ReceiveTimestamp};
+
+        // SAFETY: the descriptor is owned by `self`, which outlives this borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) };
+        Ok(setsockopt(&fd, ReceiveTimestamp, &true)?)
+    }
+
+    fn last_rx_timestamp(&self) -> io::Result<SystemTime> {
+        let mut tv = nix::libc::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+        // SAFETY: SIOCGSTAMP writes a single timeval into `tv`
+        if unsafe { nix::libc::ioctl(self.as_raw_fd(), SIOCGSTAMP as _, &mut tv) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SystemTime::UNIX_EPOCH + Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000))
+    }
+
+    fn read_frame(&self) -> impl Future<Output = io::Result<CanFrame>> + Send {
+        async move {
+            match self {
+                Self::Classic(socket) => socket.read_frame().await,
+                Self::Fd(socket) => loop {
+                    match socket.read_frame().await? {
+                        CanAnyFrame::Normal(frame) => return Ok(CanFrame::Data(frame)),
+                        CanAnyFrame::Remote(frame) => return Ok(CanFrame::Remote(frame)),
+                        CanAnyFrame::Error(frame) => return Ok(CanFrame::Error(frame)),
+                        // Only data frames have an FD form, and those are filtered out anyway
+                        CanAnyFrame::Fd(_) => continue,
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netlink_errors_keep_their_errno() {
+        let denied: NlError = NlError::Wrapped(WrappedError::IOError(
+            io::Error::from_raw_os_error(nix::libc::EPERM),
+        ));
+        assert_eq!(nl_error(denied).kind(), io::ErrorKind::PermissionDenied);
+
+        let other: NlError = NlError::Msg("bad attribute".to_string());
+        assert_eq!(nl_error(other).kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,278 @@
+//! In-memory backend for tests
+//!
+//! Frames pushed with [`FakeBackend::push_frame`] are delivered to sockets opened on that
+//! interface, and restarts are recorded instead of reaching the kernel.
+
+use super::{CanBackend, CanControl, ErrorSocket};
+use crate::{config::LinkSettings, events::ErrorCounters};
+use socketcan::{CanFrame, nl::CanState};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+
+type FrameSender = mpsc::UnboundedSender<io::Result<CanFrame>>;
+type FrameReceiver = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<io::Result<CanFrame>>>>;
+
+#[derive(Debug, Default)]
+struct FakeState {
+    frames: HashMap<String, (FrameSender, FrameReceiver)>,
+    states: HashMap<u32, CanState>,
+    restarts: Vec<u32>,
+    bring_ups: Vec<u32>,
+    bring_downs: Vec<u32>,
+    reconfigured: Vec<(u32, LinkSettings)>,
+    /// Interfaces that stay bus-off after a restart
+    stuck: HashSet<u32>,
+    /// Interfaces whose driver rejects link settings
+    rejects_settings: HashSet<u32>,
+    /// Interfaces that may not be restarted, as without CAP_NET_ADMIN
+    denied: HashSet<u32>,
+    /// Interfaces configured for CAN FD
+    fd: HashSet<u32>,
+    /// Interfaces an FD socket was opened on
+    fd_opens: Vec<String>,
+    /// Error counters reported, by interface
+    counters: HashMap<u32, ErrorCounters>,
+    /// Interfaces that can't be brought up
+    failing_bring_ups: HashSet<u32>,
+}
+
+impl FakeState {
+    fn channel(&mut self, name: &str) -> &(FrameSender, FrameReceiver) {
+        self.frames.entry(name.to_string()).or_insert_with(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (tx, Arc::new(tokio::sync::Mutex::new(rx)))
+        })
+    }
+}
+
+/// Fake socketcan backend
+///
+/// Clones share the same state, so a test can keep one clone while handing another to the code
+/// under test.
+#[derive(Debug, Clone, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    /// Create a backend with no frames queued and every interface in an unknown state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a frame for delivery on the named interface
+    pub fn push_frame(&self, name: &str, frame: impl Into<CanFrame>) {
+        let mut state = self.state.lock().unwrap();
+        let _ = state.channel(name).0.send(Ok(frame.into()));
+    }
+
+    /// Queue a read error on the named interface
+    pub fn push_error(&self, name: &str, error: io::Error) {
+        let mut state = self.state.lock().unwrap();
+        let _ = state.channel(name).0.send(Err(error));
+    }
+
+    /// Set the state reported for an interface
+    pub fn set_state(&self, idx: u32, can_state: CanState) {
+        self.state.lock().unwrap().states.insert(idx, can_state);
+    }
+
+    /// Make restarts of an interface succeed without bringing it out of bus-off
+    pub fn set_stuck(&self, idx: u32, stuck: bool) {
+        let mut state = self.state.lock().unwrap();
+        if stuck {
+            state.stuck.insert(idx);
+        } else {
+            state.stuck.remove(&idx);
+        }
+    }
+
+    /// Make re-applying link settings on an interface fail, after it is brought back up anyway
+    pub fn set_rejects_settings(&self, idx: u32, rejects: bool) {
+        let mut state = self.state.lock().unwrap();
+        if rejects {
+            state.rejects_settings.insert(idx);
+        } else {
+            state.rejects_settings.remove(&idx);
+        }
+    }
+
+    /// Make restarting an interface fail with `EPERM`, as it does without CAP_NET_ADMIN
+    pub fn set_denied(&self, idx: u32, denied: bool) {
+        let mut state = self.state.lock().unwrap();
+        if denied {
+            state.denied.insert(idx);
+        } else {
+            state.denied.remove(&idx);
+        }
+    }
+
+    /// Report an interface as configured for CAN FD
+    pub fn set_fd(&self, idx: u32, fd: bool) {
+        let mut state = self.state.lock().unwrap();
+        if fd {
+            state.fd.insert(idx);
+        } else {
+            state.fd.remove(&idx);
+        }
+    }
+
+    /// Names of the interfaces FD sockets were opened on so far, in order
+    pub fn fd_opens(&self) -> Vec<String> {
+        self.state.lock().unwrap().fd_opens.clone()
+    }
+
+    /// Set the error counters reported for an interface, which restarts leave alone
+    pub fn set_error_counters(&self, idx: u32, counters: ErrorCounters) {
+        self.state.lock().unwrap().counters.insert(idx, counters);
+    }
+
+    /// Make bringing an interface up fail, or work again
+    pub fn set_fails_bring_up(&self, idx: u32, fails: bool) {
+        let mut state = self.state.lock().unwrap();
+        if fails {
+            state.failing_bring_ups.insert(idx);
+        } else {
+            state.failing_bring_ups.remove(&idx);
+        }
+    }
+
+    /// Interface indexes restarted so far, in order
+    pub fn restarts(&self) -> Vec<u32> {
+        self.state.lock().unwrap().restarts.clone()
+    }
+
+    /// Interface indexes brought up so far, in order
+    pub fn bring_ups(&self) -> Vec<u32> {
+        self.state.lock().unwrap().bring_ups.clone()
+    }
+
+    /// Interface indexes taken down so far, in order
+    pub fn bring_downs(&self) -> Vec<u32> {
+        self.state.lock().unwrap().bring_downs.clone()
+    }
+
+    /// Link settings applied so far, in order
+    pub fn reconfigured(&self) -> Vec<(u32, LinkSettings)> {
+        self.state.lock().unwrap().reconfigured.clone()
+    }
+}
+
+impl CanControl for FakeBackend {
+    fn restart(&self, idx: u32) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.denied.contains(&idx) {
+            return Err(io::Error::from_raw_os_error(nix::libc::EPERM));
+        }
+        state.restarts.push(idx);
+        let can_state = if state.stuck.contains(&idx) {
+            CanState::BusOff
+        } else {
+            CanState::ErrorActive
+        };
+        state.states.insert(idx, can_state);
+        Ok(())
+    }
+
+    fn state(&self, idx: u32) -> io::Result<Option<CanState>> {
+        Ok(self.state.lock().unwrap().states.get(&idx).copied())
+    }
+
+    fn is_fd(&self, idx: u32) -> io::Result<bool> {
+        Ok(self.state.lock().unwrap().fd.contains(&idx))
+    }
+
+    /// Opening the interface reinitializes the controller, clearing its error counters
+    fn bring_up(&self, idx: u32) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.bring_ups.push(idx);
+        if state.failing_bring_ups.contains(&idx) {
+            return Err(io::ErrorKind::Other.into());
+        }
+        state.states.insert(idx, CanState::ErrorActive);
+        if let Some(counters) = state.counters.get_mut(&idx) {
+            *counters = ErrorCounters { tx: 0, rx: 0 };
+        }
+        Ok(())
+    }
+
+    fn bring_down(&self, idx: u32) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.bring_downs.push(idx);
+        state.states.insert(idx, CanState::Stopped);
+        Ok(())
+    }
+
+    fn reconfigure(&self, idx: u32, settings: LinkSettings) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.reconfigured.push((idx, settings));
+        state.states.insert(idx, CanState::ErrorActive);
+        if state.rejects_settings.contains(&idx) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        Ok(())
+    }
+
+    fn error_counters(&self, idx: u32) -> io::Result<Option<ErrorCounters>> {
+        Ok(self.state.lock().unwrap().counters.get(&idx).copied())
+    }
+}
+
+impl CanBackend for FakeBackend {
+    type Socket = FakeSocket;
+
+    fn open(&self, name: &str) -> io::Result<Self::Socket> {
+        let mut state = self.state.lock().unwrap();
+        Ok(FakeSocket {
+            frames: Arc::clone(&state.channel(name).1),
+        })
+    }
+
+    fn open_fd(&self, name: &str) -> io::Result<Self::Socket> {
+        self.state.lock().unwrap().fd_opens.push(name.to_string());
+        self.open(name)
+    }
+}
+
+/// Socket returned by [`FakeBackend::open`] and [`FakeBackend::open_fd`]
+#[derive(Debug)]
+pub struct FakeSocket {
+    frames: FrameReceiver,
+}
+
+impl ErrorSocket for FakeSocket {
+    fn set_error_filter_accept_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_filter_drop_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_rx_buffer(&self, bytes: usize) -> io::Result<usize> {
+        Ok(bytes)
+    }
+
+    fn set_loopback(&self, _enabled: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_recv_own_msgs(&self, _enabled: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_frame(&self) -> impl Future<Output = io::Result<CanFrame>> + Send {
+        let frames = Arc::clone(&self.frames);
+        async move {
+            match frames.lock().await.recv().await {
+                Some(frame) => frame,
+                None => Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+    }
+}
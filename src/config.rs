@@ -1,6 +1,21 @@
 //! Configuration types
 
-use std::time::Duration;
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Restart delay used when none is configured
+pub const DEFAULT_RESTART_DELAY: Duration = Duration::from_millis(1000);
+
+/// Longest restart delay [`ConfigBuilder`] accepts, longer ones are clamped to it
+pub const MAX_RESTART_DELAY: Duration = Duration::from_secs(60 * 60);
 
 /// Configuration for cansentinel
 #[derive(Debug, Clone)]
@@ -9,13 +24,664 @@ pub struct Config {
     pub restart_delay: Duration,
     /// List of CAN interface names to monitor
     pub interface_names: Vec<String>,
+    /// Per-interface restart delay overrides, keyed by interface name
+    pub interface_delays: HashMap<String, Duration>,
+    /// Link settings re-applied on restart, keyed by interface name
+    pub link_settings: HashMap<String, LinkSettings>,
+    /// How bus-off interfaces are restarted
+    pub restart_method: RestartMethod,
+    /// Per-interface restart method overrides, keyed by interface name
+    pub restart_methods: HashMap<String, RestartMethod>,
+    /// Interfaces whose error counters are reset after a restart, by name
+    pub reset_counters: HashSet<String>,
+}
+
+/// How a bus-off interface is restarted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestartMethod {
+    /// The CAN restart request, as with `ip link set can0 type can restart`
+    #[default]
+    Ioctl,
+    /// Take the interface down, wait for it to settle and bring it back up, for drivers whose
+    /// controllers don't recover from a restart request
+    DownUp,
+}
+
+impl FromStr for RestartMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ioctl" => Ok(Self::Ioctl),
+            "downup" => Ok(Self::DownUp),
+            _ => Err(format!(
+                "invalid restart method '{s}' (expected ioctl or downup)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RestartMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ioctl => f.write_str("ioctl"),
+            Self::DownUp => f.write_str("downup"),
+        }
+    }
+}
+
+/// Link settings re-applied to an interface whenever it is restarted, for controllers that lose
+/// their configuration on bus-off recovery
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkSettings {
+    /// Bitrate in bits per second
+    pub bitrate: Option<u32>,
+    /// Kernel automatic restart delay in milliseconds
+    pub restart_ms: Option<u32>,
+}
+
+impl LinkSettings {
+    /// Whether there is nothing to re-apply
+    pub fn is_empty(&self) -> bool {
+        self.bitrate.is_none() && self.restart_ms.is_none()
+    }
+}
+
+impl fmt::Display for LinkSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(bitrate) = self.bitrate {
+            settings.push(format!("bitrate {}", bitrate));
+        }
+        if let Some(restart_ms) = self.restart_ms {
+            settings.push(format!("restart-ms {}", restart_ms));
+        }
+        write!(f, "{}", settings.join(", "))
+    }
 }
 
 impl Config {
-    pub fn new(bus_off_delay: Duration, interface_names: Vec<String>) -> Self {
+    /// Create a configuration monitoring `interface_names`
+    ///
+    /// Names are trimmed, and empty or repeated names are dropped with a warning.
+    pub fn new(restart_delay: Duration, interface_names: Vec<String>) -> Self {
+        let mut config = Self {
+            restart_delay,
+            interface_names: Vec::with_capacity(interface_names.len()),
+            interface_delays: HashMap::new(),
+            link_settings: HashMap::new(),
+            restart_method: RestartMethod::default(),
+            restart_methods: HashMap::new(),
+            reset_counters: HashSet::new(),
+        };
+        for name in &interface_names {
+            config.add_interface(name);
+        }
+        config
+    }
+
+    /// Start building a configuration, see [`ConfigBuilder`]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Add an interface to monitor
+    ///
+    /// The name is trimmed. Returns false, with a warning, if it is empty or already listed.
+    pub fn add_interface(&mut self, name: &str) -> bool {
+        let name = name.trim();
+        if name.is_empty() {
+            warn!("Ignoring an empty interface name");
+            return false;
+        }
+        if self.interface_names.iter().any(|listed| listed == name) {
+            warn!(
+                "Interface '{}' is listed more than once, ignoring the duplicate",
+                name
+            );
+            return false;
+        }
+        self.interface_names.push(name.to_string());
+        true
+    }
+
+    /// Load a configuration file
+    ///
+    /// The file is TOML with an optional global `delay_ms` and `restart_method` and an
+    /// `[[interface]]` array of `{ name, delay_ms, bitrate, restart_ms, restart_method,
+    /// reset_counters }` tables, where `delay_ms` and `restart_method` override the global ones
+    /// for that interface, `bitrate` and `restart_ms` are [`LinkSettings`] re-applied on every
+    /// restart and `reset_counters` resets error counters left raised by a restart:
+    ///
+    /// ```toml
+    /// delay_ms = 1000
+    ///
+    /// [[interface]]
+    /// name = "can0"
+    /// delay_ms = 200
+    /// bitrate = 500000
+    /// restart_method = "downup"
+    /// reset_counters = true
+    ///
+    /// [[interface]]
+    /// name = "can1"
+    /// delay_ms = 2000
+    /// ```
+    ///
+    /// Unknown keys are rejected, so a misspelled setting can't silently do nothing. A learn mode
+    /// report is a valid file.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&contents)
+    }
+
+    /// Parse a configuration from TOML, see [`Config::from_file`]
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        let file: ConfigFile = toml::from_str(contents).map_err(ConfigError::Parse)?;
+
+        let mut config = Self::new(
+            file.delay_ms
+                .map_or(DEFAULT_RESTART_DELAY, Duration::from_millis),
+            Vec::with_capacity(file.interface.len()),
+        );
+        config.restart_method = file.restart_method.unwrap_or_default();
+        for interface in file.interface {
+            let name = interface.name.trim();
+            if name.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "an [[interface]] table has an empty name".to_string(),
+                ));
+            }
+            if let Some(delay_ms) = interface.delay_ms {
+                config
+                    .interface_delays
+                    .insert(name.to_string(), Duration::from_millis(delay_ms));
+            }
+            let settings = LinkSettings {
+                bitrate: interface.bitrate,
+                restart_ms: interface.restart_ms,
+            };
+            if !settings.is_empty() {
+                config.link_settings.insert(name.to_string(), settings);
+            }
+            if let Some(method) = interface.restart_method {
+                config.restart_methods.insert(name.to_string(), method);
+            }
+            if interface.reset_counters {
+                config.reset_counters.insert(name.to_string());
+            }
+            config.add_interface(name);
+        }
+        Ok(config)
+    }
+
+    /// Link settings to re-apply when an interface is restarted, if any
+    pub fn link_settings_for(&self, name: &str) -> Option<LinkSettings> {
+        self.link_settings.get(name).copied()
+    }
+
+    /// Restart delay for an interface, falling back to the global delay
+    pub fn restart_delay_for(&self, name: &str) -> Duration {
+        self.interface_delays
+            .get(name)
+            .copied()
+            .unwrap_or(self.restart_delay)
+    }
+
+    /// Restart method for an interface, falling back to the global method
+    pub fn restart_method_for(&self, name: &str) -> RestartMethod {
+        self.restart_methods
+            .get(name)
+            .copied()
+            .unwrap_or(self.restart_method)
+    }
+
+    /// Whether an interface's error counters are reset after it is restarted
+    pub fn reset_counters_for(&self, name: &str) -> bool {
+        self.reset_counters.contains(name)
+    }
+}
+
+/// Builds a validated [`Config`] from code
+///
+/// ```
+/// use cansentinel::Config;
+/// use std::time::Duration;
+///
+/// let config = Config::builder()
+///     .with_restart_delay(Duration::from_millis(500))
+///     .with_interface("can0")
+///     .with_interface("can1")
+///     .with_interface_delay("can1", Duration::from_secs(2))
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.restart_delay_for("can1"), Duration::from_secs(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    restart_delay: Duration,
+    interface_names: Vec<String>,
+    interface_delays: HashMap<String, Duration>,
+    link_settings: HashMap<String, LinkSettings>,
+    restart_method: RestartMethod,
+    restart_methods: HashMap<String, RestartMethod>,
+    reset_counters: HashSet<String>,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
         Self {
-            restart_delay: bus_off_delay,
-            interface_names,
+            restart_delay: DEFAULT_RESTART_DELAY,
+            interface_names: Vec::new(),
+            interface_delays: HashMap::new(),
+            link_settings: HashMap::new(),
+            restart_method: RestartMethod::default(),
+            restart_methods: HashMap::new(),
+            reset_counters: HashSet::new(),
         }
     }
 }
+
+impl ConfigBuilder {
+    /// Delay before restarting a bus-off interface, [`DEFAULT_RESTART_DELAY`] if not set
+    pub fn with_restart_delay(mut self, delay: Duration) -> Self {
+        self.restart_delay = delay;
+        self
+    }
+
+    /// Monitor an interface
+    pub fn with_interface(mut self, name: impl Into<String>) -> Self {
+        self.interface_names.push(name.into());
+        self
+    }
+
+    /// Monitor each of `names`
+    pub fn with_interfaces<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.interface_names
+            .extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Override the restart delay for one interface
+    pub fn with_interface_delay(mut self, name: impl Into<String>, delay: Duration) -> Self {
+        self.interface_delays.insert(name.into(), delay);
+        self
+    }
+
+    /// Re-apply link settings whenever an interface is restarted
+    pub fn with_link_settings(mut self, name: impl Into<String>, settings: LinkSettings) -> Self {
+        self.link_settings.insert(name.into(), settings);
+        self
+    }
+
+    /// How bus-off interfaces are restarted, [`RestartMethod::Ioctl`] if not set
+    pub fn with_restart_method(mut self, method: RestartMethod) -> Self {
+        self.restart_method = method;
+        self
+    }
+
+    /// Override the restart method for one interface
+    pub fn with_interface_restart_method(
+        mut self,
+        name: impl Into<String>,
+        method: RestartMethod,
+    ) -> Self {
+        self.restart_methods.insert(name.into(), method);
+        self
+    }
+
+    /// Reset an interface's error counters after it is restarted, if they are still raised
+    pub fn with_reset_counters(mut self, name: impl Into<String>) -> Self {
+        self.reset_counters.insert(name.into());
+        self
+    }
+
+    /// Validate and build the configuration
+    ///
+    /// Names are cleaned up as in [`Config::new`]. Fails if no interface is left, or if an
+    /// override names an interface that isn't monitored. Delays longer than
+    /// [`MAX_RESTART_DELAY`] are clamped to it with a warning.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut config = Config::new(clamp_delay(self.restart_delay), self.interface_names);
+        if config.interface_names.is_empty() {
+            return Err(ConfigError::Invalid("no interfaces to monitor".to_string()));
+        }
+        let overridden = self
+            .interface_delays
+            .keys()
+            .chain(self.link_settings.keys())
+            .chain(self.restart_methods.keys())
+            .chain(&self.reset_counters);
+        for name in overridden {
+            if !config
+                .interface_names
+                .iter()
+                .any(|listed| listed == name.trim())
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "settings given for '{}', which is not monitored",
+                    name
+                )));
+            }
+        }
+        for (name, delay) in self.interface_delays {
+            config
+                .interface_delays
+                .insert(name.trim().to_string(), clamp_delay(delay));
+        }
+        for (name, settings) in self.link_settings {
+            if !settings.is_empty() {
+                config
+                    .link_settings
+                    .insert(name.trim().to_string(), settings);
+            }
+        }
+        config.restart_method = self.restart_method;
+        for (name, method) in self.restart_methods {
+            config
+                .restart_methods
+                .insert(name.trim().to_string(), method);
+        }
+        config.reset_counters = self
+            .reset_counters
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .collect();
+        Ok(config)
+    }
+}
+
+/// Clamp a restart delay to [`MAX_RESTART_DELAY`], warning if it was longer
+fn clamp_delay(delay: Duration) -> Duration {
+    if delay > MAX_RESTART_DELAY {
+        warn!(
+            "Restart delay {:?} is longer than {:?}, using {:?}",
+            delay, MAX_RESTART_DELAY, MAX_RESTART_DELAY
+        );
+        return MAX_RESTART_DELAY;
+    }
+    delay
+}
+
+/// Errors loading or validating a configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not valid configuration TOML
+    Parse(toml::de::Error),
+    /// The configuration is well-formed but not usable
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// On-disk layout of a configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    delay_ms: Option<u64>,
+    restart_method: Option<RestartMethod>,
+    #[serde(default)]
+    interface: Vec<InterfaceEntry>,
+}
+
+/// An `[[interface]]` table in a configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InterfaceEntry {
+    name: String,
+    delay_ms: Option<u64>,
+    bitrate: Option<u32>,
+    restart_ms: Option<u32>,
+    restart_method: Option<RestartMethod>,
+    #[serde(default)]
+    reset_counters: bool,
+}
+
+/// Read interface names from a file, one per line
+///
+/// Surrounding whitespace is trimmed, and blank lines and lines starting with `#` are skipped.
+pub fn read_interface_file(path: &Path) -> Result<Vec<String>, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Parse an interface name, trimming surrounding whitespace and rejecting empty names
+pub fn parse_interface_name(value: &str) -> Result<String, String> {
+    let name = value.trim();
+    if name.is_empty() {
+        return Err("interface name must not be empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Parse an interface name regex, reporting invalid syntax
+pub fn parse_interface_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|e| format!("invalid regex: {}", e))
+}
+
+/// Parse a human-friendly duration such as `500ms`, `30s`, `10m` or `2h`
+///
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{value}'"))?;
+
+    let secs = |factor: u64| {
+        number
+            .checked_mul(factor)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration '{value}' is too long"))
+    };
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        other => Err(format!("invalid duration unit '{other}' in '{value}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn config_files_set_per_interface_overrides() {
+        let config = Config::from_toml(
+            r#"
+            delay_ms = 1000
+            restart_method = "downup"
+
+            [[interface]]
+            name = " can0 "
+            delay_ms = 200
+            bitrate = 500000
+            restart_ms = 100
+            restart_method = "ioctl"
+            reset_counters = true
+
+            [[interface]]
+            name = "can1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.interface_names, ["can0", "can1"]);
+        assert_eq!(config.restart_delay_for("can0"), Duration::from_millis(200));
+        assert_eq!(config.restart_delay_for("can1"), Duration::from_secs(1));
+        assert_eq!(
+            config.link_settings_for("can0"),
+            Some(LinkSettings {
+                bitrate: Some(500_000),
+                restart_ms: Some(100),
+            })
+        );
+        assert_eq!(config.link_settings_for("can1"), None);
+        assert_eq!(config.restart_method_for("can0"), RestartMethod::Ioctl);
+        assert_eq!(config.restart_method_for("can1"), RestartMethod::DownUp);
+        assert!(config.reset_counters.contains("can0"));
+        assert!(!config.reset_counters.contains("can1"));
+    }
+
+    #[test]
+    fn config_files_default_what_they_leave_out() {
+        let config = Config::from_toml("").unwrap();
+        assert!(config.interface_names.is_empty());
+        assert_eq!(config.restart_delay, DEFAULT_RESTART_DELAY);
+        assert_eq!(config.restart_method, RestartMethod::Ioctl);
+    }
+
+    #[test]
+    fn config_files_reject_invalid_interfaces() {
+        assert!(matches!(
+            Config::from_toml("[[interface]]\nname = \"  \"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[[interface]]\nname = \"can0\"\nrestart_method = \"reboot\"\n"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[[interface]]\nname = \"can0\"\nbitrate = -1\n"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            Config::from_toml("[[interface]]\ndelay_ms = 100\n"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn builder_needs_an_interface() {
+        assert!(matches!(
+            Config::builder().build(),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            Config::builder().with_interfaces(["", "  "]).build(),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn builder_rejects_settings_for_unmonitored_interfaces() {
+        let builder = || Config::builder().with_interface("can0");
+        let settings = LinkSettings {
+            bitrate: Some(500_000),
+            restart_ms: None,
+        };
+        for builder in [
+            builder().with_interface_delay("can1", Duration::ZERO),
+            builder().with_link_settings("can1", settings),
+            builder().with_interface_restart_method("can1", RestartMethod::DownUp),
+            builder().with_reset_counters("can1"),
+        ] {
+            assert!(matches!(builder.build(), Err(ConfigError::Invalid(_))));
+        }
+        // Names are trimmed before they are matched
+        let config = builder()
+            .with_interface_delay(" can0", Duration::ZERO)
+            .build()
+            .unwrap();
+        assert_eq!(config.restart_delay_for("can0"), Duration::ZERO);
+    }
+
+    #[test]
+    fn builder_clamps_long_restart_delays() {
+        let config = Config::builder()
+            .with_interface("can0")
+            .with_restart_delay(MAX_RESTART_DELAY + Duration::from_secs(1))
+            .build()
+            .unwrap();
+        assert_eq!(config.restart_delay, MAX_RESTART_DELAY);
+
+        let config = Config::builder()
+            .with_interface("can0")
+            .with_interface_delay("can0", Duration::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(config.restart_delay_for("can0"), MAX_RESTART_DELAY);
+    }
+
+    #[test]
+    fn builder_drops_empty_link_settings() {
+        let config = Config::builder()
+            .with_interface("can0")
+            .with_link_settings("can0", LinkSettings::default())
+            .build()
+            .unwrap();
+        assert_eq!(config.link_settings_for("can0"), None);
+    }
+
+    #[test]
+    fn interface_files_skip_comments_and_blank_lines() {
+        let path =
+            std::env::temp_dir().join(format!("cansentinel-interfaces-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# engine buses\ncan0\n\n  can1  \n   \n  # rear\nvcan0\n",
+        )
+        .unwrap();
+        let names = read_interface_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(names.unwrap(), ["can0", "can1", "vcan0"]);
+
+        assert!(matches!(
+            read_interface_file(&path),
+            Err(ConfigError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = Config::from_toml("[[interface]]\nname = \"can0\"\nbitrat = 500000\n");
+        assert!(matches!(error, Err(ConfigError::Parse(_))));
+        assert!(matches!(
+            Config::from_toml("delay = 100\n"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn durations_too_long_to_represent_are_rejected() {
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
+    }
+}
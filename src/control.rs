@@ -0,0 +1,422 @@
+//! Control socket
+//!
+//! A line-based admin channel for scripts on headless devices. Each line sent is a [`Command`]
+//! and is answered with a single line of JSON:
+//!
+//! - `status`: `{"ok":true,"pending_restarts":0,"interfaces":[...]}`, with an
+//!   [`InterfaceStatus`](crate::InterfaceStatus) for every monitored interface
+//! - `interfaces`: the monitored interfaces, `{"ok":true,"interfaces":[{"interface":"can0","idx":4}]}`
+//! - `restart <iface>`: restart the interface now, `{"ok":true,"interface":"can0","restarted":true}`
+//! - `cancel <iface>`: cancel its pending restart, `{"ok":true,"interface":"can0","cancelled":true}`
+//! - `history <iface> [count]`: the interface's most recent events, oldest first, as
+//!   `{"ok":true,"interface":"can0","events":[...]}` with each event as in
+//!   [`BusEvent::to_json`](crate::BusEvent::to_json)
+//!
+//! Anything else is answered with `{"ok":false,"error":"..."}`. Commands are carried out by
+//! whoever owns the [`Monitor`], through [`ControlRequest::respond`]. Listening on a Unix socket
+//! requires the `control` feature.
+
+use crate::monitor::Monitor;
+use serde_json::{Value, json};
+use std::{fmt, str::FromStr};
+use tokio::sync::oneshot;
+use tracing::{Instrument, info};
+
+/// Events returned by `history` when no count is given
+const DEFAULT_HISTORY_COUNT: usize = 20;
+
+/// A command accepted on the control socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Report every interface's state and the pending restarts
+    Status,
+    /// List the monitored interfaces
+    Interfaces,
+    /// Restart the named interface immediately
+    Restart(String),
+    /// Cancel the pending restart of the named interface
+    Cancel(String),
+    /// Report up to this many of the named interface's most recent events
+    History(String, usize),
+}
+
+/// Error parsing a [`Command`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCommandError(String);
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCommandError {}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("status"), None) => Self::Status,
+            (Some("interfaces"), None) => Self::Interfaces,
+            (Some("restart"), Some(name)) => Self::Restart(name.to_string()),
+            (Some("cancel"), Some(name)) => Self::Cancel(name.to_string()),
+            (Some("history"), Some(name)) => {
+                let count = match words.next() {
+                    Some(count) => count.parse().map_err(|_| {
+                        ParseCommandError(format!("invalid event count '{}'", count))
+                    })?,
+                    None => DEFAULT_HISTORY_COUNT,
+                };
+                Self::History(name.to_string(), count)
+            }
+            (Some("history"), None) => {
+                return Err(ParseCommandError(
+                    "usage: history <interface> [count]".to_string(),
+                ));
+            }
+            (Some(command @ ("restart" | "cancel")), None) => {
+                return Err(ParseCommandError(format!("usage: {command} <interface>")));
+            }
+            _ => {
+                return Err(ParseCommandError(format!(
+                    "unknown command '{}', expected status, interfaces, restart <interface>, cancel <interface> or history <interface> [count]",
+                    line.trim()
+                )));
+            }
+        };
+        if words.next().is_some() {
+            return Err(ParseCommandError(format!(
+                "too many arguments in '{}'",
+                line.trim()
+            )));
+        }
+        Ok(command)
+    }
+}
+
+impl Command {
+    /// Carry out the command on `monitor` and build its response
+    pub async fn execute(&self, monitor: &Monitor) -> Value {
+        match self {
+            Self::Status => json!({
+                "ok": true,
+                "pending_restarts": monitor.restart_manager().pending_count().await,
+                "interfaces": monitor.interface_status().await,
+            }),
+            Self::Interfaces => {
+                let interfaces: Vec<Value> = monitor
+                    .interfaces()
+                    .iter()
+                    .map(|interface| json!({ "interface": interface.name, "idx": interface.idx }))
+                    .collect();
+                json!({ "ok": true, "interfaces": interfaces })
+            }
+            Self::Restart(name) => {
+                let Some(interface) = monitor.interface(name) else {
+                    return not_monitored(name);
+                };
+                let restarted = async {
+                    info!("Restart requested on the control socket");
+                    monitor.restart_manager().restart_now(interface).await
+                }
+                .instrument(interface.span())
+                .await;
+                json!({ "ok": true, "interface": name, "restarted": restarted })
+            }
+            Self::Cancel(name) => {
+                let Some(interface) = monitor.interface(name) else {
+                    return not_monitored(name);
+                };
+                let restart_manager = monitor.restart_manager();
+                let cancelled = restart_manager.is_pending(interface.idx).await;
+                restart_manager
+                    .cancel_restart(interface)
+                    .instrument(interface.span())
+                    .await;
+                json!({ "ok": true, "interface": name, "cancelled": cancelled })
+            }
+            Self::History(name, count) => {
+                let Some(interface) = monitor.interface(name) else {
+                    return not_monitored(name);
+                };
+                let events: Vec<Value> = monitor
+                    .interface_events(interface.idx, *count)
+                    .iter()
+                    .map(|event| event.to_json_value())
+                    .collect();
+                json!({ "ok": true, "interface": name, "events": events })
+            }
+        }
+    }
+}
+
+/// Response to a command that failed
+pub fn error_response(error: impl fmt::Display) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
+
+fn not_monitored(name: &str) -> Value {
+    error_response(format!("interface '{}' is not monitored", name))
+}
+
+/// A command received on the control socket, waiting for its response
+#[derive(Debug)]
+pub struct ControlRequest {
+    /// Command to carry out
+    pub command: Command,
+    reply: oneshot::Sender<Value>,
+}
+
+impl ControlRequest {
+    /// Create a request and the receiver its response will be sent to
+    pub fn new(command: Command) -> (Self, oneshot::Receiver<Value>) {
+        let (reply, response) = oneshot::channel();
+        (Self { command, reply }, response)
+    }
+
+    /// Carry out the command on `monitor` and send the response back to the client
+    pub async fn respond(self, monitor: &Monitor) {
+        let response = self.command.execute(monitor).await;
+        // The client may have disconnected meanwhile
+        let _ = self.reply.send(response);
+    }
+}
+
+#[cfg(feature = "control")]
+mod server {
+    use super::{Command, ControlRequest, error_response};
+    use std::{io, os::unix::fs::FileTypeExt, path::Path};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        sync::mpsc,
+        task::JoinHandle,
+    };
+    use tracing::{debug, info};
+
+    /// Requests queued for the owner of the monitor before clients have to wait
+    const REQUEST_QUEUE: usize = 16;
+
+    /// Listen for commands on a Unix socket at `path` until the returned task is aborted
+    ///
+    /// A socket left at `path` by a previous run is replaced, any other file is an error. Requests
+    /// are delivered on the returned channel, and each client waits for the response to its
+    /// command before the next one is read. Must be called from within a tokio runtime.
+    pub fn listen(path: &Path) -> io::Result<(JoinHandle<()>, mpsc::Receiver<ControlRequest>)> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path)
+            && metadata.file_type().is_socket()
+        {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("Listening for control commands on {}", path.display());
+
+        let (tx, requests) = mpsc::channel(REQUEST_QUEUE);
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve(stream, tx).await {
+                                debug!("Control: connection failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Control: failed to accept connection: {}", e),
+                }
+            }
+        });
+        Ok((task, requests))
+    }
+
+    /// Answer commands from a single client until it disconnects
+    async fn serve(stream: UnixStream, tx: mpsc::Sender<ControlRequest>) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match line.parse::<Command>() {
+                Ok(command) => {
+                    let (request, response) = ControlRequest::new(command);
+                    if tx.send(request).await.is_err() {
+                        // Monitoring is shutting down
+                        return Ok(());
+                    }
+                    match response.await {
+                        Ok(response) => response,
+                        Err(_) => return Ok(()),
+                    }
+                }
+                Err(e) => error_response(e),
+            };
+            writer
+                .write_all(format!("{}\n", response).as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "control")]
+pub use server::listen;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config, MonitorOptions, backend::fake::FakeBackend, interface::CanInterfaceInfo,
+        runtime::StateSource,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    /// Not a real interface, so nothing but the fake backend ever reports on it
+    fn control_test_iface() -> CanInterfaceInfo {
+        CanInterfaceInfo {
+            idx: 4242,
+            name: "cantest0".to_string(),
+        }
+    }
+
+    async fn monitor(backend: &FakeBackend) -> Monitor {
+        Monitor::start(
+            Config::new(Duration::from_secs(60), vec![control_test_iface().name]),
+            vec![control_test_iface()],
+            MonitorOptions {
+                control: Some(Arc::new(backend.clone())),
+                state_source: StateSource::Sysfs,
+                interface_history_size: 20,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    async fn execute(monitor: &Monitor, line: &str) -> Value {
+        line.parse::<Command>().unwrap().execute(monitor).await
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!("status".parse(), Ok(Command::Status));
+        assert_eq!(" interfaces ".parse(), Ok(Command::Interfaces));
+        assert_eq!(
+            "restart can0".parse(),
+            Ok(Command::Restart("can0".to_string()))
+        );
+        assert_eq!(
+            "cancel can0".parse(),
+            Ok(Command::Cancel("can0".to_string()))
+        );
+        assert_eq!(
+            "history can0".parse(),
+            Ok(Command::History("can0".to_string(), DEFAULT_HISTORY_COUNT))
+        );
+        assert_eq!(
+            "history can0 5".parse(),
+            Ok(Command::History("can0".to_string(), 5))
+        );
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        for line in [
+            "",
+            "reboot",
+            "restart",
+            "cancel",
+            "history",
+            "history can0 many",
+            "status now",
+            "restart can0 can1",
+        ] {
+            assert!(line.parse::<Command>().is_err(), "accepted '{line}'");
+        }
+    }
+
+    #[tokio::test]
+    async fn status_reports_every_interface() {
+        let backend = FakeBackend::new();
+        let monitor = monitor(&backend).await;
+
+        let response = execute(&monitor, "status").await;
+        assert_eq!(response["ok"], true);
+        assert_eq!(response["pending_restarts"], 0);
+        assert_eq!(response["interfaces"][0]["interface"], "cantest0");
+        assert_eq!(response["interfaces"][0]["idx"], 4242);
+        monitor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn interfaces_lists_the_monitored_interfaces() {
+        let backend = FakeBackend::new();
+        let monitor = monitor(&backend).await;
+
+        let response = execute(&monitor, "interfaces").await;
+        assert_eq!(
+            response,
+            json!({ "ok": true, "interfaces": [{ "interface": "cantest0", "idx": 4242 }] })
+        );
+        monitor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn restart_restarts_through_the_backend() {
+        let backend = FakeBackend::new();
+        let monitor = monitor(&backend).await;
+
+        let response = execute(&monitor, "restart cantest0").await;
+        assert_eq!(
+            response,
+            json!({ "ok": true, "interface": "cantest0", "restarted": true })
+        );
+        assert_eq!(backend.restarts(), vec![4242]);
+
+        let response = execute(&monitor, "restart can9").await;
+        assert_eq!(response["ok"], false);
+        assert_eq!(backend.restarts(), vec![4242]);
+        monitor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn cancel_cancels_a_pending_restart() {
+        let backend = FakeBackend::new();
+        let monitor = monitor(&backend).await;
+        monitor
+            .restart_manager()
+            .schedule_restart(control_test_iface(), Duration::from_secs(60))
+            .await;
+
+        let response = execute(&monitor, "cancel cantest0").await;
+        assert_eq!(
+            response,
+            json!({ "ok": true, "interface": "cantest0", "cancelled": true })
+        );
+        assert!(!monitor.restart_manager().is_pending(4242).await);
+
+        // Nothing left to cancel
+        let response = execute(&monitor, "cancel cantest0").await;
+        assert_eq!(response["cancelled"], false);
+        assert!(backend.restarts().is_empty());
+        monitor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn history_is_only_kept_for_monitored_interfaces() {
+        let backend = FakeBackend::new();
+        let monitor = monitor(&backend).await;
+
+        let response = execute(&monitor, "history cantest0 5").await;
+        assert_eq!(
+            response,
+            json!({ "ok": true, "interface": "cantest0", "events": [] })
+        );
+        let response = execute(&monitor, "history can9").await;
+        assert_eq!(response["ok"], false);
+        monitor.stop().await;
+    }
+}
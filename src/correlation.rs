@@ -0,0 +1,130 @@
+//! Correlation of bus-off events across interfaces
+
+use crate::interface::CanInterfaceInfo;
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::Instant;
+
+/// A bus-off affecting several interfaces at once, e.g. a ground fault
+#[derive(Debug, Clone)]
+pub struct Incident {
+    /// Names of the affected interfaces, in the order they went bus-off
+    pub interfaces: Vec<String>,
+}
+
+/// Recognizes bus-offs on many interfaces within a short window as a single incident
+#[derive(Debug, Clone)]
+pub struct BusOffCorrelator {
+    threshold: usize,
+    window: Duration,
+    /// Recent bus-offs within the window, oldest first
+    recent: VecDeque<(Instant, CanInterfaceInfo)>,
+    /// An incident stays active while bus-offs keep arriving within the window
+    active_until: Option<Instant>,
+}
+
+impl BusOffCorrelator {
+    /// Report an incident once `threshold` distinct interfaces go bus-off within `window`
+    ///
+    /// A threshold below 2 disables correlation.
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            recent: VecDeque::new(),
+            active_until: None,
+        }
+    }
+
+    /// Whether a correlated incident is currently in progress
+    pub fn in_incident(&self, now: Instant) -> bool {
+        self.active_until.is_some_and(|until| now < until)
+    }
+
+    /// Record a bus-off, returning the incident if this one completes it
+    pub fn record(&mut self, interface: &CanInterfaceInfo, now: Instant) -> Option<Incident> {
+        if self.threshold < 2 {
+            return None;
+        }
+
+        while self
+            .recent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            self.recent.pop_front();
+        }
+        if !self.recent.iter().any(|(_, i)| i == interface) {
+            self.recent.push_back((now, interface.clone()));
+        }
+
+        if self.in_incident(now) {
+            // Already reported, keep the incident open while the storm continues
+            self.active_until = Some(now + self.window);
+            return None;
+        }
+
+        if self.recent.len() >= self.threshold {
+            self.active_until = Some(now + self.window);
+            return Some(Incident {
+                interfaces: self.recent.iter().map(|(_, i)| i.name.clone()).collect(),
+            });
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{can0, can1};
+
+    fn correlator() -> BusOffCorrelator {
+        BusOffCorrelator::new(2, Duration::from_secs(1))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bus_offs_within_the_window_are_one_incident() {
+        let mut correlator = correlator();
+        assert!(correlator.record(&can0(), Instant::now()).is_none());
+        tokio::time::advance(Duration::from_millis(500)).await;
+
+        let incident = correlator.record(&can1(), Instant::now()).unwrap();
+        assert_eq!(incident.interfaces, ["can0", "can1"]);
+        assert!(correlator.in_incident(Instant::now()));
+
+        // Reported once, and kept open while bus-offs keep coming
+        tokio::time::advance(Duration::from_millis(900)).await;
+        assert!(correlator.record(&can0(), Instant::now()).is_none());
+        tokio::time::advance(Duration::from_millis(900)).await;
+        assert!(correlator.in_incident(Instant::now()));
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(!correlator.in_incident(Instant::now()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bus_offs_further_apart_are_not_correlated() {
+        let mut correlator = correlator();
+        assert!(correlator.record(&can0(), Instant::now()).is_none());
+        tokio::time::advance(Duration::from_millis(1001)).await;
+        assert!(correlator.record(&can1(), Instant::now()).is_none());
+        assert!(!correlator.in_incident(Instant::now()));
+    }
+
+    #[test]
+    fn repeated_bus_offs_of_one_interface_are_not_correlated() {
+        let mut correlator = correlator();
+        let now = Instant::now();
+        assert!(correlator.record(&can0(), now).is_none());
+        assert!(correlator.record(&can0(), now).is_none());
+        assert!(!correlator.in_incident(now));
+    }
+
+    #[test]
+    fn thresholds_below_two_disable_correlation() {
+        let mut correlator = BusOffCorrelator::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+        assert!(correlator.record(&can0(), now).is_none());
+        assert!(correlator.record(&can1(), now).is_none());
+        assert!(!correlator.in_incident(now));
+    }
+}
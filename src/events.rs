@@ -1,9 +1,14 @@
 //! Event types
 
 use crate::interface::CanInterfaceInfo;
+use serde::{Serialize, Serializer};
+use socketcan::{CanError, EmbeddedFrame, Frame};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
 
 /// Types of CAN bus events we care about
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BusEventType {
     /// Bus has gone into bus-off state and needs restart
     BusOff,
@@ -11,6 +16,25 @@ pub enum BusEventType {
     Restart,
     /// Interface has gone down(?)
     Stopped,
+    /// Restarts were suspended after too many bus-offs in a short time
+    GaveUp,
+    /// Interface returned to ERROR_ACTIVE from a degraded state
+    Recovered,
+    /// Error counters crossed the warning limit (ERROR_WARNING), an early sign of trouble
+    ErrorWarning,
+    /// Error counters crossed the passive limit (ERROR_PASSIVE), bus-off may follow
+    ErrorPassive,
+    /// Error frames of a watched class arrived often enough to reach the configured threshold
+    ErrorThreshold,
+    /// The interface is up but lost carrier without going bus-off, e.g. the adapter was unplugged
+    CarrierLost,
+    /// The interface has carrier again after losing it
+    CarrierRestored,
+    /// An error frame reported new values of the controller's error counters
+    CountersReported,
+    /// The interface stayed error-passive for too long and is restarted, see
+    /// [`ErrorMonitorOptions::passive_restart_after`](crate::monitoring::ErrorMonitorOptions::passive_restart_after)
+    PassiveDwell,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +43,175 @@ pub enum BusEventSource {
     ErrorFrame(socketcan::CanErrorFrame),
     /// CANState from netlink linkinfo attribute
     StateUpdate(socketcan::nl::CanState),
+    /// CANState read by periodic polling
+    Poll(socketcan::nl::CanState),
+    /// CANState inferred from the interface's flags and carrier in sysfs
+    Sysfs(socketcan::nl::CanState),
+    /// The restart circuit breaker tripped
+    CircuitBreaker,
+    /// Netdevice flags from a netlink link message
+    LinkFlags {
+        /// The interface is administratively up (`IFF_UP`)
+        up: bool,
+        /// The interface has carrier and is operational (`IFF_RUNNING`)
+        running: bool,
+    },
+    /// The interface stayed error-passive for this long, see
+    /// [`ErrorMonitorOptions::passive_restart_after`](crate::monitoring::ErrorMonitorOptions::passive_restart_after)
+    PassiveDwell(Duration),
+}
+
+impl BusEventSource {
+    /// Name of the source, as in the `source` tag of the JSON form
+    pub fn name(&self) -> &'static str {
+        match self {
+            BusEventSource::ErrorFrame(_) => "error_frame",
+            BusEventSource::StateUpdate(_) => "netlink",
+            BusEventSource::Poll(_) => "poll",
+            BusEventSource::Sysfs(_) => "sysfs",
+            BusEventSource::CircuitBreaker => "circuit_breaker",
+            BusEventSource::LinkFlags { .. } => "link",
+            BusEventSource::PassiveDwell(_) => "passive_dwell",
+        }
+    }
+}
+
+/// Serialized as a `source` tag (`error_frame`, `netlink`, `poll`, `sysfs`, `circuit_breaker`,
+/// `link` or `passive_dwell`) with the decoded error, CAN id and data of error frames, the state
+/// read from the interface, the link flags, or how long the interface was error-passive.
+impl Serialize for BusEventSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SourceRecord::from(self).serialize(serializer)
+    }
+}
+
+/// Serializable form of [`BusEventSource`]
+#[derive(Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+enum SourceRecord {
+    ErrorFrame {
+        error: String,
+        can_id: u32,
+        data: Vec<u8>,
+        #[serde(flatten)]
+        detail: ErrorDetail,
+    },
+    Netlink {
+        state: String,
+    },
+    Poll {
+        state: String,
+    },
+    Sysfs {
+        state: String,
+    },
+    CircuitBreaker,
+    Link {
+        up: bool,
+        running: bool,
+    },
+    PassiveDwell {
+        dwell_ms: u64,
+    },
+}
+
+impl From<&BusEventSource> for SourceRecord {
+    fn from(source: &BusEventSource) -> Self {
+        match source {
+            BusEventSource::ErrorFrame(frame) => SourceRecord::ErrorFrame {
+                error: frame.into_error().to_string(),
+                can_id: frame.raw_id(),
+                data: frame.data().to_vec(),
+                detail: ErrorDetail::of(&frame.into_error()),
+            },
+            BusEventSource::StateUpdate(state) => SourceRecord::Netlink {
+                state: format!("{:?}", state),
+            },
+            BusEventSource::Poll(state) => SourceRecord::Poll {
+                state: format!("{:?}", state),
+            },
+            BusEventSource::Sysfs(state) => SourceRecord::Sysfs {
+                state: format!("{:?}", state),
+            },
+            BusEventSource::CircuitBreaker => SourceRecord::CircuitBreaker,
+            BusEventSource::LinkFlags { up, running } => SourceRecord::Link {
+                up: *up,
+                running: *running,
+            },
+            BusEventSource::PassiveDwell(dwell) => SourceRecord::PassiveDwell {
+                dwell_ms: dwell.as_millis() as u64,
+            },
+        }
+    }
+}
+
+/// Sub-codes of an error frame that say what exactly went wrong, for the errors that have them
+///
+/// Each is the socketcan description of the code, such as `receive buffer overflow` or
+/// `bit stuffing error`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ErrorDetail {
+    /// What the controller reported, for controller problems
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controller_problem: Option<String>,
+    /// Kind of violation, for protocol violations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<String>,
+    /// Where in the frame a protocol violation happened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Bit in which arbitration was lost, if the controller reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arbitration_bit: Option<u8>,
+}
+
+impl ErrorDetail {
+    /// Decode the sub-codes of `error`, all empty for errors without any
+    pub fn of(error: &CanError) -> Self {
+        match error {
+            CanError::ControllerProblem(problem) => Self {
+                controller_problem: Some(problem.to_string()),
+                ..Self::default()
+            },
+            CanError::ProtocolViolation { vtype, location } => Self {
+                violation: Some(vtype.to_string()),
+                location: Some(location.to_string()),
+                ..Self::default()
+            },
+            CanError::LostArbitration(bit) if *bit > 0 => Self {
+                arbitration_bit: Some(*bit),
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Transmit and receive error counters of a CAN controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ErrorCounters {
+    /// Transmit error counter (TEC)
+    pub tx: u32,
+    /// Receive error counter (REC)
+    pub rx: u32,
+}
+
+/// CAN_ERR_CNT from linux/can/error.h: the error frame carries the error counters
+const CAN_ERR_CNT: u32 = 0x0200;
+
+impl ErrorCounters {
+    /// Counters carried by an error frame, in data bytes 6 (TX) and 7 (RX)
+    ///
+    /// Drivers that report state changes with the counters attached send these as controller
+    /// problem frames with the counter bit set (0x204), which don't decode as any
+    /// [`CanError`].
+    pub fn from_error_frame(frame: &socketcan::CanErrorFrame) -> Option<Self> {
+        let data = frame.data();
+        (frame.raw_id() & CAN_ERR_CNT != 0 && data.len() >= 8).then(|| Self {
+            tx: u32::from(data[6]),
+            rx: u32::from(data[7]),
+        })
+    }
 }
 
 /// Unified event for CAN bus state changes
@@ -33,33 +226,141 @@ pub struct BusEvent {
     pub event_type: BusEventType,
     /// Where the event originated from
     pub event_source: BusEventSource,
+    /// Wall-clock time the event was created
+    pub timestamp: SystemTime,
+    /// Monotonic time the event was created, for measuring intervals between events
+    pub detected_at: Instant,
+    /// Kernel receive timestamp of the error frame, when the driver provides one
+    pub kernel_timestamp: Option<SystemTime>,
+    /// Controller error counters at the time of the event, when the driver reports them
+    pub error_counters: Option<ErrorCounters>,
 }
 
 impl BusEvent {
-    /// Create a new bus-off event
-    pub fn bus_off(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+    fn new(
+        interface: CanInterfaceInfo,
+        event_type: BusEventType,
+        event_source: BusEventSource,
+    ) -> Self {
         Self {
             interface,
-            event_type: BusEventType::BusOff,
+            event_type,
             event_source,
+            timestamp: SystemTime::now(),
+            detected_at: Instant::now(),
+            kernel_timestamp: None,
+            error_counters: None,
         }
     }
 
+    /// Create a new bus-off event
+    pub fn bus_off(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::BusOff, event_source)
+    }
+
     /// Create a new restart event
     pub fn restart(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
-        Self {
-            interface,
-            event_type: BusEventType::Restart,
-            event_source,
-        }
+        Self::new(interface, BusEventType::Restart, event_source)
     }
 
     /// Create a new stopped event
     pub fn stopped(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
-        Self {
+        Self::new(interface, BusEventType::Stopped, event_source)
+    }
+
+    /// Create a new recovered event
+    pub fn recovered(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::Recovered, event_source)
+    }
+
+    /// Create a new error-warning event
+    pub fn error_warning(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::ErrorWarning, event_source)
+    }
+
+    /// Create a new error-passive event
+    pub fn error_passive(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::ErrorPassive, event_source)
+    }
+
+    /// Create a new error-threshold event
+    pub fn error_threshold(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::ErrorThreshold, event_source)
+    }
+
+    /// Create a new carrier-lost event
+    pub fn carrier_lost(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::CarrierLost, event_source)
+    }
+
+    /// Create a new carrier-restored event
+    pub fn carrier_restored(interface: CanInterfaceInfo, event_source: BusEventSource) -> Self {
+        Self::new(interface, BusEventType::CarrierRestored, event_source)
+    }
+
+    /// Create a new counters-reported event
+    pub fn counters_reported(
+        interface: CanInterfaceInfo,
+        event_source: BusEventSource,
+        counters: ErrorCounters,
+    ) -> Self {
+        Self::new(interface, BusEventType::CountersReported, event_source)
+            .with_error_counters(Some(counters))
+    }
+
+    /// Create a new passive-dwell event for an interface that was error-passive for `dwell`
+    pub fn passive_dwell(interface: CanInterfaceInfo, dwell: Duration) -> Self {
+        Self::new(
             interface,
-            event_type: BusEventType::Stopped,
-            event_source,
+            BusEventType::PassiveDwell,
+            BusEventSource::PassiveDwell(dwell),
+        )
+    }
+
+    /// Create a new gave-up event
+    pub fn gave_up(interface: CanInterfaceInfo) -> Self {
+        Self::new(
+            interface,
+            BusEventType::GaveUp,
+            BusEventSource::CircuitBreaker,
+        )
+    }
+
+    /// Attach the controller's error counters
+    pub fn with_error_counters(mut self, counters: Option<ErrorCounters>) -> Self {
+        self.error_counters = counters;
+        self
+    }
+
+    /// Attach the kernel receive timestamp of the frame that caused this event
+    pub fn with_kernel_timestamp(mut self, timestamp: Option<SystemTime>) -> Self {
+        self.kernel_timestamp = timestamp;
+        self
+    }
+
+    /// Render as a single line of JSON, e.g.
+    /// `{"ts":1700000000.123,"interface":"can0","idx":2,"event":"bus_off","source":"netlink","state":"BusOff"}`
+    ///
+    /// `ts` is the kernel timestamp when there is one, otherwise the time the event was created.
+    pub fn to_json(&self) -> String {
+        // Nothing in the record can fail to serialize
+        serde_json::to_string(&self.record()).unwrap_or_default()
+    }
+
+    /// Same as [`BusEvent::to_json`], as a JSON value
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(self.record()).unwrap_or_default()
+    }
+
+    fn record(&self) -> EventRecord<'_> {
+        let ts = self.kernel_timestamp.unwrap_or(self.timestamp);
+        EventRecord {
+            ts: unix_seconds(ts),
+            interface: &self.interface.name,
+            idx: self.interface.idx,
+            event: &self.event_type,
+            source: &self.event_source,
+            error_counters: self.error_counters,
         }
     }
 
@@ -73,8 +374,74 @@ impl BusEvent {
         matches!(self.event_type, BusEventType::Restart)
     }
 
+    /// Check if this is an error-warning, error-passive or error-threshold event
+    pub fn is_degraded(&self) -> bool {
+        matches!(
+            self.event_type,
+            BusEventType::ErrorWarning | BusEventType::ErrorPassive | BusEventType::ErrorThreshold
+        )
+    }
+
     /// Check if this is a stopped event
     pub fn is_stopped(&self) -> bool {
         matches!(self.event_type, BusEventType::Stopped)
     }
 }
+
+/// Serializable form of [`BusEvent`]
+#[derive(Serialize)]
+struct EventRecord<'a> {
+    ts: f64,
+    interface: &'a str,
+    idx: u32,
+    event: &'a BusEventType,
+    #[serde(flatten)]
+    source: &'a BusEventSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_counters: Option<ErrorCounters>,
+}
+
+/// Seconds since the Unix epoch, with sub-second precision
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::can0;
+    use socketcan::{CanErrorFrame, nl::CanState};
+
+    #[test]
+    fn bus_event_serializes_to_json() {
+        let event = BusEvent::bus_off(can0(), BusEventSource::Poll(CanState::BusOff));
+        let json: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+
+        assert_eq!(json["interface"], "can0");
+        assert_eq!(json["idx"], 1);
+        assert_eq!(json["event"], "bus_off");
+        assert_eq!(json["source"], "poll");
+        assert_eq!(json["state"], "BusOff");
+        assert!(json["ts"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn error_frame_sub_codes_are_serialized() {
+        // CAN_ERR_PROT with CAN_ERR_PROT_STUFF in the data section
+        let frame = CanErrorFrame::new_error(0x0008, &[0, 0, 0x04, 0x0A]).unwrap();
+        let event = BusEvent::error_threshold(can0(), BusEventSource::ErrorFrame(frame));
+        let json = event.to_json_value();
+        assert!(json["violation"].as_str().is_some_and(|v| !v.is_empty()));
+        assert!(json["location"].is_string());
+        assert!(json.get("controller_problem").is_none());
+
+        // CAN_ERR_CRTL with CAN_ERR_CRTL_RX_OVERFLOW
+        let frame = CanErrorFrame::new_error(0x0004, &[0, 0x01]).unwrap();
+        let event = BusEvent::error_threshold(can0(), BusEventSource::ErrorFrame(frame));
+        let json = event.to_json_value();
+        assert!(json["controller_problem"].is_string());
+        assert!(json.get("violation").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+//! Restart groups sharing a common cooldown
+
+use crate::interface::CanInterfaceInfo;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long events of a group are suppressed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Suppression {
+    /// Until the restart of a member completes and the cooldown after it ends
+    Restarting,
+    /// Until the cooldown ends
+    Until(Instant),
+}
+
+/// Interfaces that share power or wiring and recover together
+#[derive(Debug, Clone)]
+struct RestartGroup {
+    members: Vec<CanInterfaceInfo>,
+    suppression: Option<Suppression>,
+}
+
+/// Tracks group-wide cooldowns after a member interface is restarted
+///
+/// While a member is being restarted, and for the cooldown after its restart completed, events
+/// from all of its group are suppressed so that error frames from transceivers powering back on
+/// aren't mistaken for a new fault.
+#[derive(Debug, Clone, Default)]
+pub struct RestartGroups {
+    groups: Vec<RestartGroup>,
+    cooldown: Duration,
+}
+
+impl RestartGroups {
+    /// Create groups from lists of member interfaces, applying `cooldown` after each restart
+    pub fn new(groups: Vec<Vec<CanInterfaceInfo>>, cooldown: Duration) -> Self {
+        Self {
+            groups: groups
+                .into_iter()
+                .map(|members| RestartGroup {
+                    members,
+                    suppression: None,
+                })
+                .collect(),
+            cooldown,
+        }
+    }
+
+    /// Whether events for this interface are currently suppressed
+    pub fn is_suppressed(&self, interface: &CanInterfaceInfo, now: Instant) -> bool {
+        self.groups.iter().any(|group| {
+            let active = match group.suppression {
+                Some(Suppression::Restarting) => true,
+                Some(Suppression::Until(until)) => now < until,
+                None => false,
+            };
+            active && group.members.contains(interface)
+        })
+    }
+
+    /// Suppress events from every group containing `interface`, which is about to be restarted,
+    /// until [`RestartGroups::finish_restart`] is called for it
+    ///
+    /// Returns whether any group is affected.
+    pub fn start_restart(&mut self, interface: &CanInterfaceInfo) -> bool {
+        if self.cooldown.is_zero() {
+            return false;
+        }
+
+        let mut grouped = false;
+        for group in &mut self.groups {
+            if group.members.contains(interface) {
+                group.suppression = Some(Suppression::Restarting);
+                grouped = true;
+            }
+        }
+        grouped
+    }
+
+    /// Start the cooldown for every group containing `interface`, whose restart completed at
+    /// `done`
+    ///
+    /// Returns the members of the affected groups and when their cooldown ends, so the caller can
+    /// re-check them once events are no longer suppressed.
+    pub fn finish_restart(
+        &mut self,
+        interface: &CanInterfaceInfo,
+        done: Instant,
+    ) -> Option<(Vec<CanInterfaceInfo>, Instant)> {
+        if self.cooldown.is_zero() {
+            return None;
+        }
+
+        let until = done + self.cooldown;
+        let mut members = Vec::new();
+        for group in &mut self.groups {
+            if group.members.contains(interface) {
+                group.suppression = Some(Suppression::Until(until));
+                members.extend(group.members.iter().cloned());
+            }
+        }
+
+        (!members.is_empty()).then_some((members, until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{can0, can1};
+
+    fn can2() -> CanInterfaceInfo {
+        CanInterfaceInfo {
+            idx: 3,
+            name: "can2".to_string(),
+        }
+    }
+
+    fn groups() -> RestartGroups {
+        RestartGroups::new(vec![vec![can0(), can1()]], Duration::from_secs(2))
+    }
+
+    #[test]
+    fn only_members_of_a_restarted_group_are_suppressed() {
+        let mut groups = groups();
+        let now = Instant::now();
+        assert!(!groups.is_suppressed(&can0(), now));
+
+        assert!(groups.start_restart(&can1()));
+        assert!(groups.is_suppressed(&can0(), now));
+        assert!(groups.is_suppressed(&can1(), now));
+        assert!(!groups.is_suppressed(&can2(), now));
+
+        assert!(!groups.start_restart(&can2()));
+        assert_eq!(groups.finish_restart(&can2(), now), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cooldown_runs_from_when_the_restart_completed() {
+        let mut groups = groups();
+        groups.start_restart(&can0());
+
+        // However long the restart takes
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(groups.is_suppressed(&can1(), Instant::now()));
+
+        let done = Instant::now();
+        let (members, until) = groups.finish_restart(&can0(), done).unwrap();
+        assert_eq!(members, [can0(), can1()]);
+        assert_eq!(until, done + Duration::from_secs(2));
+
+        tokio::time::advance(Duration::from_millis(1999)).await;
+        assert!(groups.is_suppressed(&can1(), Instant::now()));
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert!(!groups.is_suppressed(&can1(), Instant::now()));
+    }
+
+    #[test]
+    fn no_cooldown_suppresses_nothing() {
+        let mut groups = RestartGroups::new(vec![vec![can0(), can1()]], Duration::ZERO);
+        assert!(!groups.start_restart(&can0()));
+        assert!(!groups.is_suppressed(&can1(), Instant::now()));
+        assert_eq!(groups.finish_restart(&can0(), Instant::now()), None);
+    }
+}
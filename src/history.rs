@@ -0,0 +1,250 @@
+//! Bounded history of recent bus events
+
+use crate::{events::BusEvent, interface::CanInterfaceInfo};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// What the event loop did with an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOutcome {
+    /// A restart was scheduled (or one was already pending)
+    RestartScheduled,
+    /// The event was suppressed, e.g. during a group cooldown
+    Suppressed,
+    /// The interface was considered recovered
+    Recovered,
+    /// Held back for the debounce window, and handled again once it ends
+    Deferred,
+    /// No action was taken
+    Ignored,
+}
+
+/// A bus event as recorded in history
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    /// The event itself
+    pub event: BusEvent,
+    /// When the event loop handled it
+    pub handled_at: SystemTime,
+    /// What the event loop did with it
+    pub outcome: EventOutcome,
+}
+
+/// Bounded buffer evicting its oldest item once full, behind every history kept here
+#[derive(Debug)]
+struct Ring<T> {
+    capacity: usize,
+    items: VecDeque<T>,
+}
+
+impl<T: Clone> Ring<T> {
+    /// Create a ring holding up to `capacity` items; 0 keeps nothing
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Add an item, evicting the oldest if the ring is full
+    fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    /// Up to `count` of the most recent items, oldest first
+    fn last(&self, count: usize) -> Vec<T> {
+        self.items
+            .iter()
+            .skip(self.items.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// Every item, oldest first
+    fn all(&self) -> Vec<T> {
+        self.items.iter().cloned().collect()
+    }
+}
+
+/// Ring buffer of the most recent events, oldest first
+///
+/// Clones share the same buffer, so it can be read from outside the event loop while the loop
+/// keeps recording into it.
+#[derive(Debug, Clone)]
+pub struct RecentEvents {
+    events: Arc<Mutex<Ring<RecordedEvent>>>,
+}
+
+impl RecentEvents {
+    /// Create a buffer holding up to `capacity` events; 0 disables recording
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(Ring::new(capacity))),
+        }
+    }
+
+    /// Record a handled event, evicting the oldest if the buffer is full
+    pub fn record(&self, event: BusEvent, outcome: EventOutcome) {
+        self.events.lock().unwrap().push(RecordedEvent {
+            event,
+            handled_at: SystemTime::now(),
+            outcome,
+        });
+    }
+
+    /// Snapshot of the recorded events, oldest first
+    pub fn recent_events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().all()
+    }
+}
+
+/// Ring buffer of the most recent events on each interface, oldest first
+///
+/// Unlike [`RecentEvents`], a noisy interface can't push the others' events out. Clones share the
+/// same buffers.
+#[derive(Debug, Clone)]
+pub struct EventHistory {
+    capacity: usize,
+    /// Events by interface index
+    interfaces: Arc<Mutex<HashMap<u32, Ring<BusEvent>>>>,
+}
+
+impl EventHistory {
+    /// Create a history holding up to `capacity` events per interface; 0 disables recording
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            interfaces: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record an event, evicting the interface's oldest if its buffer is full
+    pub fn record(&self, event: &BusEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.interfaces
+            .lock()
+            .unwrap()
+            .entry(event.interface.idx)
+            .or_insert_with(|| Ring::new(self.capacity))
+            .push(event.clone());
+    }
+
+    /// Up to `count` of the most recent events on an interface, oldest first
+    pub fn last(&self, idx: u32, count: usize) -> Vec<BusEvent> {
+        self.interfaces
+            .lock()
+            .unwrap()
+            .get(&idx)
+            .map(|events| events.last(count))
+            .unwrap_or_default()
+    }
+
+    /// Drop the events recorded for an interface
+    pub fn forget(&self, idx: u32) {
+        self.interfaces.lock().unwrap().remove(&idx);
+    }
+}
+
+/// A bus-off from detection to confirmed recovery
+#[derive(Debug, Clone)]
+pub struct Outage {
+    /// Interface that was bus-off
+    pub interface: CanInterfaceInfo,
+    /// When the bus-off was detected
+    pub started: SystemTime,
+    /// How long the bus was unavailable
+    pub downtime: Duration,
+}
+
+/// Ring buffer of the most recently closed outages, oldest first
+///
+/// Clones share the same buffer.
+#[derive(Debug, Clone)]
+pub struct OutageLog {
+    outages: Arc<Mutex<Ring<Outage>>>,
+}
+
+impl OutageLog {
+    /// Create a log holding up to `capacity` outages; 0 disables recording
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            outages: Arc::new(Mutex::new(Ring::new(capacity))),
+        }
+    }
+
+    /// Record a closed outage, evicting the oldest if the log is full
+    pub fn record(&self, outage: Outage) {
+        self.outages.lock().unwrap().push(outage);
+    }
+
+    /// Snapshot of the recorded outages, oldest first
+    pub fn outages(&self) -> Vec<Outage> {
+        self.outages.lock().unwrap().all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::{BusEventSource, BusEventType},
+        testing::{can0, can1},
+    };
+    use socketcan::nl::CanState;
+
+    #[test]
+    fn rings_evict_their_oldest_items() {
+        let mut ring = Ring::new(2);
+        for item in 1..=3 {
+            ring.push(item);
+        }
+        assert_eq!(ring.all(), vec![2, 3]);
+        assert_eq!(ring.last(1), vec![3]);
+        assert_eq!(ring.last(5), vec![2, 3]);
+
+        let mut disabled = Ring::new(0);
+        disabled.push(1);
+        assert!(disabled.all().is_empty());
+    }
+
+    #[test]
+    fn event_history_keeps_the_latest_events_per_interface() {
+        let history = EventHistory::new(2);
+        history.record(&BusEvent::bus_off(
+            can0(),
+            BusEventSource::Poll(CanState::BusOff),
+        ));
+        history.record(&BusEvent::restart(
+            can0(),
+            BusEventSource::Poll(CanState::ErrorActive),
+        ));
+        history.record(&BusEvent::bus_off(
+            can1(),
+            BusEventSource::Poll(CanState::BusOff),
+        ));
+        history.record(&BusEvent::stopped(
+            can0(),
+            BusEventSource::Poll(CanState::Stopped),
+        ));
+
+        let types: Vec<_> = history
+            .last(1, 10)
+            .iter()
+            .map(|e| e.event_type.clone())
+            .collect();
+        assert_eq!(types, vec![BusEventType::Restart, BusEventType::Stopped]);
+        assert_eq!(history.last(1, 1)[0].event_type, BusEventType::Stopped);
+        assert_eq!(history.last(2, 10).len(), 1);
+    }
+}
@@ -0,0 +1,401 @@
+//! User-supplied commands run on bus events
+//!
+//! A hook command is split into words like a shell would (whitespace separated, with single
+//! quotes, double quotes and backslash escapes), then these placeholders are substituted in each
+//! word:
+//!
+//! | Placeholder     | Value                                                           |
+//! |-----------------|-----------------------------------------------------------------|
+//! | `{interface}`   | Interface name                                                  |
+//! | `{idx}`         | Interface index                                                 |
+//! | `{event}`       | `bus_off`, `restart`, `recovered`, `error_warning`,             |
+//! |                 | `error_passive`, `error_threshold` or `passive_dwell`           |
+//! | `{timestamp}`   | Unix time of the event in seconds with millisecond precision    |
+//! | `{source}`      | `error_frame`, `netlink`, `poll`, `sysfs`, `link`,              |
+//! |                 | `passive_dwell` or `circuit_breaker`                            |
+//! | `{downtime_ms}` | How long the interface was down (recovered hook only)           |
+//!
+//! The first word is executed directly, without a shell, so substituted values can never be
+//! interpreted as shell syntax. When shell mode is enabled the command runs through
+//! `/bin/sh -c` instead, with every substituted value single-quoted.
+//!
+//! The same values are also passed in the environment as `CANSENTINEL_IFACE`,
+//! `CANSENTINEL_IDX`, `CANSENTINEL_EVENT` and `CANSENTINEL_DOWNTIME_MS`.
+
+use crate::events::{BusEvent, BusEventType};
+use std::time::{Duration, SystemTime};
+use tokio::{process::Command, task::JoinSet};
+use tracing::{Instrument, error, info};
+
+/// Commands to run when bus events are handled
+///
+/// Commands are spawned into the caller's [`JoinSet`] without blocking the event loop, so
+/// dropping the set stops waiting for them. The commands themselves run on.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    /// Run when an interface goes bus-off
+    pub on_bus_off: Option<String>,
+    /// Run when an interface reports that it was restarted
+    pub on_restart: Option<String>,
+    /// Run when an interface has recovered from bus-off
+    pub on_recovered: Option<String>,
+    /// Run when an interface enters the error warning or error passive state
+    pub on_degraded: Option<String>,
+    /// Run commands through `/bin/sh -c` rather than executing them directly
+    pub shell: bool,
+}
+
+impl Hooks {
+    /// Run the bus-off hook, if configured
+    pub fn bus_off(&self, event: &BusEvent, tasks: &mut JoinSet<()>) {
+        if let Some(command) = &self.on_bus_off {
+            self.run(command, event, "bus_off", None, tasks);
+        }
+    }
+
+    /// Run the restart hook, if configured
+    pub fn restart(&self, event: &BusEvent, tasks: &mut JoinSet<()>) {
+        if let Some(command) = &self.on_restart {
+            self.run(command, event, "restart", None, tasks);
+        }
+    }
+
+    /// Run the degraded hook for an error-warning, error-passive, error-threshold or
+    /// passive-dwell event, if configured
+    pub fn degraded(&self, event: &BusEvent, tasks: &mut JoinSet<()>) {
+        let Some(event_name) = degraded_event_name(&event.event_type) else {
+            return;
+        };
+        if let Some(command) = &self.on_degraded {
+            self.run(command, event, event_name, None, tasks);
+        }
+    }
+
+    /// Run the recovered hook, if configured
+    pub fn recovered(&self, event: &BusEvent, downtime: Duration, tasks: &mut JoinSet<()>) {
+        if let Some(command) = &self.on_recovered {
+            self.run(command, event, "recovered", Some(downtime), tasks);
+        }
+    }
+
+    /// Spawn a hook command into `tasks` and log how it exited, in the caller's span
+    fn run(
+        &self,
+        command: &str,
+        event: &BusEvent,
+        event_name: &'static str,
+        downtime: Option<Duration>,
+        tasks: &mut JoinSet<()>,
+    ) {
+        let values = placeholder_values(event, event_name, downtime);
+
+        let mut cmd = if self.shell {
+            let mut cmd = Command::new("/bin/sh");
+            cmd.arg("-c").arg(substitute(command, &values, shell_quote));
+            cmd
+        } else {
+            let words: Vec<String> = match split_words(command) {
+                Some(words) if !words.is_empty() => words
+                    .iter()
+                    .map(|word| substitute(word, &values, str::to_string))
+                    .collect(),
+                _ => {
+                    error!("Invalid {} hook command: {:?}", event_name, command);
+                    return;
+                }
+            };
+            let mut cmd = Command::new(&words[0]);
+            cmd.args(&words[1..]);
+            cmd
+        };
+
+        cmd.env("CANSENTINEL_IFACE", &event.interface.name)
+            .env("CANSENTINEL_IDX", event.interface.idx.to_string())
+            .env("CANSENTINEL_EVENT", event_name);
+        if let Some(downtime) = downtime {
+            cmd.env("CANSENTINEL_DOWNTIME_MS", downtime.as_millis().to_string());
+        }
+
+        tasks.spawn(
+            async move {
+                match cmd.status().await {
+                    Ok(status) => info!("{} hook exited with {}", event_name, status),
+                    Err(e) => error!("Failed to run {} hook: {}", event_name, e),
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+/// `{event}` of the degraded hook, for the events it runs on
+fn degraded_event_name(event_type: &BusEventType) -> Option<&'static str> {
+    match event_type {
+        BusEventType::ErrorWarning => Some("error_warning"),
+        BusEventType::ErrorPassive => Some("error_passive"),
+        BusEventType::ErrorThreshold => Some("error_threshold"),
+        BusEventType::PassiveDwell => Some("passive_dwell"),
+        _ => None,
+    }
+}
+
+/// Values substituted for each placeholder
+fn placeholder_values(
+    event: &BusEvent,
+    event_name: &str,
+    downtime: Option<Duration>,
+) -> Vec<(&'static str, String)> {
+    let timestamp = event
+        .kernel_timestamp
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut values = vec![
+        ("interface", event.interface.name.clone()),
+        ("idx", event.interface.idx.to_string()),
+        ("event", event_name.to_string()),
+        (
+            "timestamp",
+            format!("{}.{:03}", timestamp.as_secs(), timestamp.subsec_millis()),
+        ),
+        ("source", event.event_source.name().to_string()),
+    ];
+    if let Some(downtime) = downtime {
+        values.push(("downtime_ms", downtime.as_millis().to_string()));
+    }
+    values
+}
+
+/// Replace every known `{placeholder}` in `template`, leaving unknown ones as they are
+fn substitute(
+    template: &str,
+    values: &[(&str, String)],
+    escape: impl Fn(&str) -> String,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let key = &rest[1..close];
+            values
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (close, value))
+        });
+        match value {
+            Some((close, value)) => {
+                out.push_str(&escape(value));
+                rest = &rest[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Quote a value so `/bin/sh` treats it as a single literal word
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Split a command line into words, honouring quotes and backslash escapes
+///
+/// Returns `None` if a quote is left unterminated.
+fn split_words(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next()?);
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::BusEventSource;
+    use socketcan::{CanErrorFrame, nl::CanState};
+
+    /// The row of the placeholder table documenting `placeholder`, with its continuation lines
+    fn documented(placeholder: &str) -> String {
+        let table = include_str!("hooks.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("//! |"));
+        let mut row = String::new();
+        for line in table {
+            let (name, value) = line.split_once('|').unwrap();
+            match name.trim() {
+                "" if !row.is_empty() => row.push_str(value),
+                name if name == format!("`{placeholder}`") => row.push_str(value),
+                _ if !row.is_empty() => break,
+                _ => {}
+            }
+        }
+        row
+    }
+
+    #[test]
+    fn every_event_name_is_documented() {
+        let event_types = [
+            BusEventType::BusOff,
+            BusEventType::Restart,
+            BusEventType::Stopped,
+            BusEventType::GaveUp,
+            BusEventType::Recovered,
+            BusEventType::ErrorWarning,
+            BusEventType::ErrorPassive,
+            BusEventType::ErrorThreshold,
+            BusEventType::CarrierLost,
+            BusEventType::CarrierRestored,
+            BusEventType::CountersReported,
+            BusEventType::PassiveDwell,
+        ];
+        let row = documented("event");
+        let names = ["bus_off", "restart", "recovered"]
+            .into_iter()
+            .chain(event_types.iter().filter_map(degraded_event_name));
+        for name in names {
+            assert!(row.contains(&format!("`{name}`")), "{name} in {row:?}");
+        }
+    }
+
+    #[test]
+    fn every_source_name_is_documented() {
+        let sources = [
+            BusEventSource::ErrorFrame(CanErrorFrame::new_error(0, &[]).unwrap()),
+            BusEventSource::StateUpdate(CanState::BusOff),
+            BusEventSource::Poll(CanState::BusOff),
+            BusEventSource::Sysfs(CanState::BusOff),
+            BusEventSource::CircuitBreaker,
+            BusEventSource::LinkFlags {
+                up: true,
+                running: false,
+            },
+            BusEventSource::PassiveDwell(Duration::ZERO),
+        ];
+        let row = documented("source");
+        for source in &sources {
+            // Fails to build when a source is added, as a reminder to list it above
+            match source {
+                BusEventSource::ErrorFrame(_)
+                | BusEventSource::StateUpdate(_)
+                | BusEventSource::Poll(_)
+                | BusEventSource::Sysfs(_)
+                | BusEventSource::CircuitBreaker
+                | BusEventSource::LinkFlags { .. }
+                | BusEventSource::PassiveDwell(_) => {}
+            }
+            let name = source.name();
+            assert!(row.contains(&format!("`{name}`")), "{name} in {row:?}");
+        }
+    }
+
+    #[test]
+    fn words_are_split_on_whitespace() {
+        assert_eq!(
+            split_words("  logger -t  cansentinel\t{interface} "),
+            Some(vec![
+                "logger".to_string(),
+                "-t".to_string(),
+                "cansentinel".to_string(),
+                "{interface}".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn quotes_keep_words_together() {
+        assert_eq!(
+            split_words(r#"echo 'bus off' "on {interface}" mixed'single'"double""#),
+            Some(vec![
+                "echo".to_string(),
+                "bus off".to_string(),
+                "on {interface}".to_string(),
+                "mixedsingledouble".to_string(),
+            ])
+        );
+        // Empty quotes are still a word
+        assert_eq!(
+            split_words("echo ''"),
+            Some(vec!["echo".to_string(), String::new()])
+        );
+    }
+
+    #[test]
+    fn backslashes_escape_the_next_character() {
+        assert_eq!(
+            split_words(r#"echo a\ b \' "say \"hi\"" 'no\escape'"#),
+            Some(vec![
+                "echo".to_string(),
+                "a b".to_string(),
+                "'".to_string(),
+                r#"say "hi""#.to_string(),
+                r"no\escape".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_and_unterminated_commands() {
+        assert_eq!(split_words(""), Some(vec![]));
+        assert_eq!(split_words("   "), Some(vec![]));
+        assert_eq!(split_words("echo 'open"), None);
+        assert_eq!(split_words(r#"echo "open"#), None);
+        assert_eq!(split_words(r"echo trailing\"), None);
+    }
+
+    #[test]
+    fn placeholders_are_quoted_for_the_shell() {
+        let values = vec![("interface", "it's".to_string())];
+        assert_eq!(
+            substitute("echo {interface} {unknown}", &values, shell_quote),
+            r"echo 'it'\''s' {unknown}"
+        );
+    }
+}
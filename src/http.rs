@@ -0,0 +1,201 @@
+//! Minimal HTTP/1 server shared by the metrics endpoint and the API
+//!
+//! Every connection carries a single request without a body, which is answered before the
+//! connection is closed.
+
+use std::{future::Future, io, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    task::JoinHandle,
+};
+use tracing::debug;
+
+/// Largest request head read before giving up on a client
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Time a client gets to send its request head
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connections answered at once, further clients wait in the listen backlog
+const MAX_CONNECTIONS: usize = 16;
+
+/// Head of a request
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Parse a request head, ignoring malformed header lines
+    pub fn parse(head: &str) -> Self {
+        let mut lines = head.split("\r\n");
+        let mut parts = lines.next().unwrap_or_default().split(' ');
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Self {
+            method,
+            path,
+            headers,
+        }
+    }
+
+    /// Values of every header called `name`, compared case-insensitively
+    pub fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Response to a [`Request`]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: &'static str,
+    content_type: &'static str,
+    headers: Vec<(&'static str, &'static str)>,
+    body: String,
+}
+
+impl Response {
+    pub fn new(status: &'static str, content_type: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// Add a header to those every response carries
+    pub fn with_header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str("Connection: close\r\n\r\n");
+        response.push_str(&self.body);
+        response.into_bytes()
+    }
+}
+
+/// Answer connections on `listener` with `respond` until the returned task is aborted
+///
+/// A request `respond` returns no response for is closed without an answer. `name` prefixes the
+/// failures logged.
+pub(crate) fn serve<F, Fut>(listener: TcpListener, name: &'static str, respond: F) -> JoinHandle<()>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<Response>> + Send + 'static,
+{
+    let respond = Arc::new(respond);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    tokio::spawn(async move {
+        loop {
+            let Ok(permit) = Arc::clone(&connections).acquire_owned().await else {
+                return;
+            };
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let respond = Arc::clone(&respond);
+                    tokio::spawn(async move {
+                        if let Err(e) = answer(stream, respond.as_ref()).await {
+                            debug!("{}: failed to answer request: {}", name, e);
+                        }
+                        drop(permit);
+                    });
+                }
+                Err(e) => debug!("{}: failed to accept connection: {}", name, e),
+            }
+        }
+    })
+}
+
+/// Answer a single request and close the connection
+async fn answer<F, Fut>(mut stream: TcpStream, respond: &F) -> io::Result<()>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Option<Response>>,
+{
+    let Ok(head) = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await else {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no request received in time",
+        ));
+    };
+    let Some(request) = head? else {
+        return Ok(());
+    };
+    let Some(response) = respond(request).await else {
+        return Ok(());
+    };
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read a request head, or nothing if the client closed the connection or sent too much
+async fn read_head(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    Ok(Some(Request::parse(&String::from_utf8_lossy(&request))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_heads() {
+        let request = Request::parse(
+            "POST /interfaces/can0/restart HTTP/1.1\r\nHost: vehicle\r\nauthorization:  Bearer x \r\nbogus\r\n\r\n",
+        );
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/interfaces/can0/restart");
+        assert_eq!(
+            request.headers("Authorization").collect::<Vec<_>>(),
+            ["Bearer x"]
+        );
+        assert_eq!(request.headers("Accept").count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_clients_are_timed_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve(listener, "Test", |_| async {
+            Some(Response::new("200 OK", "text/plain", String::new()))
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.is_empty());
+        server.abort();
+    }
+}
@@ -1,8 +1,69 @@
 //! CAN interface info
 
-use nix::Result;
+use nix::{errno::Errno, libc::ARPHRD_CAN};
+use socketcan::{CanInterface, nl::CanState};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{Span, error_span};
+
+/// Location of network device entries in sysfs
+pub(crate) const SYSFS_NET: &str = "/sys/class/net";
+
+/// Key recognized in an interface alias to set its restart delay in milliseconds
+const ALIAS_DELAY_KEY: &str = "cansentinel-delay";
+
+/// Errors resolving a CAN interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceError {
+    /// The interface could not be looked up
+    Lookup(Errno),
+    /// The interface exists but isn't a CAN interface
+    NotCan {
+        /// Interface name
+        name: String,
+        /// Hardware type reported by the kernel (`ARPHRD_*`)
+        link_type: u16,
+    },
+}
+
+impl From<Errno> for InterfaceError {
+    fn from(errno: Errno) -> Self {
+        Self::Lookup(errno)
+    }
+}
+
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lookup(errno) => write!(f, "{}", errno),
+            Self::NotCan { name, link_type } => write!(
+                f,
+                "{} is not a CAN interface (link type {}, expected {})",
+                name, link_type, ARPHRD_CAN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InterfaceError {}
+
+type Result<T> = std::result::Result<T, InterfaceError>;
+
+/// Interfaces watched by running event sources, which can be changed while they run
+pub(crate) type SharedInterfaces = Arc<RwLock<Vec<CanInterfaceInfo>>>;
+
 /// Information about a CAN interface
-#[derive(Debug, Clone)]
+///
+/// Equality and hashing cover both the index and the name. [`Monitor::reload`] matches
+/// interfaces by index alone, so one renamed in place keeps its settings and history.
+///
+/// [`Monitor::reload`]: crate::Monitor::reload
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CanInterfaceInfo {
     /// Interface index (from kernel)
     pub idx: u32,
@@ -11,12 +72,327 @@ pub struct CanInterfaceInfo {
 }
 
 impl CanInterfaceInfo {
+    /// Span for log messages about this interface
+    ///
+    /// At error level, so messages still name the interface when only warnings and errors are
+    /// logged.
+    pub(crate) fn span(&self) -> Span {
+        error_span!("can", iface = %self.name, idx = self.idx)
+    }
+
     /// Create a new CanInterfaceInfo by looking up the interface index
+    ///
+    /// Fails with [`InterfaceError::NotCan`] if the interface exists but isn't a CAN interface.
     pub fn new(name: &str) -> Result<Self> {
+        Self::lookup(name)?.check_can()
+    }
+
+    /// Look up the interface index without checking that it is a CAN interface
+    pub fn lookup(name: &str) -> Result<Self> {
         let idx = nix::net::if_::if_nametoindex(name)?;
         Ok(Self {
             idx,
             name: name.to_string(),
         })
     }
+
+    /// Create a CanInterfaceInfo by looking up the name of the interface with index `idx`
+    ///
+    /// The reverse of [`CanInterfaceInfo::new`], for when only the index is known. Fails with
+    /// [`InterfaceError::NotCan`] if the interface exists but isn't a CAN interface.
+    pub fn from_index(idx: u32) -> Result<Self> {
+        let name = nix::net::if_::if_indextoname(idx)?
+            .to_string_lossy()
+            .into_owned();
+        Self { idx, name }.check_can()
+    }
+
+    /// Fail with [`InterfaceError::NotCan`] unless this is a CAN interface
+    fn check_can(self) -> Result<Self> {
+        match link_type(&self.name) {
+            Some(link_type) if link_type != ARPHRD_CAN => Err(InterfaceError::NotCan {
+                name: self.name,
+                link_type,
+            }),
+            // If the type can't be read there's nothing to validate against
+            _ => Ok(self),
+        }
+    }
+
+    /// Every CAN interface currently present, in index order
+    ///
+    /// Walks the kernel's link table over RTNL, so interfaces in other network namespaces aren't
+    /// seen.
+    pub fn enumerate() -> io::Result<Vec<Self>> {
+        let mut interfaces: Vec<Self> = crate::monitoring::netlink::dump_links()?
+            .iter()
+            .filter_map(Self::from_link)
+            .collect();
+        interfaces.sort_by_key(|interface| interface.idx);
+        Ok(interfaces)
+    }
+
+    /// The interface a link from an RTNL dump describes, if it is a CAN interface
+    pub(crate) fn from_link(link: &neli::rtnl::Ifinfomsg) -> Option<Self> {
+        use neli::consts::rtnl::Ifla;
+
+        if u16::from(link.ifi_type) != ARPHRD_CAN {
+            return None;
+        }
+        let name = link
+            .rtattrs
+            .get_attr_handle()
+            .get_attr_payload_as_with_len::<String>(Ifla::Ifname)
+            .ok()?;
+        Some(Self {
+            idx: link.ifi_index as u32,
+            name,
+        })
+    }
+
+    /// Resolve an interface from a stable identity rather than its kernel name
+    ///
+    /// The identity is the sysfs path of the CAN controller's parent device, as found by
+    /// resolving `/sys/class/net/<name>/device`. Only the trailing components need to match, so
+    /// `platform/fe204000.can` is as good as the full `/sys/devices/...` path. Controllers that
+    /// expose several channels on one device can be told apart by appending `#<dev_port>`.
+    pub fn from_stable_id(stable_id: &str) -> Result<Self> {
+        let (wanted, port) = parse_stable_id(stable_id)?;
+
+        let mut matched: Option<String> = None;
+        for entry in std::fs::read_dir(SYSFS_NET).map_err(|_| Errno::ENOENT)? {
+            let Ok(entry) = entry else { continue };
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if !device_path(&name).is_some_and(|device| device.ends_with(wanted)) {
+                continue;
+            }
+            if port.is_some() && port != dev_port(&name) {
+                continue;
+            }
+            if matched.is_some() {
+                // Several channels share this device, a port is needed to pick one
+                return Err(Errno::ENOTUNIQ.into());
+            }
+            matched = Some(name);
+        }
+
+        match matched {
+            Some(name) => Self::new(&name),
+            None => Err(Errno::ENODEV.into()),
+        }
+    }
+
+    /// Interface alias (`ifalias`), if one is set
+    pub fn alias(&self) -> Option<String> {
+        let alias =
+            std::fs::read_to_string(Path::new(SYSFS_NET).join(&self.name).join("ifalias")).ok()?;
+        let alias = alias.trim();
+        (!alias.is_empty()).then(|| alias.to_string())
+    }
+
+    /// Restart delay annotated in the interface alias by provisioning tooling
+    ///
+    /// Looks for a `cansentinel-delay=<ms>` token among the whitespace or comma separated words
+    /// of the alias, e.g. `ip link set can0 alias "engine bus cansentinel-delay=2000"`.
+    pub fn alias_restart_delay(&self) -> Option<Duration> {
+        parse_alias_delay(&self.alias()?)
+    }
+
+    /// Canonical sysfs path of this interface's parent device, if it has one
+    pub fn device_path(&self) -> Option<PathBuf> {
+        device_path(&self.name)
+    }
+
+    /// Controller state read from the kernel, if it could be read
+    ///
+    /// Costs a netlink request on every call, see [`StateCache`] for a cheaper alternative.
+    pub fn current_state(&self) -> Option<CanState> {
+        CanInterface::open_iface(self.idx).state().ok().flatten()
+    }
+}
+
+/// Last controller state reported for each interface by netlink or polling
+///
+/// Kept up to date by the event sources, so reading it doesn't cost a syscall. Clones share the
+/// same states. Netlink only reports the state along with link changes such as bus-off and
+/// restarts, so entering ERROR_WARNING or ERROR_PASSIVE may not show up here until the next one.
+#[derive(Debug, Clone, Default)]
+pub struct StateCache {
+    states: Arc<RwLock<HashMap<u32, CanState>>>,
+}
+
+impl StateCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Last state reported for the interface with index `idx`, if any
+    pub fn get(&self, idx: u32) -> Option<CanState> {
+        self.states.read().unwrap().get(&idx).copied()
+    }
+
+    /// Last state reported for `interface`, read from the kernel if none was reported yet
+    pub fn get_or_read(&self, interface: &CanInterfaceInfo) -> Option<CanState> {
+        self.get(interface.idx)
+            .or_else(|| interface.current_state())
+    }
+
+    pub(crate) fn set(&self, idx: u32, state: CanState) {
+        self.states.write().unwrap().insert(idx, state);
+    }
+
+    pub(crate) fn forget(&self, idx: u32) {
+        self.states.write().unwrap().remove(&idx);
+    }
+}
+
+/// Whether an interface name given by the user is a glob pattern rather than a plain name
+pub fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Match an interface name against a glob pattern, where `*` matches any run of characters and
+/// `?` any single character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position in the pattern after the last `*`, and in the name where that `*` stopped matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and try again
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Canonical sysfs path of the parent device of a network interface
+fn device_path(name: &str) -> Option<PathBuf> {
+    std::fs::canonicalize(Path::new(SYSFS_NET).join(name).join("device")).ok()
+}
+
+/// Hardware type (`ARPHRD_*`) of a network interface
+fn link_type(name: &str) -> Option<u16> {
+    std::fs::read_to_string(Path::new(SYSFS_NET).join(name).join("type"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Port number of a network interface on its parent device
+fn dev_port(name: &str) -> Option<u32> {
+    std::fs::read_to_string(Path::new(SYSFS_NET).join(name).join("dev_port"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Split a stable identity into the device path and the `#<dev_port>`, if one is given
+fn parse_stable_id(stable_id: &str) -> Result<(&Path, Option<u32>)> {
+    let (path, port) = match stable_id.rsplit_once('#') {
+        Some((path, port)) => (path, Some(port.parse::<u32>().map_err(|_| Errno::EINVAL)?)),
+        None => (stable_id, None),
+    };
+    let path = Path::new(path.trim_end_matches('/'));
+    if path.as_os_str().is_empty() {
+        return Err(Errno::EINVAL.into());
+    }
+    Ok((path, port))
+}
+
+/// Parse a `cansentinel-delay=<ms>` annotation out of an interface alias
+fn parse_alias_delay(alias: &str) -> Option<Duration> {
+    alias
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .find_map(|word| word.strip_prefix(ALIAS_DELAY_KEY)?.strip_prefix('='))
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run_of_characters() {
+        assert!(glob_match("can*", "can0"));
+        assert!(glob_match("can*", "can"));
+        assert!(glob_match("*", "vcan12"));
+        assert!(glob_match("*can*", "vcan12"));
+        assert!(glob_match("c*n*1", "can_bus1"));
+        assert!(!glob_match("can*", "vcan0"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("can?", "can0"));
+        assert!(glob_match("?can?", "vcan1"));
+        assert!(!glob_match("can?", "can"));
+        assert!(!glob_match("can?", "can10"));
+    }
+
+    #[test]
+    fn names_that_differ_do_not_match() {
+        assert!(glob_match("can0", "can0"));
+        assert!(!glob_match("can0", "can1"));
+        assert!(!glob_match("can0", "can01"));
+        assert!(!glob_match("", "can0"));
+        assert!(!glob_match("can*x", "can0"));
+    }
+
+    #[test]
+    fn alias_delays_are_found_among_the_words_of_the_alias() {
+        let ms = |alias| parse_alias_delay(alias).map(|delay| delay.as_millis());
+        assert_eq!(ms("engine bus cansentinel-delay=2000"), Some(2000));
+        assert_eq!(ms("engine,cansentinel-delay=500,rear"), Some(500));
+        assert_eq!(ms("cansentinel-delay=0"), Some(0));
+    }
+
+    #[test]
+    fn malformed_alias_delays_are_ignored() {
+        assert_eq!(parse_alias_delay("engine bus"), None);
+        assert_eq!(parse_alias_delay("cansentinel-delay"), None);
+        assert_eq!(parse_alias_delay("cansentinel-delay 2000"), None);
+        assert_eq!(parse_alias_delay("cansentinel-delay=2s"), None);
+        assert_eq!(parse_alias_delay("cansentinel-delay="), None);
+        assert_eq!(parse_alias_delay("cansentinel-delayx=5"), None);
+        assert_eq!(parse_alias_delay("my-cansentinel-delay=5"), None);
+    }
+
+    #[test]
+    fn stable_ids_are_split_into_path_and_port() {
+        assert_eq!(
+            parse_stable_id("platform/fe204000.can/").unwrap(),
+            (Path::new("platform/fe204000.can"), None)
+        );
+        assert_eq!(
+            parse_stable_id("pci0000:00/0000:00:1c.0#1").unwrap(),
+            (Path::new("pci0000:00/0000:00:1c.0"), Some(1))
+        );
+        assert!(parse_stable_id("platform/fe204000.can#a").is_err());
+        assert!(parse_stable_id("platform/fe204000.can#").is_err());
+        assert!(parse_stable_id("").is_err());
+        assert!(parse_stable_id("/#0").is_err());
+    }
 }
@@ -0,0 +1,149 @@
+//! Learn mode: baseline error rate collection
+
+use crate::{
+    events::{BusEvent, BusEventType},
+    interface::CanInterfaceInfo,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Window of the error threshold a report suggests
+const SUGGESTED_WINDOW: Duration = Duration::from_secs(60);
+
+/// Counters observed for a single interface while learning
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceBaseline {
+    /// Error frames received on the interface
+    pub error_frames: u64,
+    /// Bus-off events
+    pub bus_offs: u64,
+    /// Restart events
+    pub restarts: u64,
+    /// Stopped events
+    pub stops: u64,
+}
+
+/// Records error frame rates and state transitions per interface
+///
+/// Cloning the recorder shares the underlying counters, so one clone can be handed to each monitor.
+#[derive(Debug, Clone, Default)]
+pub struct LearnRecorder {
+    baselines: Arc<Mutex<BTreeMap<String, InterfaceBaseline>>>,
+}
+
+impl LearnRecorder {
+    /// Create an empty recorder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make sure an interface shows up in the report even if nothing happens on it
+    pub fn track(&self, interface: &CanInterfaceInfo) {
+        self.with_baseline(&interface.name, |_| ());
+    }
+
+    /// Record a received error frame
+    pub fn record_error_frame(&self, interface: &CanInterfaceInfo) {
+        self.with_baseline(&interface.name, |b| b.error_frames += 1);
+    }
+
+    /// Record a bus event from the event loop
+    pub fn record_event(&self, event: &BusEvent) {
+        self.with_baseline(&event.interface.name, |b| match event.event_type {
+            BusEventType::BusOff => b.bus_offs += 1,
+            BusEventType::Restart => b.restarts += 1,
+            BusEventType::Stopped => b.stops += 1,
+            BusEventType::GaveUp
+            | BusEventType::Recovered
+            | BusEventType::ErrorWarning
+            | BusEventType::ErrorPassive
+            | BusEventType::ErrorThreshold
+            | BusEventType::CarrierLost
+            | BusEventType::CarrierRestored
+            | BusEventType::CountersReported
+            | BusEventType::PassiveDwell => (),
+        });
+    }
+
+    /// Snapshot of the counters collected so far
+    pub fn baselines(&self) -> BTreeMap<String, InterfaceBaseline> {
+        self.baselines.lock().unwrap().clone()
+    }
+
+    /// Build a config file listing the interfaces, with the counters collected over `elapsed` and
+    /// a suggested error threshold in comments
+    ///
+    /// The error threshold is global, so it is suggested for the busiest interface, at three times
+    /// its observed rate so normal operation stays quiet. The report is a valid config file.
+    pub fn report(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(1.0);
+        let baselines = self.baselines();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# cansentinel learn mode: observed for {:?}", elapsed);
+        for (name, b) in &baselines {
+            let _ = writeln!(
+                out,
+                "# {name}: {} error frames ({:.2}/s), {} bus-off, {} restart, {} stopped",
+                b.error_frames,
+                b.error_frames as f64 / secs,
+                b.bus_offs,
+                b.restarts,
+                b.stops
+            );
+        }
+        let busiest = baselines
+            .values()
+            .map(|b| b.error_frames)
+            .max()
+            .unwrap_or_default();
+        let count = (busiest as f64 / secs * SUGGESTED_WINDOW.as_secs_f64() * 3.0)
+            .ceil()
+            .max(1.0) as u64;
+        let _ = writeln!(out, "#");
+        let _ = writeln!(
+            out,
+            "# Suggested error threshold, set on the command line: --error-threshold-class <class> \
+             --error-threshold-count {count} --error-threshold-window-ms {}",
+            SUGGESTED_WINDOW.as_millis()
+        );
+        for name in baselines.keys() {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "[[interface]]");
+            let _ = writeln!(out, "name = \"{name}\"");
+        }
+        out
+    }
+
+    fn with_baseline(&self, name: &str, f: impl FnOnce(&mut InterfaceBaseline)) {
+        let mut baselines = self.baselines.lock().unwrap();
+        f(baselines.entry(name.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        testing::{can0, can1},
+    };
+
+    #[test]
+    fn reports_can_be_used_as_a_config_file() {
+        let recorder = LearnRecorder::new();
+        recorder.track(&can1());
+        for _ in 0..30 {
+            recorder.record_error_frame(&can0());
+        }
+
+        let report = recorder.report(Duration::from_secs(60));
+        assert!(report.contains("--error-threshold-count 90 --error-threshold-window-ms 60000"));
+        let config = Config::from_toml(&report).unwrap();
+        assert_eq!(config.interface_names, ["can0", "can1"]);
+    }
+}
@@ -1,11 +1,39 @@
+#[cfg(feature = "api")]
+pub mod api;
+pub mod backend;
 pub mod config;
+pub mod control;
+pub mod correlation;
 pub mod events;
+pub mod groups;
+pub mod history;
+pub mod hooks;
+#[cfg(any(feature = "api", feature = "metrics"))]
+mod http;
 pub mod interface;
+pub mod learn;
+pub mod liveness;
+pub mod log;
+pub mod metrics;
+pub mod monitor;
 pub mod monitoring;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod restart;
+pub mod runtime;
+pub mod snapshot;
+pub mod state;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use config::Config;
 pub use events::{BusEvent, BusEventType};
-pub use interface::CanInterfaceInfo;
+pub use interface::{CanInterfaceInfo, InterfaceError};
+pub use learn::LearnRecorder;
+pub use metrics::Metrics;
+pub use monitor::{
+    Action, InterfaceChanges, InterfaceStatus, Monitor, MonitorOptions, MonitorStats,
+};
 pub use monitoring::{monitor_interface_errors, monitor_netlink};
 pub use restart::RestartManager;
+pub use runtime::EventStream;
@@ -0,0 +1,84 @@
+//! Signs of life from the monitoring tasks
+//!
+//! Each source reports in at least every [`BEAT_INTERVAL`], even on a quiet bus, so a task that
+//! deadlocked or a netlink thread that silently stopped shows up as [stale](Liveness::stale)
+//! rather than leaving cansentinel running blind.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Longest a running source goes without reporting in
+pub const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A monitoring task whose liveness is tracked
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Task {
+    /// The netlink listener
+    Netlink,
+    /// The error frame monitor of the named interface
+    ErrorFrames(String),
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Task::Netlink => f.write_str("netlink"),
+            Task::ErrorFrames(name) => write!(f, "error frames on {}", name),
+        }
+    }
+}
+
+/// Last time each monitoring task showed signs of life
+///
+/// Clones share the same timestamps.
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    beats: Arc<Mutex<HashMap<Task, Instant>>>,
+}
+
+impl Liveness {
+    /// Create a tracker with no tasks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `task` is alive
+    pub fn beat(&self, task: &Task) {
+        let mut beats = self.beats.lock().unwrap();
+        match beats.get_mut(task) {
+            Some(at) => *at = Instant::now(),
+            None => {
+                beats.insert(task.clone(), Instant::now());
+            }
+        }
+    }
+
+    /// Stop tracking `task`, which stopped legitimately
+    pub fn forget(&self, task: &Task) {
+        self.beats.lock().unwrap().remove(task);
+    }
+
+    /// How long ago `task` last reported in, if it is tracked
+    pub fn age(&self, task: &Task) -> Option<Duration> {
+        self.beats.lock().unwrap().get(task).map(Instant::elapsed)
+    }
+
+    /// Tasks that haven't reported in within `max_age`, with how long ago they last did
+    pub fn stale(&self, max_age: Duration) -> Vec<(Task, Duration)> {
+        let mut stale: Vec<_> = self
+            .beats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(task, at)| (task.clone(), at.elapsed()))
+            .filter(|(_, age)| *age > max_age)
+            .collect();
+        stale.sort_by(|a, b| b.1.cmp(&a.1));
+        stale
+    }
+}
@@ -0,0 +1,508 @@
+//! Logging setup
+//!
+//! cansentinel logs through [`tracing`]. Messages about a single interface are emitted inside a
+//! `can` span carrying the interface name and index, and events carry structured fields such as
+//! their source. The spans are created at error level, so they aren't filtered out with the
+//! level. Output goes to stderr. When running under systemd (`JOURNAL_STREAM` is set) each
+//! line is prefixed with its syslog priority, e.g. `<4>`, so journald records the severity, and
+//! timestamps are left to the journal. With the `syslog` feature, output can go to the local
+//! syslog daemon instead, see [`Target`].
+//!
+//! On a terminal, output is colored unless disabled or `NO_COLOR` is set: errors and bus-offs in
+//! red, warnings and scheduled restarts in yellow, and recoveries in green. Messages about those
+//! are logged under the [`BUS_OFF`], [`RESTART`] and [`RECOVERED`] targets, so they stand out
+//! whatever their level.
+
+#[cfg(feature = "syslog")]
+mod syslog;
+
+#[cfg(feature = "syslog")]
+pub use syslog::Facility;
+
+use std::{fmt, io::IsTerminal, str::FromStr};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    filter::LevelFilter,
+    fmt::{
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+        format::Writer,
+        time::{FormatTime, SystemTime},
+    },
+    layer::{Layered, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+
+/// Target of messages reporting a bus-off
+pub const BUS_OFF: &str = "cansentinel::bus_off";
+/// Target of messages reporting a scheduled restart
+pub const RESTART: &str = "cansentinel::restart";
+/// Target of messages reporting a recovery
+pub const RECOVERED: &str = "cansentinel::recovered";
+
+/// A layer of the global subscriber, such as the log output or an exporter
+pub type BoxedLayer = Box<dyn Layer<Layered<EnvFilter, Registry>> + Send + Sync>;
+
+/// Log severity, from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Failures that stop something from working
+    Error,
+    /// Significant events such as bus-off, and recoverable problems
+    Warn,
+    /// Steady-state operational messages
+    Info,
+    /// Detailed diagnostics, e.g. every error frame
+    Debug,
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => LevelFilter::ERROR,
+            Level::Warn => LevelFilter::WARN,
+            Level::Info => LevelFilter::INFO,
+            Level::Debug => LevelFilter::DEBUG,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!(
+                "invalid log level '{s}' (expected error, warn, info or debug)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where log messages go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// Standard error
+    #[default]
+    Stderr,
+    /// The local syslog daemon, under the given facility
+    #[cfg(feature = "syslog")]
+    Syslog(Facility),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    /// Parse `stderr` or `syslog`, which logs under the daemon facility
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "stderr" => Ok(Target::Stderr),
+            #[cfg(feature = "syslog")]
+            "syslog" => Ok(Target::Syslog(Facility::default())),
+            #[cfg(not(feature = "syslog"))]
+            "syslog" => Err("syslog output requires building with the syslog feature".to_string()),
+            _ => Err(format!(
+                "invalid log target '{s}' (expected stderr or syslog)"
+            )),
+        }
+    }
+}
+
+/// Level to log at given `--log-level`, `-q` and `-v`, in that order of precedence
+///
+/// `None` leaves it to `RUST_LOG`.
+pub fn level(explicit: Option<Level>, quiet: bool, verbose: bool) -> Option<Level> {
+    explicit.or(if quiet {
+        Some(Level::Warn)
+    } else if verbose {
+        Some(Level::Debug)
+    } else {
+        None
+    })
+}
+
+/// Install the global subscriber
+///
+/// An explicit `level` applies to everything. Otherwise `RUST_LOG` is honored, defaulting to
+/// info. If syslog can't be reached, messages go to stderr instead, after a warning. With
+/// `color`, stderr output is colored if it is a terminal and `NO_COLOR` isn't set.
+pub fn init(level: Option<Level>, target: Target, color: bool) {
+    init_with(level, target, color, None);
+}
+
+/// Install the global subscriber as with [`init`], with `extra` receiving everything that is
+/// logged as well
+pub fn init_with(level: Option<Level>, target: Target, color: bool, extra: Option<BoxedLayer>) {
+    let filter = filter(level);
+
+    // Syslog may be unavailable, which is only reported once logging works
+    let (output, unavailable): (BoxedLayer, Option<std::io::Error>) = match target {
+        Target::Stderr => (stderr_layer(color), None),
+        #[cfg(feature = "syslog")]
+        Target::Syslog(facility) => match syslog::Syslog::open(facility) {
+            Ok(syslog) => (
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .event_format(LineFormat { priority: false })
+                    .with_writer(syslog)
+                    .boxed(),
+                None,
+            ),
+            Err(e) => (stderr_layer(color), Some(e)),
+        },
+    };
+    let layers: Vec<BoxedLayer> = std::iter::once(output).chain(extra).collect();
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .init();
+
+    if let Some(e) = unavailable {
+        tracing::warn!("Can't log to syslog ({}), logging to stderr instead", e);
+    }
+}
+
+/// Filter for `level`, or `RUST_LOG` defaulting to info
+fn filter(level: Option<Level>) -> EnvFilter {
+    match level {
+        Some(level) => EnvFilter::default().add_directive(LevelFilter::from(level).into()),
+        None => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    }
+}
+
+fn stderr_layer(color: bool) -> BoxedLayer {
+    let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+
+    if std::env::var_os("JOURNAL_STREAM").is_some() {
+        layer
+            .with_ansi(false)
+            .event_format(LineFormat { priority: true })
+            .boxed()
+    } else if color
+        && std::io::stderr().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+    {
+        layer.with_ansi(true).event_format(ColorFormat).boxed()
+    } else {
+        layer.with_ansi(false).boxed()
+    }
+}
+
+/// Whether messages at `level` may be printed by the installed subscriber
+pub fn enabled(level: Level) -> bool {
+    LevelFilter::current() >= LevelFilter::from(level)
+}
+
+/// Syslog severity of a message at `level`
+fn priority(level: &tracing::Level) -> u8 {
+    match *level {
+        tracing::Level::ERROR => 3,
+        tracing::Level::WARN => 4,
+        tracing::Level::INFO => 6,
+        tracing::Level::DEBUG | tracing::Level::TRACE => 7,
+    }
+}
+
+/// Single-line format without timestamps, for a log daemon that adds its own
+///
+/// With `priority`, each line starts with its syslog priority, as journald expects on stderr.
+struct LineFormat {
+    priority: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for LineFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.priority {
+            write!(writer, "<{}>", priority(event.metadata().level()))?;
+        }
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                write!(writer, "{}", span.name())?;
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>()
+                    && !fields.is_empty()
+                {
+                    write!(writer, "{{{}}}", fields)?;
+                }
+                write!(writer, ": ")?;
+            }
+        }
+
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Colored format for a terminal, with the message in the color of what it reports
+struct ColorFormat;
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+impl<S, N> FormatEvent<S, N> for ColorFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let level_color = match *metadata.level() {
+            tracing::Level::ERROR => RED,
+            tracing::Level::WARN => YELLOW,
+            tracing::Level::INFO => GREEN,
+            tracing::Level::DEBUG | tracing::Level::TRACE => DIM,
+        };
+        let message_color = match metadata.target() {
+            BUS_OFF => Some(RED),
+            RESTART => Some(YELLOW),
+            RECOVERED => Some(GREEN),
+            _ => match *metadata.level() {
+                tracing::Level::ERROR => Some(RED),
+                tracing::Level::WARN => Some(YELLOW),
+                _ => None,
+            },
+        };
+
+        write!(writer, "{DIM}")?;
+        SystemTime.format_time(&mut writer)?;
+        write!(
+            writer,
+            "{RESET} {level_color}{:>5}{RESET} ",
+            metadata.level()
+        )?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                write!(writer, "{}", span.name())?;
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>()
+                    && !fields.is_empty()
+                {
+                    write!(writer, "{{{}}}", fields)?;
+                }
+                write!(writer, ": ")?;
+            }
+        }
+
+        if let Some(color) = message_color {
+            write!(writer, "{color}")?;
+            ctx.field_format().format_fields(writer.by_ref(), event)?;
+            writeln!(writer, "{RESET}")
+        } else {
+            ctx.field_format().format_fields(writer.by_ref(), event)?;
+            writeln!(writer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::can0;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::fmt::format::DefaultFields;
+
+    /// Log output collected in memory
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        /// What `log` printed at `level` in the single-line format
+        fn capture(level: Level, log: impl FnOnce()) -> String {
+            Output::capture_with(level, false, LineFormat { priority: false }, log)
+        }
+
+        /// What `log` printed at `level` in `format`
+        fn capture_with<F>(level: Level, ansi: bool, format: F, log: impl FnOnce()) -> String
+        where
+            F: FormatEvent<Layered<EnvFilter, Registry>, DefaultFields> + Send + Sync + 'static,
+        {
+            let output = Output::default();
+            let writer = output.clone();
+            let subscriber = tracing_subscriber::registry()
+                .with(filter(Some(level)))
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .with_ansi(ansi)
+                        .event_format(format)
+                        .with_writer(move || writer.clone()),
+                );
+            tracing::subscriber::with_default(subscriber, log);
+            String::from_utf8(output.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn levels_parse_case_insensitively() {
+        assert_eq!("error".parse(), Ok(Level::Error));
+        assert_eq!("WARN".parse(), Ok(Level::Warn));
+        assert_eq!("warning".parse(), Ok(Level::Warn));
+        assert_eq!("Info".parse(), Ok(Level::Info));
+        assert_eq!("debug".parse(), Ok(Level::Debug));
+        assert!("trace".parse::<Level>().is_err());
+        assert!("".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn levels_display_as_they_parse() {
+        for level in [Level::Error, Level::Warn, Level::Info, Level::Debug] {
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+    }
+
+    #[test]
+    fn targets_parse_case_insensitively() {
+        assert_eq!("stderr".parse(), Ok(Target::Stderr));
+        assert_eq!("STDERR".parse(), Ok(Target::Stderr));
+        assert!("stdout".parse::<Target>().is_err());
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn syslog_target_logs_under_the_daemon_facility() {
+        assert_eq!("syslog".parse(), Ok(Target::Syslog(Facility::Daemon)));
+    }
+
+    #[cfg(not(feature = "syslog"))]
+    #[test]
+    fn syslog_target_needs_the_syslog_feature() {
+        let err = "syslog".parse::<Target>().unwrap_err();
+        assert!(err.contains("syslog feature"), "{err}");
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn facilities_parse_case_insensitively() {
+        assert_eq!("user".parse(), Ok(Facility::User));
+        assert_eq!("Daemon".parse(), Ok(Facility::Daemon));
+        assert_eq!("local0".parse(), Ok(Facility::Local(0)));
+        assert_eq!("LOCAL7".parse(), Ok(Facility::Local(7)));
+        for invalid in ["local8", "local", "local-1", "kern", ""] {
+            assert!(invalid.parse::<Facility>().is_err(), "{invalid}");
+        }
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn facilities_display_as_they_parse() {
+        for facility in [Facility::User, Facility::Daemon, Facility::Local(3)] {
+            assert_eq!(facility.to_string().parse(), Ok(facility));
+        }
+    }
+
+    #[test]
+    fn quiet_logs_warnings() {
+        assert_eq!(level(None, true, false), Some(Level::Warn));
+        assert_eq!(level(None, true, true), Some(Level::Warn));
+        assert_eq!(level(None, false, true), Some(Level::Debug));
+        assert_eq!(level(None, false, false), None);
+        assert_eq!(level(Some(Level::Error), true, true), Some(Level::Error));
+
+        let output = Output::capture(level(None, true, false).unwrap(), || {
+            tracing::info!("Started error monitoring");
+            tracing::warn!("Interface is error-passive");
+        });
+        assert_eq!(output, "Interface is error-passive\n");
+    }
+
+    #[test]
+    fn line_format_can_prefix_the_priority() {
+        let output =
+            Output::capture_with(Level::Debug, false, LineFormat { priority: true }, || {
+                tracing::error!("Restart failed");
+                tracing::warn!(target: BUS_OFF, "Bus-off detected");
+                tracing::info!("Recovered");
+                tracing::debug!("Error frame");
+            });
+        assert_eq!(
+            output,
+            "<3>Restart failed\n<4>Bus-off detected\n<6>Recovered\n<7>Error frame\n"
+        );
+    }
+
+    #[test]
+    fn color_format_colors_messages_by_what_they_report() {
+        let output = Output::capture_with(Level::Info, true, ColorFormat, || {
+            tracing::info!(target: BUS_OFF, "Bus-off detected");
+        });
+        assert!(output.starts_with(DIM), "{output:?}");
+        assert!(
+            output.ends_with(&format!(
+                "{RESET} {GREEN} INFO{RESET} {RED}Bus-off detected{RESET}\n"
+            )),
+            "{output:?}"
+        );
+
+        let output = Output::capture_with(Level::Info, true, ColorFormat, || {
+            tracing::info!("Started error monitoring");
+        });
+        assert!(
+            output.ends_with(&format!(
+                "{RESET} {GREEN} INFO{RESET} Started error monitoring\n"
+            )),
+            "{output:?}"
+        );
+    }
+
+    #[test]
+    fn bus_offs_name_the_interface_when_only_warnings_are_logged() {
+        let output = Output::capture(Level::Warn, || {
+            can0().span().in_scope(|| {
+                tracing::info!("Started error monitoring");
+                tracing::warn!(target: BUS_OFF, "Bus-off detected");
+            })
+        });
+        assert_eq!(output, "can{iface=can0 idx=1}: Bus-off detected\n");
+    }
+}
@@ -0,0 +1,143 @@
+//! Output to the local syslog daemon
+//!
+//! Every message is sent to `/dev/log` as a single RFC 3164 datagram,
+//! `<PRI>cansentinel[pid]: message`, without a timestamp, which the daemon adds. Each message is
+//! addressed to the socket path afresh, so logging carries on after the daemon restarts.
+
+use super::priority;
+use std::{
+    fmt, io,
+    os::unix::{fs::FileTypeExt, net::UnixDatagram},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Socket the local syslog daemon listens on
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Program name messages are tagged with
+const TAG: &str = "cansentinel";
+
+/// Syslog facility messages are logged under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Facility {
+    /// Generic user-level messages
+    User,
+    /// System daemons
+    #[default]
+    Daemon,
+    /// Reserved for local use, `local0` to `local7`
+    Local(u8),
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local(n) => 16 + n,
+        }
+    }
+}
+
+impl FromStr for Facility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "user" => Ok(Facility::User),
+            "daemon" => Ok(Facility::Daemon),
+            local => local
+                .strip_prefix("local")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n <= 7)
+                .map(Facility::Local)
+                .ok_or_else(|| {
+                    format!("invalid syslog facility '{s}' (expected user, daemon or local0-7)")
+                }),
+        }
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Facility::User => f.write_str("user"),
+            Facility::Daemon => f.write_str("daemon"),
+            Facility::Local(n) => write!(f, "local{}", n),
+        }
+    }
+}
+
+/// Creates a [`Message`] for every log event
+#[derive(Debug)]
+pub(super) struct Syslog {
+    socket: Arc<UnixDatagram>,
+    facility: Facility,
+}
+
+impl Syslog {
+    /// Prepare to log to the local syslog daemon, failing if it isn't listening
+    pub(super) fn open(facility: Facility) -> io::Result<Self> {
+        if !std::fs::metadata(SYSLOG_SOCKET)?.file_type().is_socket() {
+            return Err(io::Error::other(format!(
+                "{} is not a socket",
+                SYSLOG_SOCKET
+            )));
+        }
+        Ok(Self {
+            socket: Arc::new(UnixDatagram::unbound()?),
+            facility,
+        })
+    }
+
+    fn message(&self, level: &tracing::Level) -> Message {
+        let pri = u16::from(self.facility.code()) * 8 + u16::from(priority(level));
+        Message {
+            socket: Arc::clone(&self.socket),
+            buf: format!("<{}>{}[{}]: ", pri, TAG, std::process::id()).into_bytes(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Message;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.message(&tracing::Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.message(meta.level())
+    }
+}
+
+/// A single log message, sent once it has been formatted completely
+pub(super) struct Message {
+    socket: Arc<UnixDatagram>,
+    buf: Vec<u8>,
+}
+
+impl io::Write for Message {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message {
+    fn drop(&mut self) {
+        while self.buf.last() == Some(&b'\n') {
+            self.buf.pop();
+        }
+        // Nowhere left to report a failure to
+        let _ = self.socket.send_to(&self.buf, Path::new(SYSLOG_SOCKET));
+    }
+}
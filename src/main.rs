@@ -3,97 +3,1632 @@
 //! cansentinel monitors CAN interface state changes and automatically restarts interfaces that enter the bus-off state.
 
 use cansentinel::{
-    BusEvent, BusEventType, CanInterfaceInfo, Config, RestartManager,
-    monitoring::{monitor_interface_errors, monitor_netlink},
+    Action, CanInterfaceInfo, Config, InterfaceError, LearnRecorder, Monitor, MonitorOptions,
+    backend::{CanControl, PERMISSION_HINT, SocketCanBackend},
+    config::{
+        ConfigError, DEFAULT_RESTART_DELAY, LinkSettings, RestartMethod, parse_duration,
+        parse_interface_name, parse_interface_regex, read_interface_file,
+    },
+    control::ControlRequest,
+    hooks::Hooks,
+    interface::{glob_match, is_glob},
+    log::{self, Level, Target},
+    monitor::check_once,
+    monitoring::{ErrorClass, ErrorMonitorOptions, ErrorThreshold, RetryBackoff},
+    restart::{AdaptiveDelay, Backoff, CircuitBreaker, Jitter},
+    runtime::{OverflowPolicy, QueueLimit, StateSource},
+    snapshot::{InterfaceSnapshot, interface_table},
 };
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use git_version::git_version;
-use socketcan::{CanInterface, nl::CanState};
-use std::time::Duration;
-use tokio::sync::mpsc;
+use nix::errno::Errno;
+use regex::Regex;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    signal::unix::{Signal, SignalKind, signal},
+    sync::{Semaphore, mpsc},
+};
+use tracing::{error, info, warn};
+
+/// How often missing interfaces are looked up again with --wait-for-interfaces
+const WAIT_FOR_INTERFACES_INTERVAL: Duration = Duration::from_millis(500);
 
 const VERSION: &str = git_version!(prefix = concat!(env!("CARGO_PKG_VERSION"), "-"));
 
+/// Compile-time features, and whether this build has each
+const FEATURES: &[(&str, bool)] = &[
+    ("systemd", cfg!(feature = "systemd")),
+    ("control", cfg!(feature = "control")),
+    ("api", cfg!(feature = "api")),
+    ("metrics", cfg!(feature = "metrics")),
+    ("otel", cfg!(feature = "otel")),
+    ("syslog", cfg!(feature = "syslog")),
+    ("testing", cfg!(feature = "testing")),
+];
+
+/// Shown at the end of `--help`, before the exit codes
+const HELP_FOOTER: &str =
+    "cansentinel always runs in the foreground; leave daemonizing to the service manager.";
+
+/// Exit code of clap's usage errors, which aren't an [`ExitReason`]
+const USAGE_EXIT_CODE: i32 = 2;
+
+/// Text shown at the end of `--help`, listing every [`ExitReason`]
+fn exit_codes_help() -> String {
+    let mut codes: Vec<_> = ExitReason::ALL
+        .iter()
+        .map(|reason| (*reason as i32, reason.description()))
+        .chain([(USAGE_EXIT_CODE, "Invalid command line arguments")])
+        .collect();
+    codes.sort_by_key(|(code, _)| *code);
+    let mut help = format!("{HELP_FOOTER}\n\nExit codes:");
+    for (code, description) in codes {
+        help.push_str(&format!("\n{code:>3}  {description}"));
+    }
+    help
+}
+
 #[derive(Parser)]
 #[command(name = "cansentinel")]
 #[command(version = VERSION)]
+#[command(after_help = exit_codes_help())]
 #[command(
     about = "cansentinel monitors CAN interface state changes and automatically restarts interfaces that enter the bus-off state"
 )]
 struct Args {
     /// CAN interface names to monitor (can be specified multiple times)
-    #[arg(short = 'i', long = "interface", action = clap::ArgAction::Append)]
+    ///
+    /// Glob patterns such as 'can*' match every CAN interface present at startup.
+    #[arg(
+        short = 'i',
+        long = "interface",
+        env = "CANSENTINEL_INTERFACES",
+        value_delimiter = ',',
+        action = clap::ArgAction::Append,
+        value_parser = parse_interface_name
+    )]
     interfaces: Vec<String>,
 
+    /// File of CAN interface names to monitor, one per line, in addition to those given with -i
+    ///
+    /// Blank lines and lines starting with '#' are ignored. Patterns work as with -i.
+    #[arg(long = "interface-file", env = "CANSENTINEL_INTERFACE_FILE")]
+    interface_file: Option<PathBuf>,
+
+    /// Monitor every CAN interface present at startup
+    #[arg(
+        long = "all",
+        env = "CANSENTINEL_ALL",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    all: bool,
+
+    /// Monitor every CAN interface present at startup whose name matches this regex
+    /// (can be specified multiple times), e.g. '^veh_can\d+$'
+    #[arg(
+        long = "interface-regex",
+        env = "CANSENTINEL_INTERFACE_REGEX",
+        action = clap::ArgAction::Append,
+        value_parser = parse_interface_regex
+    )]
+    interface_regex: Vec<Regex>,
+
+    /// Leave out interfaces whose name matches this regex when resolving patterns, --all and
+    /// --interface-regex (can be specified multiple times). Interfaces named exactly are kept.
+    #[arg(
+        long = "exclude-regex",
+        env = "CANSENTINEL_EXCLUDE_REGEX",
+        action = clap::ArgAction::Append,
+        value_parser = parse_interface_regex
+    )]
+    exclude_regex: Vec<Regex>,
+
+    /// Stable identities of CAN interfaces to monitor (can be specified multiple times)
+    ///
+    /// Matched against the sysfs path of the controller's parent device, e.g.
+    /// `platform/fe204000.can`, with an optional `#<dev_port>` suffix for multi-channel devices.
+    /// Resolved to the current interface name at startup.
+    #[arg(
+        long = "stable-id",
+        env = "CANSENTINEL_STABLE_IDS",
+        value_delimiter = ',',
+        action = clap::ArgAction::Append
+    )]
+    stable_ids: Vec<String>,
+
     /// Ignore invalid interface names instead of failing
-    #[arg(long = "ignore-invalid")]
+    #[arg(
+        long = "ignore-invalid",
+        env = "CANSENTINEL_IGNORE_INVALID",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
     ignore_invalid: bool,
 
-    /// Delay in milliseconds to wait before restarting interface
-    #[arg(short = 'd', long = "delay-ms", default_value = "1000")]
-    delay_ms: u64,
+    /// Wait up to this long (e.g. 30s) for named interfaces that don't exist yet to appear,
+    /// for interfaces that are created after cansentinel starts at boot
+    #[arg(
+        long = "wait-for-interfaces",
+        env = "CANSENTINEL_WAIT_FOR_INTERFACES",
+        value_parser = parse_duration
+    )]
+    wait_for_interfaces: Option<Duration>,
+
+    /// Accept interfaces that aren't CAN interfaces instead of rejecting them at startup
+    #[arg(
+        long = "allow-non-can",
+        env = "CANSENTINEL_ALLOW_NON_CAN",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    allow_non_can: bool,
+
+    /// Delay in milliseconds to wait before restarting interface [default: 1000]
+    ///
+    /// Overrides the global delay from --config; per-interface delays from the file still apply.
+    #[arg(short = 'd', long = "delay-ms", env = "CANSENTINEL_DELAY_MS")]
+    delay_ms: Option<u64>,
+
+    /// TOML configuration file with a global `delay_ms` and `[[interface]]` entries of
+    /// `{ name, delay_ms }`. Interfaces given with -i are monitored in addition.
+    #[arg(short = 'c', long = "config", env = "CANSENTINEL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Receive buffer size in bytes to request for error frame sockets (SO_RCVBUF)
+    ///
+    /// The kernel clamps this to net.core.rmem_max; raise that sysctl to allow larger buffers.
+    #[arg(long = "rx-buffer-bytes", env = "CANSENTINEL_RX_BUFFER_BYTES")]
+    rx_buffer_bytes: Option<usize>,
+
+    /// Disable CAN_RAW_LOOPBACK on error frame sockets, so frames sent by other local sockets are
+    /// never delivered to them
+    #[arg(
+        long = "no-loopback",
+        env = "CANSENTINEL_NO_LOOPBACK",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    no_loopback: bool,
+
+    /// Enable CAN_RAW_RECV_OWN_MSGS on error frame sockets (disabled by default)
+    #[arg(
+        long = "recv-own-msgs",
+        env = "CANSENTINEL_RECV_OWN_MSGS",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    recv_own_msgs: bool,
+
+    /// Maximum number of error frame sockets that may be (re)opened concurrently
+    #[arg(
+        long = "max-concurrent-opens",
+        env = "CANSENTINEL_MAX_CONCURRENT_OPENS"
+    )]
+    max_concurrent_opens: Option<usize>,
+
+    /// Time in milliseconds to wait before retrying when an error frame socket can't be opened
+    #[arg(
+        long = "socket-retry-ms",
+        env = "CANSENTINEL_SOCKET_RETRY_MS",
+        default_value = "5000"
+    )]
+    socket_retry_ms: u64,
+
+    /// Double the wait after every further failure to open an error frame socket, up to this
+    /// many milliseconds (a fixed --socket-retry-ms by default)
+    #[arg(long = "socket-retry-max-ms", env = "CANSENTINEL_SOCKET_RETRY_MAX_MS")]
+    socket_retry_max_ms: Option<u64>,
+
+    /// Most bus events kept waiting to be handled (0 for no limit)
+    #[arg(
+        long = "event-queue-size",
+        env = "CANSENTINEL_EVENT_QUEUE_SIZE",
+        default_value = "0"
+    )]
+    event_queue_size: usize,
+
+    /// Which error-threshold or counters-reported event to drop once --event-queue-size events
+    /// are waiting: drop-oldest or drop-newest. State transitions are never dropped
+    #[arg(
+        long = "event-queue-overflow",
+        env = "CANSENTINEL_EVENT_QUEUE_OVERFLOW",
+        default_value = "drop-oldest",
+        value_parser = str::parse::<OverflowPolicy>
+    )]
+    event_queue_overflow: OverflowPolicy,
+
+    /// Restart an interface that has stayed error-passive for this many milliseconds, as if it
+    /// had gone bus-off (0 disables)
+    #[arg(
+        long = "passive-restart-ms",
+        env = "CANSENTINEL_PASSIVE_RESTART_MS",
+        default_value = "0"
+    )]
+    passive_restart_ms: u64,
+
+    /// With --verbose, log at most one error frame in detail per this many milliseconds on each
+    /// interface and summarize the rest (0, the default, logs every frame)
+    #[arg(
+        long = "verbose-error-interval-ms",
+        env = "CANSENTINEL_VERBOSE_ERROR_INTERVAL_MS",
+        default_value = "0"
+    )]
+    verbose_error_interval_ms: u64,
+
+    /// Maximum number of restarts in progress at the same time, the rest wait their turn
+    /// (unlimited by default)
+    #[arg(
+        long = "max-concurrent-restarts",
+        env = "CANSENTINEL_MAX_CONCURRENT_RESTARTS"
+    )]
+    max_concurrent_restarts: Option<usize>,
+
+    /// Print the version, git revision and enabled features as JSON and exit
+    #[arg(long = "version-json")]
+    version_json: bool,
+
+    /// List every CAN interface present with its state, bitrate and restart-ms, and exit without
+    /// monitoring
+    #[arg(long = "list-interfaces", conflicts_with_all = ["once", "learn"])]
+    list_interfaces: bool,
+
+    /// Check every interface once, restart any that are bus-off, and exit without monitoring,
+    /// with an exit code telling whether all are healthy afterwards (see Exit codes below)
+    #[arg(long = "once", conflicts_with = "learn")]
+    once: bool,
+
+    /// Learn mode: record baseline error rates for the given duration (e.g. 10m) without
+    /// restarting anything, print a suggested threshold config and exit
+    #[arg(long = "learn", value_parser = parse_duration)]
+    learn: Option<Duration>,
+
+    /// Read per-interface restart delays from interface aliases (`cansentinel-delay=<ms>`)
+    #[arg(
+        long = "read-ifalias",
+        env = "CANSENTINEL_READ_IFALIAS",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    read_ifalias: bool,
+
+    /// Comma-separated interfaces that share power and recover together (can be specified
+    /// multiple times)
+    #[arg(
+        long = "restart-group",
+        env = "CANSENTINEL_RESTART_GROUPS",
+        value_delimiter = ';',
+        action = clap::ArgAction::Append
+    )]
+    restart_groups: Vec<String>,
+
+    /// Time in milliseconds after a restart completed during which events from every interface in
+    /// the same restart group are suppressed, as they are during the restart itself
+    #[arg(
+        long = "group-cooldown-ms",
+        env = "CANSENTINEL_GROUP_COOLDOWN_MS",
+        default_value = "0"
+    )]
+    group_cooldown_ms: u64,
+
+    /// Time in milliseconds within which bus-offs after a restart are collapsed into a single
+    /// recheck, and a restart or recovery followed by another bus-off is ignored as noise
+    /// (0 disables)
+    #[arg(
+        long = "debounce-ms",
+        env = "CANSENTINEL_DEBOUNCE_MS",
+        default_value = "0"
+    )]
+    debounce_ms: u64,
+
+    /// Time in milliseconds within which bus-offs of one interface reported by different
+    /// sources (netlink, error frames, polling) count as one detection (0 disables)
+    #[arg(
+        long = "coalesce-ms",
+        env = "CANSENTINEL_COALESCE_MS",
+        default_value = "0"
+    )]
+    coalesce_ms: u64,
+
+    /// Report an error_threshold event when error frames of this class repeat (no-ack,
+    /// controller-problem, protocol-violation, transceiver, lost-arbitration, bus-error or
+    /// tx-timeout; can be specified multiple times)
+    #[arg(
+        long = "error-threshold-class",
+        env = "CANSENTINEL_ERROR_THRESHOLD_CLASSES",
+        value_delimiter = ',',
+        action = clap::ArgAction::Append,
+        value_parser = str::parse::<ErrorClass>
+    )]
+    error_threshold_classes: Vec<ErrorClass>,
+
+    /// Error frames of one class within --error-threshold-window-ms that trigger an
+    /// error_threshold event
+    #[arg(
+        long = "error-threshold-count",
+        env = "CANSENTINEL_ERROR_THRESHOLD_COUNT",
+        default_value = "10"
+    )]
+    error_threshold_count: usize,
+
+    /// Window in milliseconds for --error-threshold-count
+    #[arg(
+        long = "error-threshold-window-ms",
+        env = "CANSENTINEL_ERROR_THRESHOLD_WINDOW_MS",
+        default_value = "1000"
+    )]
+    error_threshold_window_ms: u64,
+
+    /// Command to run when an interface goes bus-off
+    ///
+    /// Placeholders {interface}, {idx}, {event}, {timestamp} and {source} are substituted in
+    /// each argument. The command is executed directly, not through a shell, unless --hook-shell
+    /// is given.
+    #[arg(
+        long = "on-bus-off",
+        env = "CANSENTINEL_ON_BUS_OFF",
+        visible_alias = "on-busoff"
+    )]
+    on_bus_off: Option<String>,
+
+    /// Command to run whenever an interface reports that it was restarted, whether or not it was
+    /// seen going bus-off. Takes the same placeholders as --on-bus-off.
+    #[arg(long = "on-restart", env = "CANSENTINEL_ON_RESTART")]
+    on_restart: Option<String>,
+
+    /// Command to run when an interface recovers from bus-off
+    ///
+    /// Takes the same placeholders as --on-bus-off, plus {downtime_ms} for how long it was down.
+    #[arg(long = "on-recovered", env = "CANSENTINEL_ON_RECOVERED")]
+    on_recovered: Option<String>,
+
+    /// Command to run when an interface enters the error warning or error passive state or
+    /// reaches an error threshold, an early sign the bus is deteriorating. Takes the same
+    /// placeholders as --on-bus-off.
+    #[arg(long = "on-degraded", env = "CANSENTINEL_ON_DEGRADED")]
+    on_degraded: Option<String>,
+
+    /// Run hook commands through /bin/sh -c, with substituted values single-quoted
+    #[arg(
+        long = "hook-shell",
+        env = "CANSENTINEL_HOOK_SHELL",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    hook_shell: bool,
+
+    /// Log the interface's CAN parameters and link statistics at the moment it goes bus-off
+    #[arg(
+        long = "snapshot-on-bus-off",
+        env = "CANSENTINEL_SNAPSHOT_ON_BUS_OFF",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    snapshot_on_bus_off: bool,
+
+    /// Report a single system-wide fault when this many interfaces go bus-off within the
+    /// correlation window (0 disables)
+    #[arg(
+        long = "correlation-threshold",
+        env = "CANSENTINEL_CORRELATION_THRESHOLD",
+        default_value = "0"
+    )]
+    correlation_threshold: usize,
+
+    /// Window in milliseconds within which bus-offs on different interfaces are correlated
+    #[arg(
+        long = "correlation-window-ms",
+        env = "CANSENTINEL_CORRELATION_WINDOW_MS",
+        default_value = "500"
+    )]
+    correlation_window_ms: u64,
+
+    /// During a correlated fault, log only the aggregated incident and skip per-interface bus-off
+    /// logs and hooks
+    #[arg(
+        long = "aggregate-incidents",
+        env = "CANSENTINEL_AGGREGATE_INCIDENTS",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    aggregate_incidents: bool,
+
+    /// Lengthen restart delays for interfaces with many recent bus-offs and shorten them for
+    /// stable ones
+    #[arg(
+        long = "adaptive-delay",
+        env = "CANSENTINEL_ADAPTIVE_DELAY",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    adaptive_delay: bool,
+
+    /// Half-life of the adaptive delay bus-off score (e.g. 30m, 1h)
+    #[arg(
+        long = "adaptive-half-life",
+        env = "CANSENTINEL_ADAPTIVE_HALF_LIFE",
+        value_parser = parse_duration,
+        default_value = "1h"
+    )]
+    adaptive_half_life: Duration,
+
+    /// Largest factor the adaptive delay may apply to the configured restart delay
+    #[arg(
+        long = "adaptive-max-multiplier",
+        env = "CANSENTINEL_ADAPTIVE_MAX_MULTIPLIER",
+        default_value = "8"
+    )]
+    adaptive_max_multiplier: f64,
+
+    /// Double the restart delay on each consecutive restart of the same interface, up to this
+    /// many milliseconds
+    #[arg(long = "backoff-max-ms", env = "CANSENTINEL_BACKOFF_MAX_MS")]
+    backoff_max_ms: Option<u64>,
+
+    /// Add a random delay of up to this many milliseconds to every restart, so interfaces that
+    /// went bus-off together don't all restart at the same instant (0 disables)
+    #[arg(
+        long = "restart-jitter-ms",
+        env = "CANSENTINEL_RESTART_JITTER_MS",
+        default_value = "0"
+    )]
+    restart_jitter_ms: u64,
+
+    /// Time in milliseconds an interface must stay out of bus-off after a restart for the
+    /// restart to count as successful (0 disables)
+    #[arg(
+        long = "confirm-restart-ms",
+        env = "CANSENTINEL_CONFIRM_RESTART_MS",
+        default_value = "0"
+    )]
+    confirm_restart_ms: u64,
+
+    /// Minimum time in milliseconds between two restarts of the same interface (0 disables)
+    #[arg(
+        long = "min-restart-interval-ms",
+        env = "CANSENTINEL_MIN_RESTART_INTERVAL_MS",
+        default_value = "0"
+    )]
+    min_restart_interval_ms: u64,
+
+    /// Ignore bus-off and stopped events of an interface for this many milliseconds after
+    /// restarting it, while the controller settles (0 disables)
+    #[arg(
+        long = "mute-after-restart-ms",
+        env = "CANSENTINEL_MUTE_AFTER_RESTART_MS",
+        default_value = "0"
+    )]
+    mute_after_restart_ms: u64,
+
+    /// How bus-off interfaces are restarted: ioctl, the CAN restart request, or downup, taking
+    /// the interface down and back up. Overrides the global method from --config;
+    /// per-interface methods from the file still apply.
+    #[arg(
+        long = "restart-method",
+        env = "CANSENTINEL_RESTART_METHOD",
+        value_parser = str::parse::<RestartMethod>
+    )]
+    restart_method: Option<RestartMethod>,
+
+    /// Time in milliseconds a downup restart keeps the interface down before bringing it back up
+    #[arg(
+        long = "downup-settle-ms",
+        env = "CANSENTINEL_DOWNUP_SETTLE_MS",
+        default_value = "100"
+    )]
+    downup_settle_ms: u64,
+
+    /// Time in milliseconds an interface must stay error-active after a restart to reset its
+    /// backoff
+    #[arg(
+        long = "backoff-stable-ms",
+        env = "CANSENTINEL_BACKOFF_STABLE_MS",
+        default_value = "60000"
+    )]
+    backoff_stable_ms: u64,
+
+    /// Stop restarting an interface after this many restarts within --give-up-window-ms
+    #[arg(long = "give-up-after", env = "CANSENTINEL_GIVE_UP_AFTER")]
+    give_up_after: Option<usize>,
+
+    /// Window in milliseconds over which restarts are counted for --give-up-after
+    #[arg(
+        long = "give-up-window-ms",
+        env = "CANSENTINEL_GIVE_UP_WINDOW_MS",
+        default_value = "60000"
+    )]
+    give_up_window_ms: u64,
+
+    /// Resume restarts this many milliseconds after giving up (0 waits for SIGUSR1)
+    #[arg(
+        long = "give-up-cooldown-ms",
+        env = "CANSENTINEL_GIVE_UP_COOLDOWN_MS",
+        default_value = "600000"
+    )]
+    give_up_cooldown_ms: u64,
+
+    /// Number of recent events to keep in memory for inspection (0 disables)
+    #[arg(
+        long = "history-size",
+        env = "CANSENTINEL_HISTORY_SIZE",
+        default_value = "0"
+    )]
+    history_size: usize,
+
+    /// Number of recent events to keep in memory for each interface, for the control socket's
+    /// history command (0 disables)
+    #[arg(
+        long = "interface-history-size",
+        env = "CANSENTINEL_INTERFACE_HISTORY_SIZE",
+        default_value = "20"
+    )]
+    interface_history_size: usize,
+
+    /// Number of recent outages to keep in memory, with their downtime (0 disables)
+    #[arg(
+        long = "outage-history-size",
+        env = "CANSENTINEL_OUTAGE_HISTORY_SIZE",
+        default_value = "20"
+    )]
+    outage_history_size: usize,
+
+    /// Also poll interface states at this interval in milliseconds, for environments where
+    /// netlink notifications are unreliable (0 disables)
+    #[arg(
+        long = "poll-interval-ms",
+        env = "CANSENTINEL_POLL_INTERVAL_MS",
+        default_value = "0"
+    )]
+    poll_interval_ms: u64,
+
+    /// Poll interface states at this interval in milliseconds if netlink link notifications are
+    /// not permitted, e.g. in a restricted container (0 disables)
+    #[arg(
+        long = "poll-fallback-ms",
+        env = "CANSENTINEL_POLL_FALLBACK_MS",
+        default_value = "1000"
+    )]
+    poll_fallback_ms: u64,
+
+    /// Where interface states come from besides error frames: netlink, falling back to polling
+    /// if it isn't permitted, or sysfs only, polled every --poll-fallback-ms
+    #[arg(
+        long = "state-source",
+        env = "CANSENTINEL_STATE_SOURCE",
+        default_value = "netlink",
+        value_parser = str::parse::<StateSource>
+    )]
+    state_source: StateSource,
+
+    /// Warn when netlink or an error frame monitor hasn't shown signs of life for this many
+    /// milliseconds, as it may be hung (0 disables)
+    #[arg(
+        long = "liveness-timeout-ms",
+        env = "CANSENTINEL_LIVENESS_TIMEOUT_MS",
+        default_value = "10000"
+    )]
+    liveness_timeout_ms: u64,
+
+    /// Log a one-line health summary this often (e.g. 5m), starting with one at startup
+    ///
+    /// Under systemd the summary also becomes the unit's status text.
+    #[arg(
+        long = "heartbeat-interval",
+        env = "CANSENTINEL_HEARTBEAT_INTERVAL",
+        value_parser = parse_duration
+    )]
+    heartbeat_interval: Option<Duration>,
+
+    /// On SIGTERM or SIGINT, time in milliseconds to let pending restarts complete before exiting
+    #[arg(
+        long = "shutdown-grace-ms",
+        env = "CANSENTINEL_SHUTDOWN_GRACE_MS",
+        default_value = "5000"
+    )]
+    shutdown_grace_ms: u64,
+
+    /// Serve Prometheus metrics at http://<ADDR>/metrics
+    #[cfg(feature = "metrics")]
+    #[arg(
+        long = "metrics-addr",
+        env = "CANSENTINEL_METRICS_ADDR",
+        value_name = "ADDR"
+    )]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Export spans and metrics to the OpenTelemetry collector at this URL over OTLP/HTTP,
+    /// e.g. http://localhost:4318
+    #[cfg(feature = "otel")]
+    #[arg(
+        long = "otlp-endpoint",
+        env = "CANSENTINEL_OTLP_ENDPOINT",
+        value_name = "URL"
+    )]
+    otlp_endpoint: Option<String>,
+
+    /// Save restart counts and circuit breaker state to this JSON file every minute and on
+    /// shutdown, and carry on from it at startup
+    #[arg(
+        long = "state-file",
+        env = "CANSENTINEL_STATE_FILE",
+        value_name = "PATH"
+    )]
+    state_file: Option<PathBuf>,
+
+    /// Accept status, restart and cancel commands on a Unix socket at this path
+    #[cfg(feature = "control")]
+    #[arg(
+        long = "control-socket",
+        env = "CANSENTINEL_CONTROL_SOCKET",
+        value_name = "PATH"
+    )]
+    control_socket: Option<PathBuf>,
+
+    /// Serve the JSON status and restart API at http://<ADDR>/
+    #[cfg(feature = "api")]
+    #[arg(long = "api-addr", env = "CANSENTINEL_API_ADDR", value_name = "ADDR")]
+    api_addr: Option<std::net::SocketAddr>,
+
+    /// File holding the bearer token every API request must carry
+    #[cfg(feature = "api")]
+    #[arg(
+        long = "api-token-file",
+        env = "CANSENTINEL_API_TOKEN_FILE",
+        value_name = "PATH"
+    )]
+    api_token_file: Option<PathBuf>,
+
+    /// Event output format; json prints every event to stdout as a line of JSON, logs, the
+    /// SIGUSR2 status and the learn report go to stderr
+    #[arg(
+        long = "output",
+        env = "CANSENTINEL_OUTPUT",
+        value_enum,
+        default_value_t = OutputFormat::Text
+    )]
+    output: OutputFormat,
+
+    /// What to do about bus-offs: restart the interface, or only warn about it (log, count and
+    /// run hooks) without ever restarting
+    #[arg(
+        long = "action",
+        env = "CANSENTINEL_ACTION",
+        default_value = "restart",
+        value_parser = str::parse::<Action>
+    )]
+    action: Action,
+
+    /// Detect and log everything as usual, but only log the restarts that would be issued
+    #[arg(
+        long = "dry-run",
+        env = "CANSENTINEL_DRY_RUN",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    dry_run: bool,
+
+    /// Bring interfaces that are stopped (administratively down) back up, instead of leaving
+    /// them down
+    #[arg(
+        long = "restart-stopped",
+        env = "CANSENTINEL_RESTART_STOPPED",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    restart_stopped: bool,
+
+    /// Set the kernel's restart-ms to 0 on monitored interfaces, so cansentinel is the only one
+    /// restarting them. Takes each affected interface down and back up.
+    #[arg(
+        long = "disable-kernel-restart",
+        env = "CANSENTINEL_DISABLE_KERNEL_RESTART",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    disable_kernel_restart: bool,
+
+    /// Tokio runtime flavor; current-thread is plenty for a handful of interfaces
+    #[arg(
+        long = "runtime",
+        env = "CANSENTINEL_RUNTIME",
+        value_enum,
+        default_value_t = RuntimeFlavor::CurrentThread
+    )]
+    runtime: RuntimeFlavor,
+
+    /// Only print messages at this level or more severe (error, warn, info, debug).
+    /// Without this, -q or -v, RUST_LOG is honored, defaulting to info.
+    #[arg(long = "log-level", env = "CANSENTINEL_LOG_LEVEL")]
+    log_level: Option<Level>,
+
+    /// Where to send log messages: stderr, or syslog through /dev/log (requires the syslog
+    /// feature)
+    #[arg(
+        long = "log-target",
+        env = "CANSENTINEL_LOG_TARGET",
+        value_parser = str::parse::<Target>,
+        default_value = "stderr"
+    )]
+    log_target: Target,
+
+    /// Syslog facility to log under with --log-target syslog (user, daemon or local0-7)
+    #[cfg(feature = "syslog")]
+    #[arg(
+        long = "syslog-facility",
+        env = "CANSENTINEL_SYSLOG_FACILITY",
+        value_parser = str::parse::<log::Facility>,
+        default_value = "daemon"
+    )]
+    syslog_facility: log::Facility,
+
+    /// Only print warnings and errors, such as bus-off and restart failures.
+    /// Same as --log-level warn.
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        env = "CANSENTINEL_QUIET",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    quiet: bool,
 
     /// Enable more verbose output
-    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::SetTrue)]
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        env = "CANSENTINEL_VERBOSE",
+        value_parser = clap::builder::BoolishValueParser::new(),
+        action = clap::ArgAction::SetTrue
+    )]
     verbose: bool,
+
+    /// Don't color messages on a terminal. NO_COLOR is honored as well.
+    #[arg(
+        long = "no-color",
+        env = "CANSENTINEL_NO_COLOR",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    no_color: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+/// Format of event output on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable log messages only
+    Text,
+    /// Additionally print one JSON object per line for every event
+    Json,
+}
+
+impl OutputFormat {
+    /// Print human-readable text, on stderr if stdout carries JSON events
+    fn print(self, text: &str) {
+        match self {
+            Self::Text => print!("{text}"),
+            Self::Json => eprint!("{text}"),
+        }
+    }
+}
+
+/// Async runtime flavor to run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RuntimeFlavor {
+    /// Single-threaded runtime, lowest memory and thread footprint
+    CurrentThread,
+    /// Work-stealing runtime with a worker thread per core
+    MultiThread,
+}
+
+/// Reasons cansentinel exits, each with a distinct process exit code
+///
+/// What each means is given by [`ExitReason::description`], which `--help` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitReason {
+    CleanShutdown = 0,
+    InterfaceResolution = 3,
+    PermissionDenied = 4,
+    MonitoringFailed = 5,
+    InvalidConfig = 6,
+    MetricsUnavailable = 7,
+    Unhealthy = 8,
+    ControlUnavailable = 9,
+    /// Kept apart from clap's usage errors
+    NoInterfaces = 10,
+}
+
+impl ExitReason {
+    const ALL: [Self; 9] = [
+        Self::CleanShutdown,
+        Self::InterfaceResolution,
+        Self::PermissionDenied,
+        Self::MonitoringFailed,
+        Self::InvalidConfig,
+        Self::MetricsUnavailable,
+        Self::Unhealthy,
+        Self::ControlUnavailable,
+        Self::NoInterfaces,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::CleanShutdown => {
+                "Clean shutdown, e.g. --learn finished or --once found every interface healthy"
+            }
+            Self::InterfaceResolution => {
+                "Interface resolution failed, no valid interfaces left to monitor, or \
+                 --list-interfaces couldn't enumerate them"
+            }
+            Self::PermissionDenied => {
+                "Permission denied (CAP_NET_ADMIN is required to restart interfaces)"
+            }
+            Self::MonitoringFailed => "All monitoring tasks stopped unexpectedly",
+            Self::InvalidConfig => "The configuration file could not be loaded",
+            Self::MetricsUnavailable => {
+                "The metrics endpoint or OpenTelemetry export could not be started"
+            }
+            Self::Unhealthy => "With --once, an interface was still unhealthy after restarting it",
+            Self::ControlUnavailable => "The control socket or HTTP API could not be created",
+            Self::NoInterfaces => "No interfaces specified",
+        }
+    }
+
+    fn exit(self) -> ! {
+        #[cfg(feature = "otel")]
+        cansentinel::otel::shutdown();
+        std::process::exit(self as i32)
+    }
+}
+
+/// Install a signal handler, warning if that isn't possible
+fn install_signal(kind: SignalKind, name: &str) -> Option<Signal> {
+    match signal(kind) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            warn!("Failed to install {} handler: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Wait for a signal, or forever if its handler couldn't be installed
+async fn recv_signal(signal: &mut Option<Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a control socket or API request, or forever if there is no such listener
+async fn recv_request(
+    requests: &mut Option<mpsc::Receiver<ControlRequest>>,
+) -> Option<ControlRequest> {
+    match requests {
+        Some(requests) => requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the next tick, or forever if there is no interval
+async fn tick(interval: &mut Option<tokio::time::Interval>) -> Option<()> {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+            Some(())
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Watchdog period requested by systemd (`WatchdogSec=`), if any
+#[cfg(feature = "systemd")]
+fn watchdog_period() -> Option<Duration> {
+    libsystemd::daemon::watchdog_enabled(false)
+}
+
+#[cfg(not(feature = "systemd"))]
+fn watchdog_period() -> Option<Duration> {
+    None
+}
+
+/// Send a watchdog keepalive to systemd
+#[cfg(feature = "systemd")]
+fn notify_watchdog() {
+    use libsystemd::daemon::{NotifyState, notify};
+    if let Err(e) = notify(false, &[NotifyState::Watchdog]) {
+        warn!("Failed to notify systemd watchdog: {}", e);
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+fn notify_watchdog() {}
+
+/// Set the status text systemd shows for the unit
+#[cfg(feature = "systemd")]
+fn notify_status(status: &str) {
+    use libsystemd::daemon::{NotifyState, notify};
+    if let Err(e) = notify(false, &[NotifyState::Status(status.to_string())]) {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+fn notify_status(_status: &str) {}
+
+/// Bit of CAP_NET_ADMIN in the capability sets from `/proc/self/status`
+const CAP_NET_ADMIN: u32 = 12;
+
+/// Whether `args` may restart interfaces, which needs CAP_NET_ADMIN
+///
+/// Warn-only, dry-run and learn modes never touch an interface.
+fn needs_net_admin(args: &Args) -> bool {
+    args.action == Action::Restart && !args.dry_run && args.learn.is_none()
+}
+
+/// Check whether the process can restart interfaces
+///
+/// Assumes it can if the effective capability set can't be read.
+fn has_net_admin() -> bool {
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return true;
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_none_or(|caps| caps & (1 << CAP_NET_ADMIN) != 0)
+}
+
+/// Print the version for inventory tools, e.g.
+/// `{"version":"0.1.0","git":"v0.1.0-3-gabcdef0","features":["systemd"]}`
+fn print_version_json() {
+    let features: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    println!(
+        "{}",
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "git": git_version!(),
+            "features": features,
+        })
+    );
+}
+
+/// Print a table of every CAN interface present, for `--list-interfaces`
+///
+/// Fails if the interfaces can't be enumerated over netlink.
+fn list_interfaces() -> Result<(), ExitReason> {
+    let snapshots = match InterfaceSnapshot::capture_all() {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            error!("Failed to enumerate CAN interfaces over netlink: {}", e);
+            return Err(ExitReason::InterfaceResolution);
+        }
+    };
+    if snapshots.is_empty() {
+        println!("No CAN interfaces found");
+        return Ok(());
+    }
+    print!("{}", interface_table(&snapshots));
+    Ok(())
+}
+
+fn main() {
+    let mut args = Args::parse();
+
+    if args.version_json {
+        print_version_json();
+        return;
+    }
+
+    let level = log::level(args.log_level, args.quiet, args.verbose);
+    #[cfg(feature = "syslog")]
+    if let Target::Syslog(facility) = &mut args.log_target {
+        *facility = args.syslog_facility;
+    }
+    // Exporting runs on threads of its own, which have to be started outside the runtime
+    #[cfg(feature = "otel")]
+    let (otel_layer, otel_error) = match args.otlp_endpoint.as_deref().map(cansentinel::otel::init)
+    {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel_layer = None;
+    log::init_with(level, args.log_target, !args.no_color, otel_layer);
+    #[cfg(feature = "otel")]
+    if let Some(e) = otel_error {
+        error!("Failed to start OpenTelemetry export: {}", e);
+        ExitReason::MetricsUnavailable.exit();
+    }
+    // Verbose-only diagnostics are debug level, so follow whatever level ended up enabled
+    args.verbose = log::enabled(Level::Debug);
+
+    if args.list_interfaces {
+        match list_interfaces() {
+            Ok(()) => ExitReason::CleanShutdown.exit(),
+            Err(reason) => reason.exit(),
+        }
+    }
+
+    let mut builder = match args.runtime {
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+        RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+    };
+    let runtime = match builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to start async runtime: {}", e);
+            ExitReason::MonitoringFailed.exit();
+        }
+    };
+
+    match runtime.block_on(run(args)) {
+        Ok(()) => ExitReason::CleanShutdown.exit(),
+        Err(reason) => reason.exit(),
+    }
+}
 
-    // Configure interfaces to monitor
-    let config = Config::new(Duration::from_millis(args.delay_ms), args.interfaces);
+/// Build the configuration from the config file, the interface file and the command line
+///
+/// Fails with the file that couldn't be loaded.
+fn load_config(args: &Args) -> Result<Config, (&Path, ConfigError)> {
+    // Command line options take precedence over the file
+    let mut config = match &args.config {
+        Some(path) => Config::from_file(path).map_err(|e| (path.as_path(), e))?,
+        None => Config::new(DEFAULT_RESTART_DELAY, Vec::new()),
+    };
+    if let Some(delay_ms) = args.delay_ms {
+        config.restart_delay = Duration::from_millis(delay_ms);
+    }
+    if let Some(method) = args.restart_method {
+        config.restart_method = method;
+    }
+    for name in &args.interfaces {
+        config.add_interface(name);
+    }
+    if let Some(path) = &args.interface_file {
+        let names = read_interface_file(path).map_err(|e| (path.as_path(), e))?;
+        for name in names {
+            config.add_interface(&name);
+        }
+    }
+    Ok(config)
+}
 
-    if config.interface_names.is_empty() {
-        println!("No interfaces specified. Use -i/--interface to specify interfaces to monitor.");
-        std::process::exit(1);
+/// Wait until every interface named in `config` exists, or `timeout` has passed
+///
+/// Patterns aren't waited for, as there is no telling how many interfaces they should match.
+/// Returns whether all of them appeared.
+async fn wait_for_interfaces(config: &Config, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut missing: Vec<&String> = config
+        .interface_names
+        .iter()
+        .filter(|name| !is_glob(name))
+        .collect();
+    let mut waiting = false;
+    loop {
+        missing.retain(|name| CanInterfaceInfo::lookup(name).is_err());
+        if missing.is_empty() {
+            if waiting {
+                info!("All interfaces are present");
+            }
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            warn!(
+                "Gave up waiting for {:?} to appear after {:?}",
+                missing, timeout
+            );
+            return false;
+        }
+        if !waiting {
+            info!("Waiting up to {:?} for {:?} to appear", timeout, missing);
+            waiting = true;
+        }
+        tokio::time::sleep(WAIT_FOR_INTERFACES_INTERVAL).await;
     }
+}
+
+/// Interfaces whose name matches none of `exclude`, logging the others
+fn without_excluded(interfaces: Vec<CanInterfaceInfo>, exclude: &[Regex]) -> Vec<CanInterfaceInfo> {
+    interfaces
+        .into_iter()
+        .filter(|interface| {
+            let Some(regex) = exclude.iter().find(|regex| regex.is_match(&interface.name)) else {
+                return true;
+            };
+            info!("Excluding {}, which matches '{}'", interface.name, regex);
+            false
+        })
+        .collect()
+}
+
+/// Interfaces whose name matches `regex`
+fn matching_regex<'a>(
+    interfaces: &'a [CanInterfaceInfo],
+    regex: &Regex,
+) -> Vec<&'a CanInterfaceInfo> {
+    interfaces
+        .iter()
+        .filter(|interface| regex.is_match(&interface.name))
+        .collect()
+}
+
+/// Resolve the interfaces named in `config`, patterns, `--all`, regexes and stable ids
+///
+/// Failures are logged, and returned as the reason to exit with.
+fn resolve_interfaces(args: &Args, config: &Config) -> Result<Vec<CanInterfaceInfo>, ExitReason> {
+    // Patterns, --all and regexes are resolved against the CAN interfaces present right now
+    let discovered = if args.all
+        || !args.interface_regex.is_empty()
+        || config.interface_names.iter().any(|name| is_glob(name))
+    {
+        match CanInterfaceInfo::enumerate() {
+            Ok(discovered) => discovered,
+            Err(e) => {
+                error!("Failed to list CAN interfaces: {}", e);
+                return Err(ExitReason::InterfaceResolution);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let discovered = without_excluded(discovered, &args.exclude_regex);
 
     let mut interfaces: Vec<CanInterfaceInfo> = Vec::with_capacity(config.interface_names.len());
     let mut got_error = false;
+    let mut permission_error = false;
     for name in &config.interface_names {
-        match CanInterfaceInfo::new(name) {
-            Ok(interface) => interfaces.push(interface),
+        if is_glob(name) {
+            let matched: Vec<_> = discovered
+                .iter()
+                .filter(|interface| glob_match(name, &interface.name))
+                .collect();
+            if matched.is_empty() {
+                if args.ignore_invalid {
+                    warn!("No CAN interfaces match '{}'. Ignoring.", name);
+                } else {
+                    error!("No CAN interfaces match '{}'", name);
+                    got_error = true;
+                }
+            }
+            for interface in matched {
+                if !interfaces.contains(interface) {
+                    interfaces.push(interface.clone());
+                }
+            }
+            continue;
+        }
+
+        let resolved = if args.allow_non_can {
+            CanInterfaceInfo::lookup(name)
+        } else {
+            CanInterfaceInfo::new(name)
+        };
+        match resolved {
+            Ok(interface) => push_unique(&mut interfaces, interface, name),
             Err(e) => {
                 if args.ignore_invalid {
-                    println!("Could not find interface '{}': {}. Ignoring.", name, e);
+                    warn!("Could not find interface '{}': {}. Ignoring.", name, e);
                 } else {
-                    println!("Could not find interface '{}': {}", name, e);
+                    error!("Could not find interface '{}': {}", name, e);
                     got_error = true;
+                    permission_error |=
+                        matches!(e, InterfaceError::Lookup(Errno::EPERM | Errno::EACCES));
                 }
             }
         }
     }
 
+    for regex in &args.interface_regex {
+        let matched = matching_regex(&discovered, regex);
+        if matched.is_empty() {
+            if args.ignore_invalid {
+                warn!("No CAN interfaces match regex '{}'. Ignoring.", regex);
+            } else {
+                error!("No CAN interfaces match regex '{}'", regex);
+                got_error = true;
+            }
+        } else {
+            info!(
+                "Regex '{}' matched {:?}",
+                regex,
+                matched.iter().map(|i| &i.name).collect::<Vec<_>>()
+            );
+        }
+        for interface in matched {
+            if !interfaces.contains(interface) {
+                interfaces.push(interface.clone());
+            }
+        }
+    }
+
+    if args.all {
+        for interface in &discovered {
+            if !interfaces.contains(interface) {
+                interfaces.push(interface.clone());
+            }
+        }
+    }
+
+    for stable_id in &args.stable_ids {
+        match CanInterfaceInfo::from_stable_id(stable_id) {
+            Ok(interface) => {
+                info!(
+                    "Stable id '{}' resolved to {} (idx={})",
+                    stable_id, interface.name, interface.idx
+                );
+                push_unique(&mut interfaces, interface, stable_id);
+            }
+            Err(e) => {
+                if args.ignore_invalid {
+                    warn!(
+                        "Could not resolve stable id '{}': {}. Ignoring.",
+                        stable_id, e
+                    );
+                } else {
+                    error!("Could not resolve stable id '{}': {}", stable_id, e);
+                    got_error = true;
+                    permission_error |=
+                        matches!(e, InterfaceError::Lookup(Errno::EPERM | Errno::EACCES));
+                }
+            }
+        }
+    }
+
+    if permission_error {
+        error!("{}", PERMISSION_HINT);
+        return Err(ExitReason::PermissionDenied);
+    }
+
     if got_error {
-        std::process::exit(1);
+        return Err(ExitReason::InterfaceResolution);
     }
 
     if interfaces.is_empty() {
-        println!("No valid interfaces found to monitor.");
-        std::process::exit(1);
+        error!("No valid interfaces found to monitor.");
+        return Err(ExitReason::InterfaceResolution);
     }
 
-    println!("Starting cansentinel {VERSION}");
-    println!("Restart delay: {:?}", config.restart_delay);
-    println!("Monitoring interfaces: {:?}", config.interface_names);
+    Ok(interfaces)
+}
+
+/// Add an interface requested as `requested`, unless it is already monitored
+///
+/// Compares indexes, since alternative names and stable ids resolve to the same interface under
+/// another name.
+fn push_unique(
+    interfaces: &mut Vec<CanInterfaceInfo>,
+    interface: CanInterfaceInfo,
+    requested: &str,
+) {
+    if let Some(existing) = interfaces.iter().find(|i| i.idx == interface.idx) {
+        warn!(
+            "'{}' is {} (idx={}), which is already monitored. Ignoring the duplicate.",
+            requested, existing.name, existing.idx
+        );
+        return;
+    }
+    interfaces.push(interface);
+}
+
+/// Take restart delays from interface aliases, if enabled
+fn apply_alias_delays(args: &Args, config: &mut Config, interfaces: &[CanInterfaceInfo]) {
+    if !args.read_ifalias {
+        return;
+    }
+    for interface in interfaces {
+        if let Some(delay) = interface.alias_restart_delay() {
+            info!(
+                "{}: using restart delay {:?} from interface alias",
+                interface.name, delay
+            );
+            config
+                .interface_delays
+                .insert(interface.name.clone(), delay);
+        }
+    }
+}
 
-    let restart_manager = RestartManager::new();
+/// Warn about interfaces the kernel restarts on its own, or stop it with --disable-kernel-restart
+///
+/// Kernel restarts race with cansentinel's, producing restart and stopped events out of order.
+fn check_kernel_restart(args: &Args, interfaces: &[CanInterfaceInfo]) {
+    let backend = SocketCanBackend;
+    for interface in interfaces {
+        let restart_ms = match backend.restart_ms(interface.idx) {
+            Ok(Some(restart_ms)) if restart_ms > 0 => restart_ms,
+            _ => continue,
+        };
+        if !args.disable_kernel_restart {
+            warn!(
+                "{}: the kernel restarts this interface on its own after {} ms (restart-ms), which races with cansentinel. Set restart-ms to 0, pass --disable-kernel-restart or stop monitoring it.",
+                interface.name, restart_ms
+            );
+            continue;
+        }
+        if args.dry_run {
+            info!(
+                "{}: dry run, would disable kernel automatic restart (restart-ms is {})",
+                interface.name, restart_ms
+            );
+            continue;
+        }
+        let settings = LinkSettings {
+            bitrate: None,
+            restart_ms: Some(0),
+        };
+        match backend.reconfigure(interface.idx, settings) {
+            Ok(()) => info!(
+                "{}: disabled kernel automatic restart (restart-ms was {})",
+                interface.name, restart_ms
+            ),
+            Err(e) => error!(
+                "{}: failed to disable kernel automatic restart: {}",
+                interface.name, e
+            ),
+        }
+    }
+}
 
-    for interface in &interfaces {
-        // Check initial interface status and restart if already in bus-off state
-        if let Ok(Some(CanState::BusOff)) = CanInterface::open_iface(interface.idx).state() {
-            println!(
-                "{}: already in bus-off state, restarting immediately",
-                interface.name
+/// Re-read the configuration file and apply it to the running monitor
+///
+/// The running configuration is kept if the file or its interfaces can't be loaded.
+async fn reload(args: &Args, monitor: &mut Monitor) {
+    let Some(path) = &args.config else {
+        warn!("Received SIGHUP, but there is no config file to reload");
+        return;
+    };
+    info!("Received SIGHUP, reloading {}", path.display());
+
+    let mut config = match load_config(args) {
+        Ok(config) => config,
+        Err((path, e)) => {
+            error!(
+                "Failed to reload {}: {}. Keeping the current configuration.",
+                path.display(),
+                e
             );
-            restart_manager
-                .schedule_restart(interface.clone(), Duration::from_millis(0))
-                .await;
+            return;
+        }
+    };
+    let Ok(interfaces) = resolve_interfaces(args, &config) else {
+        error!("Failed to reload interfaces. Keeping the current configuration.");
+        return;
+    };
+    apply_alias_delays(args, &mut config, &interfaces);
+
+    info!("Restart delay: {:?}", config.restart_delay);
+    let changes = monitor.reload(config, interfaces).await;
+    check_kernel_restart(args, &changes.added);
+    if changes.is_empty() {
+        info!("Monitored interfaces unchanged");
+    } else {
+        info!(
+            "Monitoring added for {:?}, removed for {:?}, renamed to {:?}",
+            changes.added.iter().map(|i| &i.name).collect::<Vec<_>>(),
+            changes.removed.iter().map(|i| &i.name).collect::<Vec<_>>(),
+            changes.renamed.iter().map(|i| &i.name).collect::<Vec<_>>()
+        );
+    }
+}
+
+/// Monitor until shut down, failing with the reason to exit with
+async fn run(args: Args) -> Result<(), ExitReason> {
+    let mut config = match load_config(&args) {
+        Ok(config) => config,
+        Err((path, e)) => {
+            error!("Failed to load {}: {}", path.display(), e);
+            return Err(ExitReason::InvalidConfig);
         }
+    };
+
+    if config.interface_names.is_empty()
+        && args.stable_ids.is_empty()
+        && args.interface_regex.is_empty()
+        && !args.all
+    {
+        error!(
+            "No interfaces specified. Use -i/--interface, --interface-file, --all, --interface-regex, --stable-id or --config to specify interfaces to monitor."
+        );
+        return Err(ExitReason::NoInterfaces);
+    }
+
+    if needs_net_admin(&args) && !has_net_admin() {
+        error!("{}", PERMISSION_HINT);
+        return Err(ExitReason::PermissionDenied);
     }
 
+    if let Some(timeout) = args.wait_for_interfaces {
+        // Whatever is still missing is reported when resolving
+        wait_for_interfaces(&config, timeout).await;
+    }
+
+    let interfaces = resolve_interfaces(&args, &config)?;
+    apply_alias_delays(&args, &mut config, &interfaces);
+    // Learn mode only observes, restarting would change the error rates it records
+    let action = if args.learn.is_some() {
+        Action::Warn
+    } else {
+        args.action
+    };
+    if args.learn.is_some() {
+        info!("Learn mode, bus-offs will be recorded but never restarted");
+    } else if action == Action::Warn {
+        // Nothing of ours races with the kernel's restarts
+        warn!("Warn-only mode, bus-offs will be reported but never restarted");
+    } else {
+        check_kernel_restart(&args, &interfaces);
+    }
+    if args.dry_run {
+        warn!("Dry run, restarts will only be logged");
+    }
+
+    if args.once {
+        if check_once(
+            &interfaces,
+            Arc::new(SocketCanBackend),
+            action,
+            args.dry_run,
+        )
+        .await
+        {
+            return Ok(());
+        }
+        return Err(ExitReason::Unhealthy);
+    }
+
+    info!("Starting cansentinel {VERSION}");
+    info!("Restart delay: {:?}", config.restart_delay);
+    info!(
+        "Monitoring interfaces: {:?}",
+        interfaces.iter().map(|i| &i.name).collect::<Vec<_>>()
+    );
+
+    let restart_groups = args
+        .restart_groups
+        .iter()
+        .map(|group| {
+            group
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .filter_map(|name| {
+                    let member = interfaces.iter().find(|i| i.name == name).cloned();
+                    if member.is_none() {
+                        warn!("Restart group member '{}' is not monitored, ignoring", name);
+                    }
+                    member
+                })
+                .collect()
+        })
+        .collect();
+
+    let recorder = args.learn.map(|_| {
+        let recorder = LearnRecorder::new();
+        for interface in &interfaces {
+            recorder.track(interface);
+        }
+        recorder
+    });
+
+    #[cfg(feature = "metrics")]
+    let metrics = match args.metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(cansentinel::Metrics::new());
+            if let Err(e) = Arc::clone(&metrics).serve(addr).await {
+                error!("Failed to serve metrics on {}: {}", addr, e);
+                return Err(ExitReason::MetricsUnavailable);
+            }
+            Some(metrics)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "metrics"))]
+    let metrics = None;
+    #[cfg(feature = "otel")]
+    let metrics = match &args.otlp_endpoint {
+        Some(_) => {
+            let metrics = metrics.unwrap_or_else(|| Arc::new(cansentinel::Metrics::new()));
+            cansentinel::otel::export_metrics(Arc::clone(&metrics));
+            Some(metrics)
+        }
+        None => metrics,
+    };
+
+    let options = MonitorOptions {
+        error_monitor: ErrorMonitorOptions {
+            verbose: args.verbose,
+            verbose_interval: Duration::from_millis(args.verbose_error_interval_ms),
+            rx_buffer_bytes: args.rx_buffer_bytes,
+            open_limiter: args
+                .max_concurrent_opens
+                .map(|limit| Arc::new(Semaphore::new(limit.max(1)))),
+            recorder: recorder.clone(),
+            loopback: args.no_loopback.then_some(false),
+            recv_own_msgs: Some(args.recv_own_msgs),
+            threshold: (!args.error_threshold_classes.is_empty()).then(|| ErrorThreshold {
+                classes: args.error_threshold_classes.clone(),
+                count: args.error_threshold_count,
+                window: Duration::from_millis(args.error_threshold_window_ms),
+            }),
+            retry: match args.socket_retry_max_ms {
+                Some(max_ms) => RetryBackoff::Exponential {
+                    initial: Duration::from_millis(args.socket_retry_ms),
+                    max: Duration::from_millis(max_ms),
+                },
+                None => RetryBackoff::Fixed(Duration::from_millis(args.socket_retry_ms)),
+            },
+            // Created by the monitor, which reports stalls
+            liveness: None,
+            passive_restart_after: (args.passive_restart_ms > 0)
+                .then(|| Duration::from_millis(args.passive_restart_ms)),
+        },
+        restart_groups,
+        group_cooldown: Duration::from_millis(args.group_cooldown_ms),
+        debounce: Duration::from_millis(args.debounce_ms),
+        coalesce_window: Duration::from_millis(args.coalesce_ms),
+        hooks: Hooks {
+            on_bus_off: args.on_bus_off.clone(),
+            on_restart: args.on_restart.clone(),
+            on_recovered: args.on_recovered.clone(),
+            on_degraded: args.on_degraded.clone(),
+            shell: args.hook_shell,
+        },
+        snapshot_on_bus_off: args.snapshot_on_bus_off,
+        correlation_threshold: args.correlation_threshold,
+        correlation_window: Duration::from_millis(args.correlation_window_ms),
+        aggregate_incidents: args.aggregate_incidents,
+        adaptive_delay: args.adaptive_delay.then(|| AdaptiveDelay {
+            half_life: args.adaptive_half_life,
+            max_multiplier: args.adaptive_max_multiplier,
+            ..Default::default()
+        }),
+        jitter: (args.restart_jitter_ms > 0).then(|| Jitter {
+            spread: Duration::from_millis(args.restart_jitter_ms),
+            seed: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+        }),
+        confirm_window: (args.confirm_restart_ms > 0)
+            .then(|| Duration::from_millis(args.confirm_restart_ms)),
+        settle_time: Some(Duration::from_millis(args.downup_settle_ms)),
+        queue_limit: (args.event_queue_size > 0).then_some(QueueLimit {
+            capacity: args.event_queue_size,
+            overflow: args.event_queue_overflow,
+        }),
+        min_restart_interval: (args.min_restart_interval_ms > 0)
+            .then(|| Duration::from_millis(args.min_restart_interval_ms)),
+        mute_after_restart: (args.mute_after_restart_ms > 0)
+            .then(|| Duration::from_millis(args.mute_after_restart_ms)),
+        backoff: args.backoff_max_ms.map(|max_ms| Backoff {
+            max_delay: Duration::from_millis(max_ms),
+            stable_after: Duration::from_millis(args.backoff_stable_ms),
+        }),
+        circuit_breaker: args.give_up_after.map(|max_restarts| CircuitBreaker {
+            max_restarts,
+            window: Duration::from_millis(args.give_up_window_ms),
+            cooldown: (args.give_up_cooldown_ms > 0)
+                .then(|| Duration::from_millis(args.give_up_cooldown_ms)),
+        }),
+        restart_stopped: args.restart_stopped,
+        action,
+        dry_run: args.dry_run,
+        max_concurrent_restarts: args.max_concurrent_restarts,
+        history_size: args.history_size,
+        interface_history_size: args.interface_history_size,
+        outage_history_size: args.outage_history_size,
+        json_events: args.output == OutputFormat::Json,
+        poll_interval: (args.poll_interval_ms > 0)
+            .then(|| Duration::from_millis(args.poll_interval_ms)),
+        liveness_timeout: (args.liveness_timeout_ms > 0)
+            .then(|| Duration::from_millis(args.liveness_timeout_ms)),
+        poll_fallback: (args.poll_fallback_ms > 0)
+            .then(|| Duration::from_millis(args.poll_fallback_ms)),
+        state_source: args.state_source,
+        metrics,
+        control: None,
+        state_file: args.state_file.clone(),
+    };
+
+    let mut monitor = Monitor::start(config, interfaces, options).await;
+
     #[cfg(feature = "systemd")]
     {
         // This is where we are considered ready, for the purposes of service ordering
@@ -102,57 +1637,272 @@ async fn main() {
             false,
             &[
                 NotifyState::Ready,
-                NotifyState::Status(format!("Monitoring {} CAN interfaces...", interfaces.len(),)),
+                NotifyState::Status(format!(
+                    "Monitoring {} CAN interfaces...",
+                    monitor.interfaces().len(),
+                )),
             ],
         ) {
             Ok(_) => (), // If this returns false, systemd isn't available and we don't care.
-            Err(e) => println!("Failed to notify systemd: {}", e),
+            Err(e) => warn!("Failed to notify systemd: {}", e),
         }
     }
 
-    // Create a unified channel for bus-off detection from both sources
-    let (tx, mut rx) = mpsc::unbounded_channel::<BusEvent>();
+    if let Some(duration) = args.learn {
+        info!(
+            "Learn mode: collecting baseline error rates for {:?}",
+            duration
+        );
+    }
 
-    let netlink_handle = {
-        // Start netlink monitoring
-        let netlink_tx = tx.clone();
-        let netlink_interfaces = interfaces.clone();
-        tokio::task::spawn_blocking(move || {
-            monitor_netlink(netlink_tx, netlink_interfaces, args.verbose);
-        })
+    #[cfg(feature = "control")]
+    let mut control_requests = match &args.control_socket {
+        Some(path) => match cansentinel::control::listen(path) {
+            Ok((_, requests)) => Some(requests),
+            Err(e) => {
+                error!(
+                    "Failed to listen on control socket {}: {}",
+                    path.display(),
+                    e
+                );
+                return Err(ExitReason::ControlUnavailable);
+            }
+        },
+        None => None,
     };
+    #[cfg(not(feature = "control"))]
+    let mut control_requests = None;
 
-    // Start CAN error frame monitoring for each interface
-    let error_handles = {
-        let mut handles = Vec::with_capacity(interfaces.capacity());
-        for interface in &interfaces {
-            let interface = interface.clone();
-            let error_tx = tx.clone();
-            let handle = tokio::spawn(async move {
-                monitor_interface_errors(error_tx, interface, args.verbose).await;
-            });
-            handles.push(handle);
+    #[cfg(feature = "api")]
+    let (api_task, mut api_requests) = match args.api_addr {
+        Some(addr) => {
+            let token = match &args.api_token_file {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(token) => Some(token.trim().to_string()),
+                    Err(e) => {
+                        error!("Failed to read API token from {}: {}", path.display(), e);
+                        return Err(ExitReason::ControlUnavailable);
+                    }
+                },
+                None => None,
+            };
+            match cansentinel::api::serve(addr, token).await {
+                Ok((task, requests)) => (Some(task), Some(requests)),
+                Err(e) => {
+                    error!("Failed to serve the HTTP API on {}: {}", addr, e);
+                    return Err(ExitReason::ControlUnavailable);
+                }
+            }
         }
-        handles
+        None => (None, None),
     };
+    #[cfg(not(feature = "api"))]
+    let (api_task, mut api_requests) = (None::<tokio::task::JoinHandle<()>>, None);
+
+    let learn_deadline = args.learn.map(|d| tokio::time::Instant::now() + d);
+    let mut result = Ok(());
+
+    // SIGHUP reloads the config file, SIGUSR1 resumes restarts after giving up, SIGUSR2 prints a
+    // one-line status summary, SIGTERM and SIGINT shut down gracefully
+    let mut reload_signal = install_signal(SignalKind::hangup(), "SIGHUP");
+    let mut resume_signal = install_signal(SignalKind::user_defined1(), "SIGUSR1");
+    let mut status_signal = install_signal(SignalKind::user_defined2(), "SIGUSR2");
+    let mut terminate_signal = install_signal(SignalKind::terminate(), "SIGTERM");
+    let mut interrupt_signal = install_signal(SignalKind::interrupt(), "SIGINT");
 
-    // Main event loop - handle bus-off events from both sources
-    while let Some(event) = rx.recv().await {
-        match event.event_type {
-            BusEventType::BusOff => {
-                restart_manager
-                    .schedule_restart(event.interface, config.restart_delay)
-                    .await;
+    // Keepalives are withheld while monitoring is unresponsive, so a hang trips the watchdog
+    let watchdog = watchdog_period();
+    let mut watchdog_ticker = watchdog.map(|period| tokio::time::interval(period / 2));
+    // The first tick is immediate, which logs the startup summary
+    let mut heartbeat_ticker = args
+        .heartbeat_interval
+        .filter(|interval| !interval.is_zero())
+        .map(tokio::time::interval);
+
+    loop {
+        tokio::select! {
+            _ = monitor.wait() => {
+                error!("All monitoring tasks stopped");
+                result = Err(ExitReason::MonitoringFailed);
+                break;
+            }
+            _ = async {
+                match learn_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            } => break,
+            Some(_) = tick(&mut watchdog_ticker) => {
+                let period = watchdog.unwrap_or_default();
+                if monitor.is_alive(period) {
+                    notify_watchdog();
+                } else {
+                    warn!("Monitoring unresponsive, withholding watchdog keepalive");
+                }
+            }
+            Some(_) = tick(&mut heartbeat_ticker) => {
+                let status = monitor.status().await;
+                info!("Heartbeat: {}", status);
+                notify_status(&status);
+            }
+            Some(request) = recv_request(&mut control_requests) => request.respond(&monitor).await,
+            Some(request) = recv_request(&mut api_requests) => request.respond(&monitor).await,
+            Some(_) = recv_signal(&mut reload_signal) => reload(&args, &mut monitor).await,
+            Some(_) = recv_signal(&mut resume_signal) => {
+                let resumed = monitor.resume_restarts();
+                info!(
+                    "Received SIGUSR1, resumed restarts on {:?}",
+                    resumed.iter().map(|i| &i.name).collect::<Vec<_>>()
+                );
             }
-            BusEventType::Restart | BusEventType::Stopped => {
-                // Just let pending restarts ride out.
-                // These can arrive in a weird order during a continuous bus short condition causing this to race
+            Some(_) = recv_signal(&mut status_signal) => {
+                args.output.print(&format!("Status: {}\n", monitor.status().await));
             }
+            Some(_) = recv_signal(&mut terminate_signal) => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            }
+            Some(_) = recv_signal(&mut interrupt_signal) => {
+                info!("Received SIGINT, shutting down");
+                break;
+            }
+        }
+    }
+
+    // No more requests are answered while shutting down
+    if let Some(task) = api_task {
+        task.abort();
+    }
+    monitor
+        .shutdown(Duration::from_millis(args.shutdown_grace_ms))
+        .await;
+
+    #[cfg(feature = "control")]
+    if let Some(path) = &args.control_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    if let (Some(recorder), Some(duration)) = (recorder, args.learn) {
+        args.output.print(&recorder.report(duration));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        ffi::OsString,
+        sync::{Mutex, PoisonError},
+    };
+
+    /// Held while the `CANSENTINEL_*` variables are set or read, since clap reads them when parsing
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn parse<I, T>(args: I) -> Result<Args, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        Args::try_parse_from(args)
+    }
+
+    #[test]
+    fn help_lists_every_exit_code_once() {
+        let help = exit_codes_help();
+        let codes: Vec<i32> = help
+            .lines()
+            .skip_while(|line| *line != "Exit codes:")
+            .skip(1)
+            .map(|line| line.split_whitespace().next().unwrap().parse().unwrap())
+            .collect();
+        let mut expected: Vec<i32> = ExitReason::ALL
+            .iter()
+            .map(|reason| *reason as i32)
+            .collect();
+        expected.push(USAGE_EXIT_CODE);
+        expected.sort();
+        assert_eq!(codes, expected);
+        expected.dedup();
+        assert_eq!(codes.len(), expected.len());
+    }
+
+    #[test]
+    fn listing_interfaces_excludes_checking_and_learning() {
+        for other in [&["--once"][..], &["--learn", "10m"]] {
+            let args = ["cansentinel", "--list-interfaces"].iter().chain(other);
+            let error = parse(args).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
         }
     }
 
-    for handle in error_handles {
-        handle.abort();
+    #[test]
+    fn only_restarting_needs_net_admin() {
+        let needs = |flags: &[&str]| {
+            let args = ["cansentinel", "-i", "can0"].iter().chain(flags);
+            needs_net_admin(&parse(args).unwrap())
+        };
+        assert!(needs(&[]));
+        assert!(!needs(&["--action", "warn"]));
+        assert!(!needs(&["--dry-run"]));
+        assert!(!needs(&["--learn", "10m"]));
+        assert!(!needs(&["--once", "--action", "warn"]));
+    }
+
+    #[test]
+    fn boolean_flags_accept_boolish_env_values() {
+        let variables = [
+            ("CANSENTINEL_DRY_RUN", "1"),
+            ("CANSENTINEL_VERBOSE", "yes"),
+            ("CANSENTINEL_NO_COLOR", "off"),
+        ];
+        let args = {
+            let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+            // SAFETY: every other test reading the environment parses through `parse`, which
+            // waits for ENV, and the variables are gone again before it is released
+            unsafe {
+                for (name, value) in variables {
+                    std::env::set_var(name, value);
+                }
+            }
+            let args = Args::try_parse_from(["cansentinel", "-i", "can0"]);
+            // SAFETY: as above
+            unsafe {
+                for (name, _) in variables {
+                    std::env::remove_var(name);
+                }
+            }
+            args.unwrap()
+        };
+        assert!(args.dry_run);
+        assert!(args.verbose);
+        assert!(!args.no_color);
+    }
+
+    #[test]
+    fn regexes_select_interfaces_minus_the_excluded() {
+        let discovered: Vec<_> = ["can0", "can1", "vcan0", "slcan0"]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| CanInterfaceInfo {
+                idx: idx as u32 + 1,
+                name: name.to_string(),
+            })
+            .collect();
+        let exclude = [parse_interface_regex("^vcan").unwrap()];
+        let remaining = without_excluded(discovered, &exclude);
+
+        let names = |regex: &str| -> Vec<String> {
+            matching_regex(&remaining, &parse_interface_regex(regex).unwrap())
+                .into_iter()
+                .map(|interface| interface.name.clone())
+                .collect()
+        };
+        assert_eq!(names("can0$"), ["can0", "slcan0"]);
+        assert_eq!(names("^can[0-9]+$"), ["can0", "can1"]);
+        assert!(names("^vcan").is_empty());
+        assert!(parse_interface_regex("can(").is_err());
     }
-    netlink_handle.abort();
 }
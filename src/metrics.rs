@@ -0,0 +1,355 @@
+//! Prometheus metrics
+//!
+//! [`Metrics`] holds the counters, which are cheap enough to always keep. Serving them over HTTP
+//! requires the `metrics` feature.
+
+use crate::{events::ErrorCounters, interface::CanInterfaceInfo};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fmt::Write,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the bus-off downtime histogram buckets, in seconds
+pub(crate) const DOWNTIME_BUCKETS: [f64; 8] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Name, help and value of each per-interface counter
+pub(crate) const COUNTERS: [(&str, &str, fn(&InterfaceCounters) -> u64); 3] = [
+    ("cansentinel_busoff_total", "Bus-off events detected", |c| {
+        c.bus_offs
+    }),
+    (
+        "cansentinel_restart_total",
+        "Interface restarts attempted",
+        |c| c.restarts,
+    ),
+    (
+        "cansentinel_restart_failed_total",
+        "Interface restarts that returned an error or didn't hold",
+        |c| c.failed_restarts,
+    ),
+];
+
+/// Name, help and value of each controller error counter gauge
+pub(crate) const ERROR_COUNTER_GAUGES: [(&str, &str, fn(&ErrorCounters) -> u32); 2] = [
+    (
+        "cansentinel_tx_errors",
+        "Controller transmit error counter",
+        |c| c.tx,
+    ),
+    (
+        "cansentinel_rx_errors",
+        "Controller receive error counter",
+        |c| c.rx,
+    ),
+];
+
+/// Name and help of the bus-off downtime histogram
+pub(crate) const DOWNTIME: (&str, &str) = (
+    "cansentinel_busoff_downtime_seconds",
+    "Time from bus-off detection to confirmed recovery",
+);
+
+/// Name and help of the pending restarts gauge
+pub(crate) const PENDING_RESTARTS: (&str, &str) = (
+    "cansentinel_pending_restarts",
+    "Restarts waiting out their delay",
+);
+
+/// Name and help of the stalled tasks gauge
+pub(crate) const STALLED_TASKS: (&str, &str) = (
+    "cansentinel_stalled_tasks",
+    "Monitoring tasks that haven't shown signs of life in time",
+);
+
+/// Called with the interface name and the downtime in seconds of every recovered bus-off
+type DowntimeObserver = Box<dyn Fn(&str, f64) + Send + Sync>;
+
+/// Where downtimes are passed on to, once set
+#[derive(Default)]
+struct DowntimeObserverSlot(OnceLock<DowntimeObserver>);
+
+impl fmt::Debug for DowntimeObserverSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DowntimeObserverSlot")
+            .field(&self.0.get().is_some())
+            .finish()
+    }
+}
+
+/// Bus-off downtimes of a single interface
+#[derive(Debug, Clone, Copy, Default)]
+struct DowntimeHistogram {
+    /// Incidents at or below each bound, not cumulative
+    buckets: [u64; DOWNTIME_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl DowntimeHistogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = DOWNTIME_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Counters for a single interface
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct InterfaceCounters {
+    pub(crate) bus_offs: u64,
+    pub(crate) restarts: u64,
+    pub(crate) failed_restarts: u64,
+    /// Last controller error counters reported, if the driver reports them
+    pub(crate) error_counters: Option<ErrorCounters>,
+    downtime: DowntimeHistogram,
+}
+
+/// Bus-off and restart counters, rendered in the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Counters by interface name
+    interfaces: Mutex<BTreeMap<String, InterfaceCounters>>,
+    pending_restarts: AtomicU64,
+    stalled_tasks: AtomicU64,
+    downtime_observer: DowntimeObserverSlot,
+}
+
+impl Metrics {
+    /// Create an empty set of metrics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report an interface with zeroed counters before anything has happened on it
+    pub fn register(&self, interface: &CanInterfaceInfo) {
+        self.update(interface, |_| ());
+    }
+
+    /// Count a bus-off
+    pub fn record_bus_off(&self, interface: &CanInterfaceInfo) {
+        self.update(interface, |counters| counters.bus_offs += 1);
+    }
+
+    /// Count a restart attempt, and whether it failed
+    pub fn record_restart(&self, interface: &CanInterfaceInfo, succeeded: bool) {
+        self.update(interface, |counters| {
+            counters.restarts += 1;
+            if !succeeded {
+                counters.failed_restarts += 1;
+            }
+        });
+    }
+
+    /// Count a restart that didn't hold, without counting another attempt
+    pub fn record_failed_restart(&self, interface: &CanInterfaceInfo) {
+        self.update(interface, |counters| counters.failed_restarts += 1);
+    }
+
+    /// Record how long a bus-off kept the interface unavailable
+    pub fn record_downtime(&self, interface: &CanInterfaceInfo, downtime: Duration) {
+        self.update(interface, |counters| {
+            counters.downtime.observe(downtime.as_secs_f64())
+        });
+        if let Some(observer) = self.downtime_observer.0.get() {
+            observer(&interface.name, downtime.as_secs_f64());
+        }
+    }
+
+    /// Also pass every recorded downtime to `observer`, for exporters that can't read the
+    /// histogram back
+    ///
+    /// Only the first observer set is kept.
+    pub(crate) fn observe_downtime(&self, observer: impl Fn(&str, f64) + Send + Sync + 'static) {
+        let _ = self.downtime_observer.0.set(Box::new(observer));
+    }
+
+    /// Update the controller error counter gauges
+    pub fn set_error_counters(&self, interface: &CanInterfaceInfo, error_counters: ErrorCounters) {
+        self.update(interface, |counters| {
+            counters.error_counters = Some(error_counters)
+        });
+    }
+
+    /// Set the number of restarts currently waiting out their delay
+    pub fn set_pending_restarts(&self, pending: usize) {
+        self.pending_restarts
+            .store(pending as u64, Ordering::Relaxed);
+    }
+
+    /// Set the number of monitoring tasks that haven't shown signs of life in time
+    pub fn set_stalled_tasks(&self, stalled: usize) {
+        self.stalled_tasks.store(stalled as u64, Ordering::Relaxed);
+    }
+
+    fn update(&self, interface: &CanInterfaceInfo, f: impl FnOnce(&mut InterfaceCounters)) {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        f(interfaces.entry(interface.name.clone()).or_default());
+    }
+
+    /// Current counters of every interface, by interface name
+    pub(crate) fn snapshot(&self) -> BTreeMap<String, InterfaceCounters> {
+        self.interfaces.lock().unwrap().clone()
+    }
+
+    /// Number of restarts currently waiting out their delay
+    pub(crate) fn pending_restarts(&self) -> u64 {
+        self.pending_restarts.load(Ordering::Relaxed)
+    }
+
+    /// Number of monitoring tasks that haven't shown signs of life in time
+    pub(crate) fn stalled_tasks(&self) -> u64 {
+        self.stalled_tasks.load(Ordering::Relaxed)
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let interfaces = self.snapshot();
+        let mut out = String::new();
+
+        for (name, help, value) in COUNTERS {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (interface, counters) in &interfaces {
+                let _ = writeln!(
+                    out,
+                    "{name}{{interface=\"{}\"}} {}",
+                    escape_label(interface),
+                    value(counters)
+                );
+            }
+        }
+
+        for (name, help, value) in ERROR_COUNTER_GAUGES {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            for (interface, counters) in &interfaces {
+                if let Some(error_counters) = &counters.error_counters {
+                    let _ = writeln!(
+                        out,
+                        "{name}{{interface=\"{}\"}} {}",
+                        escape_label(interface),
+                        value(error_counters)
+                    );
+                }
+            }
+        }
+
+        let (name, help) = DOWNTIME;
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (interface, counters) in &interfaces {
+            let label = escape_label(interface);
+            let histogram = &counters.downtime;
+            let mut cumulative = 0;
+            for (bound, count) in DOWNTIME_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{interface=\"{label}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{interface=\"{label}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{interface=\"{label}\"}} {}", histogram.sum);
+            let _ = writeln!(
+                out,
+                "{name}_count{{interface=\"{label}\"}} {}",
+                histogram.count
+            );
+        }
+
+        for ((name, help), value) in [
+            (PENDING_RESTARTS, self.pending_restarts()),
+            (STALLED_TASKS, self.stalled_tasks()),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        }
+        out
+    }
+}
+
+/// Escape a label value for the text exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(feature = "metrics")]
+mod server {
+    use super::Metrics;
+    use crate::http::{self, Response};
+    use std::{io, net::SocketAddr, sync::Arc};
+    use tokio::{net::TcpListener, task::JoinHandle};
+    use tracing::info;
+
+    /// Content type of the Prometheus text format
+    const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+    impl Metrics {
+        /// Serve `GET /metrics` on `addr` until the returned task is aborted
+        ///
+        /// Fails if the address can't be bound. Must be called from within a tokio runtime.
+        pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> io::Result<JoinHandle<()>> {
+            let listener = TcpListener::bind(addr).await?;
+            info!(
+                "Serving metrics on http://{}/metrics",
+                listener.local_addr()?
+            );
+            Ok(http::serve(listener, "Metrics", move |request| {
+                let metrics = Arc::clone(&self);
+                async move {
+                    Some(match (request.method.as_str(), request.path.as_str()) {
+                        ("GET", "/metrics") => {
+                            Response::new("200 OK", CONTENT_TYPE, metrics.render())
+                        }
+                        _ => Response::new("404 Not Found", CONTENT_TYPE, String::new()),
+                    })
+                }
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::can0;
+
+    #[test]
+    fn downtime_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        metrics.record_downtime(&can0(), Duration::from_millis(300));
+        metrics.record_downtime(&can0(), Duration::from_secs(20));
+        metrics.record_downtime(&can0(), Duration::from_secs(600));
+
+        let rendered = metrics.render();
+        let bucket = |le: &str, count: u32| {
+            format!(
+                "cansentinel_busoff_downtime_seconds_bucket{{interface=\"can0\",le=\"{le}\"}} {count}\n"
+            )
+        };
+        assert!(rendered.contains(&bucket("0.1", 0)));
+        assert!(rendered.contains(&bucket("0.5", 1)));
+        assert!(rendered.contains(&bucket("30", 2)));
+        assert!(rendered.contains(&bucket("300", 2)));
+        assert!(rendered.contains(&bucket("+Inf", 3)));
+        assert!(
+            rendered.contains("cansentinel_busoff_downtime_seconds_count{interface=\"can0\"} 3\n")
+        );
+    }
+}
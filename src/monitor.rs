@@ -0,0 +1,1745 @@
+//! Lifecycle of a complete monitoring session
+//!
+//! A [`Monitor`] owns every task watching a set of interfaces: the netlink listener, one error
+//! frame monitor per interface and the event loop that schedules restarts. It can be stopped and
+//! a fresh one started in the same process, which lets cansentinel run as a component of a
+//! larger service.
+
+use crate::{
+    backend::{CanControl, SocketCanBackend},
+    config::{Config, RestartMethod},
+    correlation::BusOffCorrelator,
+    events::{BusEvent, BusEventSource, BusEventType},
+    groups::RestartGroups,
+    history::{EventHistory, EventOutcome, Outage, OutageLog, RecentEvents, RecordedEvent},
+    hooks::Hooks,
+    interface::CanInterfaceInfo,
+    liveness::{Liveness, Task},
+    log,
+    metrics::Metrics,
+    monitoring::ErrorMonitorOptions,
+    restart::{AdaptiveDelay, Backoff, CircuitBreaker, Jitter, RestartManager},
+    runtime::{self, EventSources, QueueLimit, SourceOptions, StateSource},
+    snapshot::InterfaceSnapshot,
+    state::{self, StateFile},
+};
+use serde::Serialize;
+use socketcan::nl::CanState;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    task::{AbortHandle, JoinHandle, JoinSet},
+    time::Instant,
+};
+use tracing::{Instrument, Span, debug, error, error_span, field, info, warn};
+
+/// Interval at which the event loop reports that it is alive while idle
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval at which a restart is checked for completion, to start its group's cooldown
+const GROUP_RESTART_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Restart every interface that is bus-off, wait for the restarts to complete, and report
+/// whether every interface ended up healthy
+///
+/// This is the startup check of [`Monitor::start`] on its own, without starting any monitoring,
+/// restarting and reading states through `control`. With [`Action::Warn`] nothing is restarted,
+/// and with `dry_run` restarts are only logged. Interfaces that are stopped or whose state can't
+/// be read count as unhealthy.
+pub async fn check_once(
+    interfaces: &[CanInterfaceInfo],
+    control: Arc<dyn CanControl>,
+    action: Action,
+    dry_run: bool,
+) -> bool {
+    let mut restart_manager = RestartManager::with_control(Arc::clone(&control));
+    if dry_run {
+        restart_manager = restart_manager.with_dry_run();
+    }
+    let states = restart_manager.current_states(interfaces);
+    for interface in interfaces {
+        let state = states.get(&interface.idx).copied();
+        if state == Some(CanState::BusOff) {
+            restart_if_bus_off(&restart_manager, interface, state, action).await;
+        }
+    }
+    // Restarts are verified before they complete, so states are settled after this
+    restart_manager.shutdown().await;
+
+    let mut healthy = true;
+    for interface in interfaces {
+        let _span = interface.span().entered();
+        match control.state(interface.idx) {
+            Ok(Some(state @ (CanState::BusOff | CanState::Stopped))) => {
+                error!("Unhealthy: {:?}", state);
+                healthy = false;
+            }
+            Ok(Some(state)) => info!("Healthy: {:?}", state),
+            Ok(None) => {
+                error!("Unhealthy: state not reported");
+                healthy = false;
+            }
+            Err(e) => {
+                error!("Failed to read state: {}", e);
+                healthy = false;
+            }
+        }
+    }
+    healthy
+}
+
+/// What a [`Monitor`] does about interfaces that go bus-off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Action {
+    /// Restart them
+    #[default]
+    Restart,
+    /// Only report them: bus-offs are logged and counted and run hooks, but nothing is ever
+    /// restarted or brought back up
+    Warn,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "restart" => Ok(Self::Restart),
+            "warn" => Ok(Self::Warn),
+            _ => Err(format!("invalid action '{s}' (expected restart or warn)")),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Restart => f.write_str("restart"),
+            Self::Warn => f.write_str("warn"),
+        }
+    }
+}
+
+/// Options controlling how a [`Monitor`] reacts to bus events
+#[derive(Debug, Clone, Default)]
+pub struct MonitorOptions {
+    /// Options passed to every error frame monitor
+    pub error_monitor: ErrorMonitorOptions,
+    /// Whether bus-offs are acted on or only reported
+    pub action: Action,
+    /// Interfaces that share power or wiring and recover together
+    pub restart_groups: Vec<Vec<CanInterfaceInfo>>,
+    /// Time after a restart during which events from the rest of its group are suppressed
+    pub group_cooldown: Duration,
+    /// Window within which bus-offs after a restart are collapsed into one recheck, and a
+    /// recovery followed by a bus-off is ignored as noise (zero disables)
+    pub debounce: Duration,
+    /// Window within which bus-offs of one interface from different sources count as a single
+    /// detection (zero disables)
+    pub coalesce_window: Duration,
+    /// Commands run on bus-off and recovery
+    pub hooks: Hooks,
+    /// Log the interface's parameters and statistics when it goes bus-off
+    pub snapshot_on_bus_off: bool,
+    /// Number of interfaces going bus-off within the correlation window that counts as a
+    /// system-wide fault (0 disables)
+    pub correlation_threshold: usize,
+    /// Window within which bus-offs on different interfaces are correlated
+    pub correlation_window: Duration,
+    /// During a correlated fault, skip per-interface bus-off logs and hooks
+    pub aggregate_incidents: bool,
+    /// Adapt restart delays to each interface's bus-off history
+    pub adaptive_delay: Option<AdaptiveDelay>,
+    /// Back off exponentially on interfaces that keep going bus-off after restarts
+    pub backoff: Option<Backoff>,
+    /// Stop restarting interfaces that go bus-off too often
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Bring interfaces that are stopped (administratively down) back up, like bus-off ones are
+    /// restarted
+    pub restart_stopped: bool,
+    /// Number of restarts allowed in progress at the same time, unlimited if unset
+    pub max_concurrent_restarts: Option<usize>,
+    /// Add a random extra delay to restarts
+    pub jitter: Option<Jitter>,
+    /// Log restarts instead of issuing them
+    pub dry_run: bool,
+    /// Only count a restart as successful once the interface has stayed out of bus-off this long
+    pub confirm_window: Option<Duration>,
+    /// How long down/up restarts keep the interface down, or
+    /// [`DEFAULT_SETTLE_TIME`](crate::restart::DEFAULT_SETTLE_TIME) if unset
+    pub settle_time: Option<Duration>,
+    /// Shortest time between two restarts of the same interface
+    pub min_restart_interval: Option<Duration>,
+    /// Ignore bus-off and stopped events of an interface for this long after restarting it, then
+    /// check once whether it is still bus-off
+    pub mute_after_restart: Option<Duration>,
+    /// Bound on the events waiting to be handled, unbounded if unset
+    pub queue_limit: Option<QueueLimit>,
+    /// Number of recent events kept for inspection (0 disables)
+    pub history_size: usize,
+    /// Number of recent events kept for each interface (0 disables)
+    pub interface_history_size: usize,
+    /// Number of recent outages kept for [`Monitor::outages`] (0 disables)
+    pub outage_history_size: usize,
+    /// Print every event to stdout as a line of JSON, see [`BusEvent::to_json`]
+    pub json_events: bool,
+    /// Also poll interface states at this interval, alongside netlink
+    pub poll_interval: Option<Duration>,
+    /// Poll interface states at this interval if netlink link notifications are not permitted,
+    /// and sysfs at this interval with [`StateSource::Sysfs`]
+    pub poll_fallback: Option<Duration>,
+    /// Where link changes and interface states come from
+    pub state_source: StateSource,
+    /// Warn about monitoring tasks that haven't shown signs of life for this long
+    pub liveness_timeout: Option<Duration>,
+    /// Count bus-offs and restarts here
+    pub metrics: Option<Arc<Metrics>>,
+    /// Restart interfaces through this instead of socketcan
+    pub control: Option<Arc<dyn CanControl>>,
+    /// Save restart counts and circuit breaker state here, and carry on from them at startup
+    pub state_file: Option<PathBuf>,
+}
+
+/// Totals for a monitoring session, returned by [`Monitor::stop`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MonitorStats {
+    /// How long the monitor ran
+    pub uptime: Duration,
+    /// Number of bus events handled by the event loop
+    pub events_handled: u64,
+    /// Number of restarts issued
+    pub restarts: u64,
+    /// Number of restarts still pending when the monitor was stopped, which were cancelled
+    pub cancelled_restarts: usize,
+}
+
+/// Live state of a monitored interface, see [`Monitor::interface_status`]
+#[derive(Debug, Clone, Serialize)]
+pub struct InterfaceStatus {
+    /// Interface name
+    pub interface: String,
+    /// Interface index
+    pub idx: u32,
+    /// Controller state, if it could be read
+    pub state: Option<String>,
+    /// Milliseconds until the pending restart, if one is pending
+    pub restart_in_ms: Option<u64>,
+    /// Milliseconds since the interface went bus-off, while it is down
+    pub down_ms: Option<u64>,
+    /// Whether the circuit breaker has suspended restarts
+    pub restarts_suspended: bool,
+    /// Restarts issued since monitoring started
+    pub restarts: u64,
+    /// Restarts that returned an error
+    pub failed_restarts: u64,
+    /// When the last restart was issued, in seconds since the Unix epoch
+    pub last_restart: Option<f64>,
+}
+
+/// Interfaces added and removed by [`Monitor::reload`]
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceChanges {
+    /// Interfaces now monitored that weren't before
+    pub added: Vec<CanInterfaceInfo>,
+    /// Interfaces no longer monitored
+    pub removed: Vec<CanInterfaceInfo>,
+    /// Interfaces still monitored under a new name, with their new name
+    pub renamed: Vec<CanInterfaceInfo>,
+}
+
+impl InterfaceChanges {
+    /// Whether the set of monitored interfaces is unchanged
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+}
+
+/// A running set of monitoring tasks for a list of interfaces
+///
+/// The list and the configuration can be changed while monitoring with [`Monitor::reload`].
+#[derive(Debug)]
+pub struct Monitor {
+    interfaces: Vec<CanInterfaceInfo>,
+    /// Shared with the event loop, which reads restart delays from it
+    config: Arc<RwLock<Config>>,
+    started: Instant,
+    restart_manager: Arc<RestartManager>,
+    action: Action,
+    metrics: Option<Arc<Metrics>>,
+    history: RecentEvents,
+    interface_history: EventHistory,
+    outages: OutageLog,
+    /// When each interface that is currently down went bus-off
+    down_since: Arc<Mutex<HashMap<u32, Instant>>>,
+    /// Debounce and mute rechecks scheduled by the event loop
+    rechecks: Arc<Mutex<HashMap<u32, (Instant, AbortHandle)>>>,
+    /// Spans of unrecovered bus-offs opened by the event loop
+    bus_off_spans: Arc<Mutex<HashMap<u32, Span>>>,
+    events_handled: Arc<AtomicU64>,
+    /// Set once the event loop has finished on its own
+    finished: watch::Receiver<bool>,
+    /// Last time the event loop went around, at least every [`HEARTBEAT_INTERVAL`]
+    heartbeat: watch::Receiver<Instant>,
+    /// Feeds events back into the event loop
+    injector: mpsc::WeakUnboundedSender<BusEvent>,
+    sources: EventSources,
+    event_loop: JoinHandle<()>,
+    /// Where restart state is saved, and the task saving it periodically
+    state: Option<(Arc<StateFile>, JoinHandle<()>)>,
+}
+
+impl Monitor {
+    /// Start monitoring `interfaces`
+    ///
+    /// Interfaces that are already bus-off are restarted immediately, unless the action is
+    /// [`Action::Warn`]. With a state file, restart counts and circuit breaker state saved by a
+    /// previous run are restored first. Must be called from within a tokio runtime.
+    pub async fn start(
+        config: Config,
+        interfaces: Vec<CanInterfaceInfo>,
+        options: MonitorOptions,
+    ) -> Self {
+        let (mut rx, recheck_tx, sources) = runtime::watch(
+            interfaces.clone(),
+            SourceOptions {
+                error_monitor: options.error_monitor.clone(),
+                poll_interval: options.poll_interval,
+                poll_fallback: options.poll_fallback,
+                state_source: options.state_source,
+                queue_limit: options.queue_limit,
+            },
+        )
+        .into_parts();
+
+        // Restarts that don't take and tripped circuit breakers come back around as events
+        let control = options
+            .control
+            .clone()
+            .unwrap_or_else(|| Arc::new(SocketCanBackend));
+        let mut restart_manager =
+            RestartManager::with_control(control).with_event_sender(recheck_tx.clone());
+        if let Some(policy) = options.adaptive_delay {
+            restart_manager = restart_manager.with_adaptive_delay(policy);
+        }
+        if let Some(policy) = options.backoff {
+            restart_manager = restart_manager.with_backoff(policy);
+        }
+        if let Some(policy) = options.circuit_breaker {
+            restart_manager = restart_manager.with_circuit_breaker(policy);
+        }
+        if options.restart_stopped {
+            restart_manager = restart_manager.with_restart_stopped();
+        }
+        if let Some(limit) = options.max_concurrent_restarts {
+            restart_manager = restart_manager.with_max_concurrent_restarts(limit);
+        }
+        if let Some(policy) = options.jitter {
+            restart_manager = restart_manager.with_jitter(policy);
+        }
+        if options.dry_run {
+            restart_manager = restart_manager.with_dry_run();
+        }
+        if let Some(window) = options.confirm_window {
+            restart_manager = restart_manager.with_confirmation_window(window);
+        }
+        if let Some(settle) = options.settle_time {
+            restart_manager = restart_manager.with_settle_time(settle);
+        }
+        if let Some(interval) = options.min_restart_interval {
+            restart_manager = restart_manager.with_min_interval(interval);
+        }
+        if let Some(window) = options.mute_after_restart {
+            restart_manager = restart_manager.with_mute_window(window);
+        }
+        if let Some(metrics) = &options.metrics {
+            for interface in &interfaces {
+                metrics.register(interface);
+            }
+            restart_manager = restart_manager.with_metrics(Arc::clone(metrics));
+        }
+        let restart_manager = Arc::new(restart_manager);
+        let state_file = options
+            .state_file
+            .clone()
+            .map(|path| Arc::new(StateFile::open(path)));
+
+        // One dump for all of them rather than a state request per interface
+        let states = restart_manager.current_states(&interfaces);
+        for interface in &interfaces {
+            if let Some(state_file) = &state_file {
+                state_file.restore(&restart_manager, interface);
+            }
+            restart_manager
+                .set_link_settings(interface.idx, config.link_settings_for(&interface.name));
+            restart_manager
+                .set_restart_method(interface.idx, config.restart_method_for(&interface.name));
+            restart_manager
+                .set_reset_counters(interface.idx, config.reset_counters_for(&interface.name));
+            let state = states.get(&interface.idx).copied();
+            restart_if_bus_off(&restart_manager, interface, state, options.action).await;
+        }
+
+        let history = RecentEvents::new(options.history_size);
+        let interface_history = EventHistory::new(options.interface_history_size);
+        let outages = OutageLog::new(options.outage_history_size);
+        let down_since = Arc::new(Mutex::new(HashMap::new()));
+        let events_handled = Arc::new(AtomicU64::new(0));
+        let (finished_tx, finished) = watch::channel(false);
+        let (heartbeat_tx, heartbeat) = watch::channel(Instant::now());
+
+        let config = Arc::new(RwLock::new(config));
+        let metrics = options.metrics.clone();
+        let action = options.action;
+        let json_events = options.json_events;
+        let mut handler = EventHandler::new(
+            Arc::clone(&config),
+            Arc::clone(&restart_manager),
+            Arc::clone(&down_since),
+            outages.clone(),
+            recheck_tx.clone(),
+            sources.liveness(),
+            options,
+        );
+        let rechecks = Arc::clone(&handler.rechecks);
+        let bus_off_spans = Arc::clone(&handler.bus_off_spans);
+        let event_loop = {
+            let history = history.clone();
+            let interface_history = interface_history.clone();
+            let events_handled = Arc::clone(&events_handled);
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+                loop {
+                    let recovery_due = handler.next_recovery_due();
+                    tokio::select! {
+                        event = rx.recv() => {
+                            let Some(event) = event else { break };
+                            if json_events {
+                                println!("{}", event.to_json());
+                            }
+                            // Counter reports would push the state changes out of the histories
+                            let recorded = (event.event_type != BusEventType::CountersReported)
+                                .then(|| event.clone());
+                            if let Some(event) = &recorded {
+                                interface_history.record(event);
+                            }
+                            let span = event.interface.span();
+                            let outcome = handler.handle(event).instrument(span).await;
+                            if let Some(event) = recorded {
+                                history.record(event, outcome);
+                            }
+                            events_handled.fetch_add(1, Ordering::Relaxed);
+                        }
+                        _ = async {
+                            match recovery_due {
+                                Some(due) => tokio::time::sleep_until(due).await,
+                                None => std::future::pending().await,
+                            }
+                        } => {
+                            for event in handler.take_due_recoveries(Instant::now()) {
+                                let handled = event.clone();
+                                let span = event.interface.span();
+                                let outcome = handler.handle_recovery(event).instrument(span).await;
+                                history.record(handled, outcome);
+                            }
+                        }
+                        _ = ticker.tick() => handler.check_liveness(),
+                    }
+                    let _ = heartbeat_tx.send(Instant::now());
+                }
+                let _ = finished_tx.send(true);
+            })
+        };
+
+        let state = state_file.map(|state_file| {
+            let restart_manager = Arc::clone(&restart_manager);
+            let watched = sources.watched();
+            let task = {
+                let state_file = Arc::clone(&state_file);
+                tokio::spawn(async move {
+                    let mut ticker = tokio::time::interval(state::SAVE_INTERVAL);
+                    ticker.tick().await;
+                    loop {
+                        ticker.tick().await;
+                        let interfaces = watched.read().unwrap().clone();
+                        state_file.save(&restart_manager, &interfaces);
+                    }
+                })
+            };
+            (state_file, task)
+        });
+
+        Self {
+            interfaces,
+            config,
+            started: Instant::now(),
+            restart_manager,
+            action,
+            metrics,
+            history,
+            interface_history,
+            outages,
+            down_since,
+            rechecks,
+            bus_off_spans,
+            events_handled,
+            finished,
+            heartbeat,
+            injector: recheck_tx,
+            sources,
+            event_loop,
+            state,
+        }
+    }
+
+    /// Interfaces being monitored
+    pub fn interfaces(&self) -> &[CanInterfaceInfo] {
+        &self.interfaces
+    }
+
+    /// Monitored interface with the given name
+    pub fn interface(&self, name: &str) -> Option<&CanInterfaceInfo> {
+        self.interfaces
+            .iter()
+            .find(|interface| interface.name == name)
+    }
+
+    /// Live state of every monitored interface
+    pub async fn interface_status(&self) -> Vec<InterfaceStatus> {
+        let mut statuses = Vec::with_capacity(self.interfaces.len());
+        for interface in &self.interfaces {
+            let down_since = self.down_since.lock().unwrap().get(&interface.idx).copied();
+            let stats = self
+                .restart_manager
+                .stats(interface.idx)
+                .unwrap_or_default();
+            statuses.push(InterfaceStatus {
+                interface: interface.name.clone(),
+                idx: interface.idx,
+                state: self
+                    .interface_state(interface)
+                    .map(|state| format!("{:?}", state)),
+                restart_in_ms: self
+                    .restart_manager
+                    .restart_due_in(interface.idx)
+                    .await
+                    .map(|due| due.as_millis() as u64),
+                down_ms: down_since.map(|since| since.elapsed().as_millis() as u64),
+                restarts_suspended: self.restart_manager.is_tripped(interface.idx),
+                restarts: stats.restarts,
+                failed_restarts: stats.failures,
+                last_restart: stats.last_restart.map(|time| {
+                    time.duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs_f64()
+                }),
+            });
+        }
+        statuses
+    }
+
+    /// Last state reported for `interface` by netlink or polling, read through
+    /// [`MonitorOptions::control`] if none was reported yet, see
+    /// [`StateCache`](crate::interface::StateCache)
+    pub fn interface_state(&self, interface: &CanInterfaceInfo) -> Option<CanState> {
+        self.sources
+            .states()
+            .get(interface.idx)
+            .or_else(|| self.restart_manager.current_state(interface.idx))
+    }
+
+    /// Configuration in effect
+    pub fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Switch to a new configuration and list of interfaces without stopping monitoring
+    ///
+    /// Interfaces not monitored before are watched from now on, and restarted if already
+    /// bus-off. Interfaces missing from `interfaces` are no longer watched and their pending
+    /// restarts are cancelled. Restart delays are taken from `config` for every bus-off handled
+    /// after this returns, and its link settings for every restart.
+    pub async fn reload(
+        &mut self,
+        config: Config,
+        interfaces: Vec<CanInterfaceInfo>,
+    ) -> InterfaceChanges {
+        for interface in &interfaces {
+            self.restart_manager
+                .set_link_settings(interface.idx, config.link_settings_for(&interface.name));
+            self.restart_manager
+                .set_restart_method(interface.idx, config.restart_method_for(&interface.name));
+            self.restart_manager
+                .set_reset_counters(interface.idx, config.reset_counters_for(&interface.name));
+        }
+        *self.config.write().unwrap() = config;
+
+        // Interfaces are told apart by index, so one renamed in place keeps its history
+        let removed: Vec<_> = self
+            .interfaces
+            .iter()
+            .filter(|old| !interfaces.iter().any(|new| new.idx == old.idx))
+            .cloned()
+            .collect();
+        let added: Vec<_> = interfaces
+            .iter()
+            .filter(|new| !self.interfaces.iter().any(|old| old.idx == new.idx))
+            .cloned()
+            .collect();
+        let renamed: Vec<_> = interfaces
+            .iter()
+            .filter(|new| {
+                self.interfaces
+                    .iter()
+                    .any(|old| old.idx == new.idx && old.name != new.name)
+            })
+            .cloned()
+            .collect();
+
+        if let Some((state_file, _)) = &self.state {
+            // Kept in the file in case they come back
+            state_file.save(&self.restart_manager, &removed);
+        }
+        for interface in &removed {
+            if self.sources.remove(interface.idx).is_none() {
+                warn!("{} was not being watched", interface.name);
+            }
+            self.restart_manager.cancel_restart(interface).await;
+            self.restart_manager.set_link_settings(interface.idx, None);
+            self.restart_manager
+                .set_restart_method(interface.idx, RestartMethod::Ioctl);
+            self.restart_manager
+                .set_reset_counters(interface.idx, false);
+            self.interface_history.forget(interface.idx);
+            self.down_since.lock().unwrap().remove(&interface.idx);
+            self.bus_off_spans.lock().unwrap().remove(&interface.idx);
+            if let Some((_, recheck)) = self.rechecks.lock().unwrap().remove(&interface.idx) {
+                recheck.abort();
+            }
+        }
+        let states = if added.is_empty() {
+            HashMap::new()
+        } else {
+            self.restart_manager.current_states(&added)
+        };
+        for interface in &added {
+            if let Some(metrics) = &self.metrics {
+                metrics.register(interface);
+            }
+            if let Some((state_file, _)) = &self.state {
+                state_file.restore(&self.restart_manager, interface);
+            }
+            self.sources.add(interface.clone());
+            let state = states.get(&interface.idx).copied();
+            restart_if_bus_off(&self.restart_manager, interface, state, self.action).await;
+        }
+
+        for interface in &renamed {
+            if let Some(metrics) = &self.metrics {
+                metrics.register(interface);
+            }
+            if !self.sources.rename(interface.clone()) {
+                warn!("{} was not being watched", interface.name);
+            }
+        }
+
+        self.interfaces = interfaces;
+        InterfaceChanges {
+            added,
+            removed,
+            renamed,
+        }
+    }
+
+    /// Restart manager shared with the event loop
+    pub fn restart_manager(&self) -> &RestartManager {
+        &self.restart_manager
+    }
+
+    /// Most recently handled events, oldest first
+    pub fn recent_events(&self) -> Vec<RecordedEvent> {
+        self.history.recent_events()
+    }
+
+    /// Most recent bus-offs that have been recovered from, with their downtime, oldest first
+    ///
+    /// Keeps as many as [`MonitorOptions::outage_history_size`].
+    pub fn outages(&self) -> Vec<Outage> {
+        self.outages.outages()
+    }
+
+    /// Up to `count` of the most recent events on an interface, oldest first
+    pub fn interface_events(&self, idx: u32, count: usize) -> Vec<BusEvent> {
+        self.interface_history.last(idx, count)
+    }
+
+    /// Resume restarts suspended by the circuit breaker, returning the interfaces resumed
+    ///
+    /// Interfaces that are still bus-off get a fresh bus-off event so they are restarted.
+    pub fn resume_restarts(&self) -> Vec<CanInterfaceInfo> {
+        let mut resumed = Vec::new();
+        for interface in &self.interfaces {
+            let _span = interface.span().entered();
+            if !self.restart_manager.reset_circuit_breaker(interface.idx) {
+                continue;
+            }
+            if self.restart_manager.current_state(interface.idx) == Some(CanState::BusOff)
+                && let Some(tx) = self.injector.upgrade()
+            {
+                let _ = tx.send(BusEvent::bus_off(
+                    interface.clone(),
+                    BusEventSource::Poll(CanState::BusOff),
+                ));
+            }
+            resumed.push(interface.clone());
+        }
+        resumed
+    }
+
+    /// Whether monitoring is demonstrably alive: the event loop and every source showed signs of
+    /// life within `max_age`, and every error frame monitor is still running
+    ///
+    /// Use this to gate watchdog keepalives. `max_age` should be well above one second, the
+    /// interval at which idle tasks report in.
+    pub fn is_alive(&self, max_age: Duration) -> bool {
+        !self.event_loop.is_finished()
+            && self.heartbeat.borrow().elapsed() <= max_age
+            && self.sources.error_monitors_running()
+            && self.sources.liveness().stale(max_age).is_empty()
+    }
+
+    /// Sources that haven't shown signs of life within `max_age`, with how long ago they last did
+    pub fn stalled_tasks(&self, max_age: Duration) -> Vec<(Task, Duration)> {
+        self.sources.liveness().stale(max_age)
+    }
+
+    /// Wait until every monitoring task has stopped on its own
+    ///
+    /// This only happens if monitoring failed; a healthy monitor runs until [`Monitor::stop`].
+    pub async fn wait(&self) {
+        let mut finished = self.finished.clone();
+        let _ = finished.wait_for(|finished| *finished).await;
+    }
+
+    /// One-line status summary: uptime, interface states, pending and total restarts
+    pub async fn status(&self) -> String {
+        let states: Vec<String> = {
+            let down_since = self.down_since.lock().unwrap();
+            self.interfaces
+                .iter()
+                .map(|interface| {
+                    let state = match self.interface_state(interface) {
+                        Some(state) => format!("{:?}", state),
+                        None => "unknown".to_string(),
+                    };
+                    let suspended = if self.restart_manager.is_tripped(interface.idx) {
+                        ", restarts suspended"
+                    } else {
+                        ""
+                    };
+                    match down_since.get(&interface.idx) {
+                        Some(since) => format!(
+                            "{}={} (down {:?}{})",
+                            interface.name,
+                            state,
+                            since.elapsed(),
+                            suspended
+                        ),
+                        None => format!("{}={}", interface.name, state),
+                    }
+                })
+                .collect()
+        };
+
+        format!(
+            "uptime {:?}, {} interfaces [{}], {} pending restarts, {} restarts total",
+            Duration::from_secs(self.started.elapsed().as_secs()),
+            self.interfaces.len(),
+            states.join(", "),
+            self.restart_manager.pending_count().await,
+            self.restart_manager.total_restarts()
+        )
+    }
+
+    /// Stop all monitoring tasks, cancel pending restarts and return the session's totals
+    ///
+    /// Same as [`Monitor::shutdown`] without a grace period.
+    pub async fn stop(self) -> MonitorStats {
+        self.shutdown(Duration::ZERO).await
+    }
+
+    /// Stop handling events, give pending restarts up to `grace` to complete, then stop all
+    /// monitoring tasks and return the session's totals
+    ///
+    /// Restarts still pending after the grace period are cancelled, then the event sources are
+    /// stopped as by [`EventSources::stop`]. Hook commands still running are no longer waited
+    /// for, but not killed.
+    pub async fn shutdown(self, grace: Duration) -> MonitorStats {
+        // Stop accepting events first so no new restarts get scheduled
+        self.event_loop.abort();
+        let _ = self.event_loop.await;
+
+        let pending = self.restart_manager.pending_count().await;
+        if pending > 0 && !grace.is_zero() {
+            info!(
+                "Waiting up to {:?} for {} pending restarts to complete",
+                grace, pending
+            );
+        }
+        let cancelled_restarts = self.restart_manager.shutdown_timeout(grace).await;
+        if cancelled_restarts > 0 {
+            warn!("Cancelled {} pending restarts", cancelled_restarts);
+        }
+        if let Some((state_file, task)) = &self.state {
+            task.abort();
+            state_file.save(&self.restart_manager, &self.interfaces);
+        }
+
+        self.sources.stop().await;
+        info!("Monitoring stopped");
+
+        MonitorStats {
+            uptime: self.started.elapsed(),
+            events_handled: self.events_handled.load(Ordering::Relaxed),
+            restarts: self.restart_manager.total_restarts(),
+            cancelled_restarts,
+        }
+    }
+}
+
+/// Restart `interface` immediately if it is already bus-off, or already stopped if stopped
+/// interfaces are brought back up
+///
+/// With [`Action::Warn`] a bus-off interface is only reported.
+async fn restart_if_bus_off(
+    restart_manager: &RestartManager,
+    interface: &CanInterfaceInfo,
+    state: Option<CanState>,
+    action: Action,
+) {
+    if action == Action::Warn {
+        if state == Some(CanState::BusOff) {
+            let _span = interface.span().entered();
+            warn!("Already in bus-off state, not restarting it in warn-only mode");
+        }
+        return;
+    }
+    let message = match state {
+        Some(CanState::BusOff) => "Already in bus-off state, restarting immediately",
+        Some(CanState::Stopped) if restart_manager.restarts_stopped() => {
+            "Already stopped, bringing up immediately"
+        }
+        _ => return,
+    };
+    async {
+        warn!("{}", message);
+        restart_manager
+            .schedule_restart(interface.clone(), Duration::from_millis(0))
+            .await;
+    }
+    .instrument(interface.span())
+    .await;
+}
+
+/// State of the event loop, which decides what to do with each bus event
+struct EventHandler {
+    config: Arc<RwLock<Config>>,
+    options: MonitorOptions,
+    restart_manager: Arc<RestartManager>,
+    /// Shared with the tasks starting each group's cooldown once its restart completed
+    restart_groups: Arc<Mutex<RestartGroups>>,
+    correlator: BusOffCorrelator,
+    down_since: Arc<Mutex<HashMap<u32, Instant>>>,
+    /// Bus-offs closed by a recovery
+    outages: OutageLog,
+    recheck_tx: mpsc::WeakUnboundedSender<BusEvent>,
+    /// Recovered events held back for the debounce window, and when they are due
+    held_recoveries: HashMap<u32, (BusEvent, Instant)>,
+    /// When a restart was last scheduled for each interface
+    last_scheduled: HashMap<u32, Instant>,
+    /// When the debounce or mute recheck of each interface fires, and its task, shared with the
+    /// [`Monitor`] so removing the interface can abort it
+    rechecks: Arc<Mutex<HashMap<u32, (Instant, AbortHandle)>>>,
+    /// Span of each bus-off that hasn't been recovered from yet, which its restarts run in,
+    /// shared with the [`Monitor`] so removing the interface ends it
+    bus_off_spans: Arc<Mutex<HashMap<u32, Span>>>,
+    /// Number of the last bus-off incident, which tags every log line about it
+    last_incident: u64,
+    /// When each interface's latest bus-off was first reported, and every source reporting it
+    /// since, within the coalescing window
+    bus_off_reports: HashMap<u32, (Instant, Vec<&'static str>)>,
+    /// Signs of life from the event sources
+    liveness: Liveness,
+    /// Sources already warned about for not showing signs of life
+    stalled: HashSet<Task>,
+    /// Rechecks and hook runs started by the handler, aborted when it is dropped
+    tasks: JoinSet<()>,
+}
+
+impl EventHandler {
+    fn new(
+        config: Arc<RwLock<Config>>,
+        restart_manager: Arc<RestartManager>,
+        down_since: Arc<Mutex<HashMap<u32, Instant>>>,
+        outages: OutageLog,
+        recheck_tx: mpsc::WeakUnboundedSender<BusEvent>,
+        liveness: Liveness,
+        options: MonitorOptions,
+    ) -> Self {
+        Self {
+            config,
+            restart_manager,
+            restart_groups: Arc::new(Mutex::new(RestartGroups::new(
+                options.restart_groups.clone(),
+                options.group_cooldown,
+            ))),
+            correlator: BusOffCorrelator::new(
+                options.correlation_threshold,
+                options.correlation_window,
+            ),
+            down_since,
+            outages,
+            recheck_tx,
+            held_recoveries: HashMap::new(),
+            last_scheduled: HashMap::new(),
+            rechecks: Arc::new(Mutex::new(HashMap::new())),
+            bus_off_spans: Arc::new(Mutex::new(HashMap::new())),
+            last_incident: 0,
+            bus_off_reports: HashMap::new(),
+            liveness,
+            stalled: HashSet::new(),
+            tasks: JoinSet::new(),
+            options,
+        }
+    }
+
+    async fn handle(&mut self, event: BusEvent) -> EventOutcome {
+        while self.tasks.try_join_next().is_some() {}
+        if let Some(recorder) = &self.options.error_monitor.recorder {
+            recorder.record_event(&event);
+        }
+        if let Some(metrics) = &self.options.metrics
+            && let Some(counters) = event.error_counters
+        {
+            metrics.set_error_counters(&event.interface, counters);
+        }
+
+        if event.event_type == BusEventType::Stopped
+            && self
+                .restart_manager
+                .caused_stop(event.interface.idx, event.detected_at)
+        {
+            if self.options.error_monitor.verbose {
+                debug!(source = ?event.event_source, "Stopped by a restart, ignoring");
+            }
+            return EventOutcome::Suppressed;
+        }
+
+        if matches!(
+            event.event_type,
+            BusEventType::BusOff | BusEventType::Stopped
+        ) && let Some(until) = self.restart_manager.muted_until(event.interface.idx)
+        {
+            if self.options.error_monitor.verbose {
+                debug!(
+                    source = ?event.event_source,
+                    "{:?} ignored while settling after a restart, for another {:?}",
+                    event.event_type,
+                    until - Instant::now()
+                );
+            }
+            self.recheck_after_mute(event, until);
+            return EventOutcome::Suppressed;
+        }
+
+        match event.event_type {
+            BusEventType::BusOff => self.handle_bus_off(event).await,
+            BusEventType::Restart => {
+                // The interface may well go bus-off again, only `Recovered` closes the outage
+                self.options.hooks.restart(&event, &mut self.tasks);
+                EventOutcome::Ignored
+            }
+            BusEventType::Recovered => {
+                let down = self
+                    .down_since
+                    .lock()
+                    .unwrap()
+                    .contains_key(&event.interface.idx);
+                if down && !self.options.debounce.is_zero() {
+                    // Held back in case another bus-off follows, which makes it noise
+                    let due = Instant::now() + self.options.debounce;
+                    self.held_recoveries
+                        .insert(event.interface.idx, (event, due));
+                    return EventOutcome::Deferred;
+                }
+                self.handle_recovery(event).await
+            }
+            BusEventType::Stopped
+                if self.options.restart_stopped && self.options.action == Action::Restart =>
+            {
+                self.handle_stopped(event).await
+            }
+            BusEventType::Stopped => {
+                // Just let pending restarts ride out.
+                // These can arrive in a weird order during a continuous bus short condition causing this to race
+                EventOutcome::Ignored
+            }
+            // Logged by the restart manager, the interface stays down until resumed
+            BusEventType::GaveUp => EventOutcome::Ignored,
+            // Early warnings only, the controller recovers from these on its own
+            BusEventType::ErrorWarning => {
+                warn!(source = ?event.event_source, "Error warning: error counters rising");
+                self.options.hooks.degraded(&event, &mut self.tasks);
+                EventOutcome::Ignored
+            }
+            BusEventType::ErrorPassive => {
+                warn!(source = ?event.event_source, "Error passive: bus-off may follow");
+                self.options.hooks.degraded(&event, &mut self.tasks);
+                EventOutcome::Ignored
+            }
+            BusEventType::ErrorThreshold => {
+                warn!(source = ?event.event_source, "Error threshold reached");
+                self.options.hooks.degraded(&event, &mut self.tasks);
+                EventOutcome::Ignored
+            }
+            // Nothing to restart, the cable or adapter needs attention
+            BusEventType::CarrierLost => {
+                warn!(source = ?event.event_source, "Carrier lost, check the cable or adapter");
+                EventOutcome::Ignored
+            }
+            BusEventType::CarrierRestored => {
+                info!(target: log::RECOVERED, source = ?event.event_source, "Carrier restored");
+                EventOutcome::Ignored
+            }
+            BusEventType::PassiveDwell => self.handle_passive_dwell(event).await,
+            // Diagnostics only, the metrics were updated above
+            BusEventType::CountersReported => {
+                if let Some(counters) = event.error_counters {
+                    debug!("Error counters: TX={} RX={}", counters.tx, counters.rx);
+                }
+                EventOutcome::Ignored
+            }
+        }
+    }
+
+    async fn handle_recovery(&mut self, event: BusEvent) -> EventOutcome {
+        info!(target: log::RECOVERED, source = ?event.event_source, "Back to error-active");
+        // Pending restarts still ride out, this only closes the downtime window
+        self.bus_off_reports.remove(&event.interface.idx);
+        let since = self.down_since.lock().unwrap().remove(&event.interface.idx);
+        if let Some(since) = since {
+            // Measured between the events, so a recovery held back by debouncing isn't longer
+            let downtime = event.detected_at.saturating_duration_since(since);
+            let span = self
+                .bus_off_spans
+                .lock()
+                .unwrap()
+                .remove(&event.interface.idx)
+                .unwrap_or_else(Span::none);
+            span.record("downtime_ms", downtime.as_millis() as u64);
+            span.in_scope(|| {
+                info!(target: log::RECOVERED, source = ?event.event_source, "Recovered after {:?}", downtime);
+            });
+            if let Some(metrics) = &self.options.metrics {
+                metrics.record_downtime(&event.interface, downtime);
+            }
+            self.outages.record(Outage {
+                interface: event.interface.clone(),
+                started: event.timestamp - downtime,
+                downtime,
+            });
+            self.options
+                .hooks
+                .recovered(&event, downtime, &mut self.tasks);
+            self.restart_manager.record_healthy(&event.interface);
+            EventOutcome::Recovered
+        } else {
+            EventOutcome::Ignored
+        }
+    }
+
+    /// Warn about sources that stopped showing signs of life, and when they come back
+    fn check_liveness(&mut self) {
+        let Some(timeout) = self.options.liveness_timeout else {
+            return;
+        };
+        let stale = self.liveness.stale(timeout);
+        for (task, age) in &stale {
+            if self.stalled.insert(task.clone()) {
+                warn!(
+                    "No sign of life from {} for {:?}, monitoring may be hung",
+                    task, age
+                );
+            }
+        }
+        self.stalled.retain(|task| {
+            let still_stale = stale.iter().any(|(stale, _)| stale == task);
+            // Tasks that stopped legitimately aren't tracked anymore
+            if !still_stale && self.liveness.age(task).is_some() {
+                info!("{} is alive again", task);
+            }
+            still_stale
+        });
+        if let Some(metrics) = &self.options.metrics {
+            metrics.set_stalled_tasks(stale.len());
+        }
+    }
+
+    /// Whether `event` reports a bus-off already reported within the coalescing window, recording
+    /// its source either way
+    fn coalesce(&mut self, event: &BusEvent) -> bool {
+        let window = self.options.coalesce_window;
+        if window.is_zero() {
+            return false;
+        }
+        let source = event.event_source.name();
+        match self.bus_off_reports.get_mut(&event.interface.idx) {
+            Some((first, sources))
+                if event.detected_at.saturating_duration_since(*first) <= window =>
+            {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+                if self.options.error_monitor.verbose {
+                    debug!(
+                        source = ?event.event_source,
+                        "Bus-off also reported by {} after {:?}, reported by {}",
+                        source,
+                        event.detected_at.saturating_duration_since(*first),
+                        sources.join(", ")
+                    );
+                }
+                true
+            }
+            _ => {
+                self.bus_off_reports
+                    .insert(event.interface.idx, (event.detected_at, vec![source]));
+                false
+            }
+        }
+    }
+
+    /// When the earliest held recovery is due, if any are held
+    fn next_recovery_due(&self) -> Option<Instant> {
+        self.held_recoveries.values().map(|(_, due)| *due).min()
+    }
+
+    /// Release the held recoveries that are due at `now`
+    fn take_due_recoveries(&mut self, now: Instant) -> Vec<BusEvent> {
+        let due: Vec<u32> = self
+            .held_recoveries
+            .iter()
+            .filter(|(_, (_, due))| *due <= now)
+            .map(|(idx, _)| *idx)
+            .collect();
+        due.into_iter()
+            .filter_map(|idx| self.held_recoveries.remove(&idx))
+            .map(|(event, _)| event)
+            .collect()
+    }
+
+    /// Check again at `at`, once the debounce window ends, whether `interface` is still bus-off,
+    /// sending a fresh bus-off event if it is
+    fn recheck_after_debounce(&mut self, interface: CanInterfaceInfo, at: Instant) {
+        self.schedule_recheck(interface, at, CanState::BusOff);
+    }
+
+    /// Check again at `until`, once the mute window after a restart ends, whether the interface
+    /// is still as the muted bus-off or stopped `event` reported, sending it afresh if so
+    fn recheck_after_mute(&mut self, event: BusEvent, until: Instant) {
+        let state = if event.is_stopped() {
+            CanState::Stopped
+        } else {
+            CanState::BusOff
+        };
+        self.schedule_recheck(event.interface, until, state);
+    }
+
+    /// Send a fresh bus-off or stopped event for `interface` at `at` if it is in `state` then,
+    /// superseding any earlier recheck
+    fn schedule_recheck(&mut self, interface: CanInterfaceInfo, at: Instant, state: CanState) {
+        let mut rechecks = self.rechecks.lock().unwrap();
+        if rechecks
+            .get(&interface.idx)
+            .is_some_and(|(scheduled, task)| *scheduled >= at && !task.is_finished())
+        {
+            return;
+        }
+        let idx = interface.idx;
+        let recheck_tx = self.recheck_tx.clone();
+        let restart_manager = Arc::clone(&self.restart_manager);
+        let task = self.tasks.spawn(async move {
+            tokio::time::sleep_until(at).await;
+            if restart_manager.current_state(idx) == Some(state)
+                && let Some(tx) = recheck_tx.upgrade()
+            {
+                let source = BusEventSource::StateUpdate(state);
+                let _ = tx.send(if state == CanState::Stopped {
+                    BusEvent::stopped(interface, source)
+                } else {
+                    BusEvent::bus_off(interface, source)
+                });
+            }
+        });
+        // Superseded by the later recheck
+        if let Some((_, earlier)) = rechecks.insert(idx, (at, task)) {
+            earlier.abort();
+        }
+    }
+
+    /// Bring a stopped interface back up after its restart delay
+    ///
+    /// Unlike bus-off, this doesn't run hooks or count towards incidents.
+    async fn handle_stopped(&mut self, event: BusEvent) -> EventOutcome {
+        if self.restart_manager.is_pending(event.interface.idx).await {
+            return EventOutcome::Ignored;
+        }
+        info!(source = ?event.event_source, "Interface stopped, bringing it back up");
+        self.down_since
+            .lock()
+            .unwrap()
+            .entry(event.interface.idx)
+            .or_insert_with(Instant::now);
+
+        let delay = self
+            .config
+            .read()
+            .unwrap()
+            .restart_delay_for(&event.interface.name);
+        let idx = event.interface.idx;
+        self.restart_manager
+            .schedule_restart(event.interface, delay)
+            .await;
+        if self.restart_manager.is_tripped(idx) {
+            EventOutcome::Suppressed
+        } else {
+            EventOutcome::RestartScheduled
+        }
+    }
+
+    /// Restart an interface that stayed error-passive for too long
+    ///
+    /// Not a bus-off, so it neither opens an outage nor runs the bus-off hook.
+    async fn handle_passive_dwell(&mut self, event: BusEvent) -> EventOutcome {
+        self.options.hooks.degraded(&event, &mut self.tasks);
+        let idx = event.interface.idx;
+        if self.options.action == Action::Warn || self.restart_manager.is_pending(idx).await {
+            return EventOutcome::Ignored;
+        }
+        let delay = self
+            .config
+            .read()
+            .unwrap()
+            .restart_delay_for(&event.interface.name);
+        self.last_scheduled.insert(idx, Instant::now());
+        self.restart_manager
+            .schedule_passive_restart(event.interface, delay)
+            .await;
+        if self.restart_manager.is_tripped(idx) {
+            EventOutcome::Suppressed
+        } else {
+            EventOutcome::RestartScheduled
+        }
+    }
+
+    async fn handle_bus_off(&mut self, event: BusEvent) -> EventOutcome {
+        let now = Instant::now();
+        if self.coalesce(&event) {
+            return EventOutcome::Ignored;
+        }
+        if self.held_recoveries.remove(&event.interface.idx).is_some() {
+            debug!(
+                source = ?event.event_source,
+                "Bus-off right after recovering, ignoring the recovery as noise"
+            );
+        }
+        if self
+            .restart_groups
+            .lock()
+            .unwrap()
+            .is_suppressed(&event.interface, now)
+        {
+            if self.options.error_monitor.verbose {
+                debug!(source = ?event.event_source, "Bus-off suppressed during group cooldown");
+            }
+            return EventOutcome::Suppressed;
+        }
+
+        let first_detection = !self
+            .down_since
+            .lock()
+            .unwrap()
+            .contains_key(&event.interface.idx);
+        // Several sources report the same bus-off, only the first one is acted on
+        if !first_detection && self.restart_manager.is_pending(event.interface.idx).await {
+            if self.options.error_monitor.verbose {
+                debug!(
+                    source = ?event.event_source,
+                    "Duplicate bus-off ignored, restart already pending"
+                );
+            }
+            return EventOutcome::Ignored;
+        }
+        if first_detection {
+            if let Some(metrics) = &self.options.metrics {
+                metrics.record_bus_off(&event.interface);
+            }
+            if let Some(incident) = self.correlator.record(&event.interface, now) {
+                warn!(
+                    "System-wide CAN fault affecting {} interfaces: {}",
+                    incident.interfaces.len(),
+                    incident.interfaces.join(", ")
+                );
+            }
+            let aggregated = self.options.aggregate_incidents && self.correlator.in_incident(now);
+
+            self.last_incident += 1;
+            // At error level like the interface span, so it isn't filtered out with --quiet
+            let span = error_span!(
+                "bus_off",
+                incident = self.last_incident,
+                downtime_ms = field::Empty
+            );
+            span.in_scope(|| {
+                if !aggregated {
+                    warn!(target: log::BUS_OFF, source = ?event.event_source, "Bus-off detected");
+                }
+                if self.options.snapshot_on_bus_off {
+                    warn!(
+                        "Bus-off snapshot: {}",
+                        InterfaceSnapshot::capture(&event.interface)
+                    );
+                }
+            });
+            self.down_since
+                .lock()
+                .unwrap()
+                .insert(event.interface.idx, event.detected_at);
+            self.bus_off_spans
+                .lock()
+                .unwrap()
+                .insert(event.interface.idx, span);
+            if !aggregated {
+                self.options.hooks.bus_off(&event, &mut self.tasks);
+            }
+        }
+        if self.options.action == Action::Warn {
+            return EventOutcome::Ignored;
+        }
+
+        // Late reports of a bus-off that was just restarted; check again once they've settled
+        if let Some(last) = self.last_scheduled.get(&event.interface.idx)
+            && now < *last + self.options.debounce
+        {
+            let until = *last + self.options.debounce;
+            if self.options.error_monitor.verbose {
+                debug!(source = ?event.event_source, "Bus-off debounced until {:?}", until - now);
+            }
+            self.recheck_after_debounce(event.interface, until);
+            return EventOutcome::Suppressed;
+        }
+
+        let delay = self
+            .config
+            .read()
+            .unwrap()
+            .restart_delay_for(&event.interface.name);
+        let grouped = self
+            .restart_groups
+            .lock()
+            .unwrap()
+            .start_restart(&event.interface);
+        let interface = event.interface.clone();
+
+        let idx = event.interface.idx;
+        self.last_scheduled.insert(idx, now);
+        let span = self
+            .bus_off_spans
+            .lock()
+            .unwrap()
+            .get(&idx)
+            .cloned()
+            .unwrap_or_else(Span::current);
+        self.restart_manager
+            .schedule_restart(event.interface, delay)
+            .instrument(span)
+            .await;
+        if grouped {
+            self.start_group_cooldown(interface);
+        }
+        if self.restart_manager.is_tripped(idx) {
+            EventOutcome::Suppressed
+        } else {
+            EventOutcome::RestartScheduled
+        }
+    }
+
+    /// Start the cooldown of the groups of `interface` once its restart completed, however long
+    /// it takes, and recheck every member still bus-off once the cooldown ends
+    fn start_group_cooldown(&mut self, interface: CanInterfaceInfo) {
+        let restart_groups = Arc::clone(&self.restart_groups);
+        let restart_manager = Arc::clone(&self.restart_manager);
+        let recheck_tx = self.recheck_tx.clone();
+        self.tasks.spawn(async move {
+            while restart_manager.is_pending(interface.idx).await {
+                tokio::time::sleep(GROUP_RESTART_POLL_INTERVAL).await;
+            }
+            let Some((members, until)) = restart_groups
+                .lock()
+                .unwrap()
+                .finish_restart(&interface, Instant::now())
+            else {
+                return;
+            };
+            tokio::time::sleep_until(until).await;
+            for member in members {
+                if restart_manager.current_state(member.idx) == Some(CanState::BusOff)
+                    && let Some(tx) = recheck_tx.upgrade()
+                {
+                    let _ = tx.send(BusEvent::bus_off(
+                        member,
+                        BusEventSource::StateUpdate(CanState::BusOff),
+                    ));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::fake::FakeBackend, testing::can0};
+
+    fn netlink(state: CanState) -> BusEventSource {
+        BusEventSource::StateUpdate(state)
+    }
+
+    /// Event handler restarting through a fake backend
+    struct Harness {
+        handler: EventHandler,
+        backend: FakeBackend,
+        /// Events the handler sends itself, such as rechecks
+        rechecks: mpsc::UnboundedReceiver<BusEvent>,
+        _recheck_tx: mpsc::UnboundedSender<BusEvent>,
+    }
+
+    impl Harness {
+        fn new(options: MonitorOptions) -> Self {
+            let backend = FakeBackend::new();
+            let (recheck_tx, rechecks) = mpsc::unbounded_channel();
+            let mut restart_manager = RestartManager::with_control(Arc::new(backend.clone()))
+                .with_event_sender(recheck_tx.downgrade());
+            if let Some(window) = options.mute_after_restart {
+                restart_manager = restart_manager.with_mute_window(window);
+            }
+            let handler = EventHandler::new(
+                Arc::new(RwLock::new(Config::new(Duration::ZERO, vec![]))),
+                Arc::new(restart_manager),
+                Arc::new(Mutex::new(HashMap::new())),
+                OutageLog::new(8),
+                recheck_tx.downgrade(),
+                Liveness::new(),
+                options,
+            );
+            Self {
+                handler,
+                backend,
+                rechecks,
+                _recheck_tx: recheck_tx,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_events_leave_the_outage_open_until_recovered() {
+        let mut harness = Harness::new(MonitorOptions {
+            action: Action::Warn,
+            ..Default::default()
+        });
+        let handler = &mut harness.handler;
+
+        handler
+            .handle(BusEvent::bus_off(can0(), netlink(CanState::BusOff)))
+            .await;
+        let outcome = handler
+            .handle(BusEvent::restart(can0(), netlink(CanState::ErrorActive)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Ignored);
+        assert!(handler.outages.outages().is_empty());
+
+        let outcome = handler
+            .handle(BusEvent::recovered(can0(), netlink(CanState::ErrorActive)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Recovered);
+        assert_eq!(handler.outages.outages().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopped_events_caused_by_a_restart_are_suppressed() {
+        let mut harness = Harness::new(MonitorOptions {
+            restart_stopped: true,
+            ..Default::default()
+        });
+        let handler = &mut harness.handler;
+        handler
+            .restart_manager
+            .set_restart_method(1, RestartMethod::DownUp);
+        harness.backend.set_state(1, CanState::BusOff);
+
+        assert!(handler.restart_manager.restart_now(&can0()).await);
+        let outcome = handler
+            .handle(BusEvent::stopped(can0(), netlink(CanState::Stopped)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Suppressed);
+        assert!(!handler.restart_manager.is_pending(1).await);
+    }
+
+    #[tokio::test]
+    async fn removing_an_interface_aborts_its_recheck() {
+        let backend = FakeBackend::new();
+        let interface = CanInterfaceInfo {
+            idx: 4242,
+            name: "cantest0".to_string(),
+        };
+        let mut monitor = Monitor::start(
+            Config::new(Duration::ZERO, vec![interface.name.clone()]),
+            vec![interface.clone()],
+            MonitorOptions {
+                control: Some(Arc::new(backend.clone())),
+                state_source: StateSource::Sysfs,
+                debounce: Duration::from_secs(60),
+                ..Default::default()
+            },
+        )
+        .await;
+        let inject = |event| monitor.injector.upgrade().unwrap().send(event).unwrap();
+
+        // The second bus-off comes right after the first was restarted, so it is rechecked later
+        inject(BusEvent::bus_off(
+            interface.clone(),
+            netlink(CanState::BusOff),
+        ));
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while backend.restarts().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        inject(BusEvent::bus_off(
+            interface.clone(),
+            netlink(CanState::BusOff),
+        ));
+        let recheck = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let Some((_, task)) = monitor.rechecks.lock().unwrap().get(&4242) {
+                    return task.clone();
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        monitor
+            .reload(Config::new(Duration::ZERO, vec![]), vec![])
+            .await;
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !recheck.is_finished() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("recheck still running after its interface was removed");
+        assert!(monitor.rechecks.lock().unwrap().is_empty());
+        assert!(monitor.bus_off_spans.lock().unwrap().is_empty());
+        monitor.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn check_once_reads_and_restarts_through_the_control() {
+        let backend = FakeBackend::new();
+        let interfaces = [4242, 4243].map(|idx| CanInterfaceInfo {
+            idx,
+            name: format!("cantest{idx}"),
+        });
+        backend.set_state(4242, CanState::BusOff);
+        backend.set_state(4243, CanState::ErrorPassive);
+
+        assert!(
+            check_once(
+                &interfaces,
+                Arc::new(backend.clone()),
+                Action::Restart,
+                false
+            )
+            .await
+        );
+        assert_eq!(backend.restarts(), vec![4242]);
+
+        backend.set_state(4242, CanState::BusOff);
+        backend.set_stuck(4242, true);
+        assert!(
+            !check_once(
+                &interfaces,
+                Arc::new(backend.clone()),
+                Action::Restart,
+                false
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn renaming_an_interface_keeps_its_history() {
+        let backend = FakeBackend::new();
+        let interface = CanInterfaceInfo {
+            idx: 4242,
+            name: "cantest0".to_string(),
+        };
+        let mut monitor = Monitor::start(
+            Config::new(Duration::ZERO, vec![interface.name.clone()]),
+            vec![interface.clone()],
+            MonitorOptions {
+                control: Some(Arc::new(backend.clone())),
+                state_source: StateSource::Sysfs,
+                ..Default::default()
+            },
+        )
+        .await;
+        monitor.interface_history.record(&BusEvent::bus_off(
+            interface.clone(),
+            netlink(CanState::BusOff),
+        ));
+
+        let renamed = CanInterfaceInfo {
+            idx: 4242,
+            name: "cantest1".to_string(),
+        };
+        let changes = monitor
+            .reload(
+                Config::new(Duration::ZERO, vec![renamed.name.clone()]),
+                vec![renamed.clone()],
+            )
+            .await;
+        assert!(changes.added.is_empty());
+        assert!(changes.removed.is_empty());
+        assert_eq!(changes.renamed, vec![renamed]);
+        assert_eq!(monitor.interface_events(4242, 8).len(), 1);
+        monitor.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn check_once_leaves_interfaces_alone_when_only_warning_or_dry_running() {
+        let backend = FakeBackend::new();
+        let interfaces = [can0()];
+        backend.set_state(1, CanState::BusOff);
+
+        assert!(!check_once(&interfaces, Arc::new(backend.clone()), Action::Warn, false).await);
+        assert!(
+            !check_once(
+                &interfaces,
+                Arc::new(backend.clone()),
+                Action::Restart,
+                true
+            )
+            .await
+        );
+        assert!(backend.restarts().is_empty());
+        assert_eq!(backend.state(1).unwrap(), Some(CanState::BusOff));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesced_bus_off_burst_still_delivers_the_recovery() {
+        let mut harness = Harness::new(MonitorOptions {
+            coalesce_window: Duration::from_millis(250),
+            ..Default::default()
+        });
+        let handler = &mut harness.handler;
+        harness.backend.set_state(1, CanState::BusOff);
+
+        let outcome = handler
+            .handle(BusEvent::bus_off(can0(), netlink(CanState::BusOff)))
+            .await;
+        assert_eq!(outcome, EventOutcome::RestartScheduled);
+        for source in [
+            BusEventSource::Poll(CanState::BusOff),
+            BusEventSource::ErrorFrame(socketcan::CanErrorFrame::new_error(0x0040, &[]).unwrap()),
+        ] {
+            let outcome = handler.handle(BusEvent::bus_off(can0(), source)).await;
+            assert_eq!(outcome, EventOutcome::Ignored);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(harness.backend.restarts(), vec![1]);
+
+        let outcome = handler
+            .handle(BusEvent::recovered(can0(), netlink(CanState::ErrorActive)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Recovered);
+        assert_eq!(handler.outages.outages().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passive_dwell_restarts_without_reporting_a_bus_off() {
+        let metrics = Arc::new(Metrics::new());
+        let mut harness = Harness::new(MonitorOptions {
+            metrics: Some(Arc::clone(&metrics)),
+            ..Default::default()
+        });
+        harness.backend.set_state(1, CanState::ErrorPassive);
+
+        let outcome = harness
+            .handler
+            .handle(BusEvent::passive_dwell(can0(), Duration::from_secs(30)))
+            .await;
+        assert_eq!(outcome, EventOutcome::RestartScheduled);
+        harness.handler.restart_manager.shutdown().await;
+        assert_eq!(harness.backend.bring_downs(), vec![1]);
+        assert!(harness.handler.down_since.lock().unwrap().is_empty());
+        assert!(
+            !metrics
+                .render()
+                .contains("cansentinel_busoff_total{interface=\"can0\"} 1")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bus_offs_and_stops_inside_the_mute_window_are_suppressed() {
+        let mut harness = Harness::new(MonitorOptions {
+            mute_after_restart: Some(Duration::from_secs(2)),
+            restart_stopped: true,
+            ..Default::default()
+        });
+        let handler = &mut harness.handler;
+        harness.backend.set_state(1, CanState::BusOff);
+        assert!(handler.restart_manager.restart_now(&can0()).await);
+
+        let outcome = handler
+            .handle(BusEvent::bus_off(can0(), netlink(CanState::BusOff)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Suppressed);
+        let outcome = handler
+            .handle(BusEvent::stopped(can0(), netlink(CanState::Stopped)))
+            .await;
+        assert_eq!(outcome, EventOutcome::Suppressed);
+        assert!(!handler.restart_manager.is_pending(1).await);
+        assert!(handler.outages.outages().is_empty());
+
+        // Still stopped once the window is over, so that is reported again
+        harness.backend.set_state(1, CanState::Stopped);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let recheck = harness.rechecks.try_recv().unwrap();
+        assert_eq!(recheck.event_type, BusEventType::Stopped);
+        assert!(harness.rechecks.try_recv().is_err());
+    }
+}
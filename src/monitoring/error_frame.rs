@@ -1,41 +1,567 @@
 //! CAN error frame monitoring
 
-use crate::events::BusEventSource;
+use crate::backend::{CanBackend, CanControl, ErrorSocket, SocketCanBackend};
+use crate::events::{BusEventSource, ErrorCounters};
+use crate::learn::LearnRecorder;
+use crate::liveness::{BEAT_INTERVAL, Liveness, Task};
 use crate::{events::BusEvent, interface::CanInterfaceInfo};
-use socketcan::async_io::CanSocket;
-use socketcan::{CanError, CanErrorFrame, SocketOptions};
+use socketcan::errors::ControllerProblem;
+use socketcan::nl::CanState;
+use socketcan::{CanError, CanErrorFrame};
 use socketcan::{CanFrame, EmbeddedFrame, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use std::{fmt, io, str::FromStr};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::Instant;
+use tracing::{Instrument, debug, error, info, warn};
+
+/// How long to wait between reopen attempts once the interface has disappeared
+const DEVICE_GONE_RETRY: Duration = Duration::from_secs(30);
+
+/// How long to wait before reopening the socket after reading from it failed
+const READ_ERROR_DELAY: Duration = Duration::from_millis(200);
+
+/// Consecutive read errors between warnings, the rest are only logged at debug level
+const READ_ERROR_LOG_EVERY: u32 = 100;
+
+/// Shortest time between two counters-reported events of an interface
+const COUNTERS_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before retrying after the error frame socket couldn't be opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryBackoff {
+    /// Wait the same time after every failure
+    Fixed(Duration),
+    /// Wait `initial` after the first failure, doubling after every further one up to `max`
+    Exponential {
+        /// Delay after the first failure
+        initial: Duration,
+        /// Longest delay
+        max: Duration,
+    },
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self::Fixed(Duration::from_secs(5))
+    }
+}
+
+impl RetryBackoff {
+    /// Delay before the next attempt after `failures` consecutive failures, counting from 1
+    pub fn delay(&self, failures: u32) -> Duration {
+        match *self {
+            Self::Fixed(delay) => delay,
+            Self::Exponential { initial, max } => initial
+                .saturating_mul(1 << failures.saturating_sub(1).min(31))
+                .min(max),
+        }
+    }
+}
+
+/// Classes of error frames that can be promoted to
+/// [`ErrorThreshold`](crate::BusEventType::ErrorThreshold) events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Transmitted frames weren't acknowledged, e.g. no other node is on the bus
+    NoAck,
+    /// The controller reported a problem, such as error passive or a receive overflow
+    ControllerProblem,
+    /// A bit, form or stuff error on the bus
+    ProtocolViolation,
+    /// The transceiver reported a fault
+    Transceiver,
+    /// Arbitration was lost
+    LostArbitration,
+    /// A bus error not covered by another class
+    BusError,
+    /// A transmission timed out
+    TransmitTimeout,
+}
+
+impl ErrorClass {
+    /// Class of a decoded error frame, if it has one
+    pub fn of(error: &CanError) -> Option<Self> {
+        match error {
+            CanError::NoAck => Some(Self::NoAck),
+            CanError::ControllerProblem(_) => Some(Self::ControllerProblem),
+            CanError::ProtocolViolation { .. } => Some(Self::ProtocolViolation),
+            CanError::TransceiverError => Some(Self::Transceiver),
+            CanError::LostArbitration(_) => Some(Self::LostArbitration),
+            CanError::BusError => Some(Self::BusError),
+            CanError::TransmitTimeout => Some(Self::TransmitTimeout),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::NoAck => "no-ack",
+            Self::ControllerProblem => "controller-problem",
+            Self::ProtocolViolation => "protocol-violation",
+            Self::Transceiver => "transceiver",
+            Self::LostArbitration => "lost-arbitration",
+            Self::BusError => "bus-error",
+            Self::TransmitTimeout => "tx-timeout",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ErrorClass {
+    type Err = String;
+
+    /// Parse the names shown by [`Display`](fmt::Display), such as `no-ack`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        const ALL: [ErrorClass; 7] = [
+            ErrorClass::NoAck,
+            ErrorClass::ControllerProblem,
+            ErrorClass::ProtocolViolation,
+            ErrorClass::Transceiver,
+            ErrorClass::LostArbitration,
+            ErrorClass::BusError,
+            ErrorClass::TransmitTimeout,
+        ];
+        ALL.into_iter()
+            .find(|class| class.name() == value.trim())
+            .ok_or_else(|| {
+                let names: Vec<_> = ALL.iter().map(|class| class.name()).collect();
+                format!(
+                    "unknown error class '{}', expected one of {}",
+                    value,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Report an event once `count` error frames of a class arrive within any span of `window`
+///
+/// Each class is counted separately over a sliding window, and its count starts over after each
+/// event. Bus-off and restart frames have no [`ErrorClass`] and are never counted.
+#[derive(Debug, Clone)]
+pub struct ErrorThreshold {
+    /// Error classes counted, each separately
+    pub classes: Vec<ErrorClass>,
+    /// Error frames of one class that trigger an event
+    pub count: usize,
+    /// Time within which the frames must arrive
+    pub window: Duration,
+}
+
+/// Recent error frames of each watched class
+#[derive(Debug)]
+struct ThresholdCounter {
+    threshold: ErrorThreshold,
+    seen: HashMap<ErrorClass, VecDeque<Instant>>,
+}
+
+impl ThresholdCounter {
+    fn new(threshold: ErrorThreshold) -> Self {
+        Self {
+            threshold,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Count an error frame, returning true once its class reaches the threshold
+    ///
+    /// The count starts over after each report, so a steady stream of errors is reported once
+    /// per `count` frames.
+    fn record(&mut self, error: &CanError, now: Instant) -> bool {
+        let Some(class) = ErrorClass::of(error) else {
+            return false;
+        };
+        if !self.threshold.classes.contains(&class) {
+            return false;
+        }
+        let seen = self.seen.entry(class).or_default();
+        while seen
+            .front()
+            .is_some_and(|&at| now.duration_since(at) > self.threshold.window)
+        {
+            seen.pop_front();
+        }
+        seen.push_back(now);
+        if seen.len() < self.threshold.count.max(1) {
+            return false;
+        }
+        seen.clear();
+        true
+    }
+}
+
+/// Options for error frame monitoring
+#[derive(Debug, Clone, Default)]
+pub struct ErrorMonitorOptions {
+    /// Log every received error frame
+    pub verbose: bool,
+    /// With `verbose`, log at most one error frame in detail per interval and summarize the
+    /// rest, or every frame if zero (the default)
+    pub verbose_interval: Duration,
+    /// Requested receive buffer size (`SO_RCVBUF`) for the error frame socket, in bytes
+    ///
+    /// The kernel doubles the requested value to account for bookkeeping overhead and clamps it
+    /// to `net.core.rmem_max`, so the size actually achieved is logged once applied.
+    pub rx_buffer_bytes: Option<usize>,
+    /// Limits how many monitors may (re)open their socket at the same time
+    ///
+    /// Shared between all error frame monitors so that several flapping interfaces don't cause a
+    /// burst of socket operations.
+    pub open_limiter: Option<Arc<Semaphore>>,
+    /// Records every received error frame while in learn mode
+    pub recorder: Option<LearnRecorder>,
+    /// `CAN_RAW_LOOPBACK` for the error frame socket, or the kernel default (enabled) if unset
+    ///
+    /// Error frames are generated by the driver and reach every socket regardless of this
+    /// setting. It only decides whether frames transmitted by other sockets on this host are
+    /// seen, which the data frame filter drops anyway.
+    pub loopback: Option<bool>,
+    /// `CAN_RAW_RECV_OWN_MSGS` for the error frame socket, or the kernel default (disabled) if
+    /// unset
+    ///
+    /// The monitor never transmits, so enabling this gains nothing. Keeping it disabled ensures
+    /// nothing but driver-generated error frames can show up on the socket. Neither option hides
+    /// the genuine error frames a restart itself produces, such as `CAN_ERR_RESTARTED`.
+    pub recv_own_msgs: Option<bool>,
+    /// Error classes that produce an event when they repeat, besides bus-off and state changes
+    pub threshold: Option<ErrorThreshold>,
+    /// How long to wait before retrying after the socket couldn't be opened or configured
+    pub retry: RetryBackoff,
+    /// Reported to at least every [`BEAT_INTERVAL`] while the monitor runs
+    pub liveness: Option<Liveness>,
+    /// Report a bus-off once the interface has stayed error-passive this long, so it gets
+    /// restarted even though it never went bus-off
+    ///
+    /// Checked at least every [`BEAT_INTERVAL`].
+    pub passive_restart_after: Option<Duration>,
+}
+
+/// Limits verbose error frame logging to one frame per interval, counting the rest
+#[derive(Debug)]
+struct LogLimiter {
+    interval: Duration,
+    /// When a frame was last logged in detail
+    last: Option<Instant>,
+    /// Frames not logged since
+    suppressed: u64,
+}
+
+impl LogLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Whether a frame arriving at `now` is logged, counting it as suppressed if not
+    fn allow(&mut self, now: Instant) -> bool {
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            self.suppressed += 1;
+            return false;
+        }
+        self.summarize();
+        self.last = Some(now);
+        true
+    }
+
+    /// Log how many frames were suppressed, once the interval is over
+    fn flush(&mut self, now: Instant) {
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) >= self.interval)
+        {
+            self.summarize();
+        }
+    }
+
+    fn summarize(&mut self) {
+        if self.suppressed > 0 {
+            debug!(
+                "Suppressed {} more error frames within {:?}",
+                self.suppressed, self.interval
+            );
+            self.suppressed = 0;
+        }
+    }
+}
+
+/// Limits counters-reported events to one per [`COUNTERS_REPORT_INTERVAL`], so an error storm
+/// doesn't flood the event loop
+#[derive(Debug, Default)]
+struct CounterReports {
+    /// Counters last reported or carried by another event
+    reported: Option<ErrorCounters>,
+    /// When counters were last reported
+    last: Option<Instant>,
+    /// Changed counters not reported yet, with the frame that brought them
+    pending: Option<(ErrorCounters, CanErrorFrame)>,
+}
+
+impl CounterReports {
+    /// Counters to report now that `frame` brought `counters` at `now`, if any
+    fn update(
+        &mut self,
+        counters: ErrorCounters,
+        frame: CanErrorFrame,
+        now: Instant,
+    ) -> Option<(ErrorCounters, CanErrorFrame)> {
+        self.pending = (self.reported != Some(counters)).then_some((counters, frame));
+        self.due(now)
+    }
+
+    /// Counters held back earlier, once they may be reported
+    fn due(&mut self, now: Instant) -> Option<(ErrorCounters, CanErrorFrame)> {
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < COUNTERS_REPORT_INTERVAL)
+        {
+            return None;
+        }
+        let (counters, frame) = self.pending.take()?;
+        self.reported = Some(counters);
+        self.last = Some(now);
+        Some((counters, frame))
+    }
+
+    /// Note counters another event carries, which needn't be reported again
+    fn carried(&mut self, counters: ErrorCounters) {
+        self.reported = Some(counters);
+        self.pending = None;
+    }
+}
+
+/// Tracks how long an interface has been error-passive, for
+/// [`ErrorMonitorOptions::passive_restart_after`]
+#[derive(Debug)]
+struct PassiveTimer {
+    after: Duration,
+    /// When the interface last went error-passive, if it still is
+    since: Option<Instant>,
+}
+
+impl PassiveTimer {
+    fn new(after: Duration) -> Self {
+        Self { after, since: None }
+    }
+
+    /// Follow the state changes reported by an error frame
+    fn record(&mut self, error: &CanError, now: Instant) {
+        match error {
+            CanError::ControllerProblem(
+                ControllerProblem::ReceiveErrorPassive | ControllerProblem::TransmitErrorPassive,
+            ) => {
+                self.since.get_or_insert(now);
+            }
+            CanError::ControllerProblem(
+                ControllerProblem::ReceiveErrorWarning | ControllerProblem::TransmitErrorWarning,
+            )
+            | CanError::BusOff
+            | CanError::Restarted => self.since = None,
+            _ => {}
+        }
+    }
+
+    /// A passive-dwell event, once the error-passive state has lasted too long
+    fn check(
+        &mut self,
+        control: &impl CanControl,
+        interface: &CanInterfaceInfo,
+    ) -> Option<BusEvent> {
+        let dwell = self.since?.elapsed();
+        if dwell < self.after {
+            return None;
+        }
+        self.since = None;
+        // Drivers don't always send a frame when the counters drop again, so ask the interface
+        match control.state(interface.idx) {
+            Ok(Some(CanState::ErrorPassive)) | Ok(None) | Err(_) => {}
+            Ok(Some(state)) => {
+                debug!("No longer error-passive ({:?})", state);
+                return None;
+            }
+        }
+        warn!("Error-passive for {:?}, restarting it", dwell);
+        Some(BusEvent::passive_dwell(interface.clone(), dwell))
+    }
+}
 
 /// Monitor error frames on a specific CAN interface
 pub async fn monitor_interface_errors(
     tx: mpsc::UnboundedSender<BusEvent>,
     interface: CanInterfaceInfo,
-    verbose: bool,
+    options: ErrorMonitorOptions,
+) {
+    monitor_interface_errors_with(&SocketCanBackend, tx, interface, options).await
+}
+
+/// Monitor error frames on a specific CAN interface using the given backend
+pub async fn monitor_interface_errors_with<B: CanBackend>(
+    backend: &B,
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interface: CanInterfaceInfo,
+    options: ErrorMonitorOptions,
 ) {
+    let span = interface.span();
+    watch_error_frames(backend, tx, interface, options)
+        .instrument(span)
+        .await
+}
+
+async fn watch_error_frames<B: CanBackend>(
+    backend: &B,
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interface: CanInterfaceInfo,
+    options: ErrorMonitorOptions,
+) {
+    let verbose = options.verbose;
+    let task = Task::ErrorFrames(interface.name.clone());
+    let beat = || {
+        if let Some(liveness) = &options.liveness {
+            liveness.beat(&task);
+        }
+    };
+    beat();
+    let mut thresholds = options.threshold.clone().map(ThresholdCounter::new);
+    let mut passive = options.passive_restart_after.map(PassiveTimer::new);
+    let mut log_limiter = LogLimiter::new(options.verbose_interval);
+    let mut counter_reports = CounterReports::default();
+    // Set while the interface is removed, so it's only reported once
+    let mut device_gone = false;
+    // Consecutive failures to open the socket, for the retry backoff
+    let mut open_failures = 0;
+    // Consecutive read errors without a frame in between, to keep a failing socket from
+    // flooding the log
+    let mut read_errors = 0;
     loop {
-        match CanSocket::open(&interface.name) {
+        let mut read_failed = false;
+        // Error frames look the same either way, but an FD interface gets an FD socket. Checked
+        // on every open, as the interface may have been reconfigured meanwhile.
+        let fd = match backend.is_fd(interface.idx) {
+            Ok(fd) => fd,
+            Err(e) => {
+                debug!("Could not tell whether the interface is CAN FD: {}", e);
+                false
+            }
+        };
+        let opened = {
+            let _permit = match &options.open_limiter {
+                Some(limiter) => limiter.acquire().await.ok(),
+                None => None,
+            };
+            if fd {
+                backend.open_fd(&interface.name)
+            } else {
+                backend.open(&interface.name)
+            }
+        };
+
+        match opened {
             Ok(socket) => {
+                if device_gone {
+                    info!("Interface is back");
+                    device_gone = false;
+                }
+
                 // Configure socket to receive only error frames and drop all regular data frames
                 if let Err(e) = socket
                     .set_error_filter_accept_all()
                     .and_then(|_| socket.set_filter_drop_all())
                 {
-                    println!(
-                        "Failed to configure socket filters for {}: {}",
-                        interface.name, e
-                    );
+                    error!("Failed to configure socket filters: {}", e);
+                    open_failures += 1;
+                    let delay = options.retry.delay(open_failures);
+                    info!("Retrying in {:?}...", delay);
+                    sleep_beating(delay, beat).await;
                     continue;
                 }
+                open_failures = 0;
 
-                println!("Started error monitoring for interface: {}", interface.name);
+                if let Some(enabled) = options.loopback
+                    && let Err(e) = socket.set_loopback(enabled)
+                {
+                    warn!("Failed to set CAN_RAW_LOOPBACK: {}", e);
+                }
+                if let Some(enabled) = options.recv_own_msgs
+                    && let Err(e) = socket.set_recv_own_msgs(enabled)
+                {
+                    warn!("Failed to set CAN_RAW_RECV_OWN_MSGS: {}", e);
+                }
+
+                if let Some(requested) = options.rx_buffer_bytes {
+                    match socket.set_rx_buffer(requested) {
+                        Ok(actual) => info!(
+                            "Requested {} byte receive buffer, kernel reports {} bytes",
+                            requested, actual
+                        ),
+                        Err(e) => warn!("Failed to set receive buffer size: {}", e),
+                    }
+                }
+
+                if let Err(e) = socket.enable_timestamps() {
+                    debug!(
+                        "Kernel timestamps unavailable, events won't carry them: {}",
+                        e
+                    );
+                }
+
+                if fd {
+                    info!("Started error monitoring in CAN FD mode");
+                } else {
+                    info!("Started error monitoring");
+                }
 
                 loop {
-                    match socket.read_frame().await {
+                    // Woken up regularly on a quiet bus, to report in and check the passive timer
+                    let read = tokio::time::timeout(BEAT_INTERVAL, socket.read_frame()).await;
+                    beat();
+                    if verbose {
+                        log_limiter.flush(Instant::now());
+                    }
+                    if let Some(event) = passive
+                        .as_mut()
+                        .and_then(|passive| passive.check(backend, &interface))
+                        && tx.send(event).is_err()
+                    {
+                        info!("Channel closed, stopping monitoring");
+                        return;
+                    }
+                    if let Some((counters, frame)) = counter_reports.due(Instant::now())
+                        && tx
+                            .send(BusEvent::counters_reported(
+                                interface.clone(),
+                                BusEventSource::ErrorFrame(frame),
+                                counters,
+                            ))
+                            .is_err()
+                    {
+                        info!("Channel closed, stopping monitoring");
+                        return;
+                    }
+                    let Ok(read) = read else {
+                        continue;
+                    };
+                    match read {
                         Ok(CanFrame::Error(frame)) => {
-                            if verbose {
-                                log_can_error(&interface, &frame);
+                            read_errors = 0;
+                            let timestamp = socket.last_rx_timestamp().ok();
+                            if let Some(recorder) = &options.recorder {
+                                recorder.record_error_frame(&interface);
+                            }
+                            if verbose && log_limiter.allow(Instant::now()) {
+                                log_can_error(&frame);
                             }
 
                             let event = match frame.into_error() {
@@ -47,64 +573,433 @@ pub async fn monitor_interface_errors(
                                     interface.clone(),
                                     BusEventSource::ErrorFrame(frame),
                                 )),
+                                CanError::ControllerProblem(
+                                    ControllerProblem::ReceiveErrorWarning
+                                    | ControllerProblem::TransmitErrorWarning,
+                                ) => Some(BusEvent::error_warning(
+                                    interface.clone(),
+                                    BusEventSource::ErrorFrame(frame),
+                                )),
+                                CanError::ControllerProblem(
+                                    ControllerProblem::ReceiveErrorPassive
+                                    | ControllerProblem::TransmitErrorPassive,
+                                ) => Some(BusEvent::error_passive(
+                                    interface.clone(),
+                                    BusEventSource::ErrorFrame(frame),
+                                )),
                                 _ => None,
                             };
+                            let counters = ErrorCounters::from_error_frame(&frame);
+                            let event = match (event, counters) {
+                                (None, Some(counters)) => counter_reports
+                                    .update(counters, frame, Instant::now())
+                                    .map(|(counters, frame)| {
+                                        BusEvent::counters_reported(
+                                            interface.clone(),
+                                            BusEventSource::ErrorFrame(frame),
+                                            counters,
+                                        )
+                                    }),
+                                (Some(event), Some(counters)) => {
+                                    counter_reports.carried(counters);
+                                    Some(event.with_error_counters(Some(counters)))
+                                }
+                                (event, None) => event,
+                            };
+
+                            if let Some(passive) = passive.as_mut() {
+                                passive.record(&frame.into_error(), Instant::now());
+                            }
+                            let threshold_reached = thresholds.as_mut().is_some_and(|counter| {
+                                counter.record(&frame.into_error(), Instant::now())
+                            });
+                            let threshold_event = threshold_reached.then(|| {
+                                BusEvent::error_threshold(
+                                    interface.clone(),
+                                    BusEventSource::ErrorFrame(frame),
+                                )
+                            });
 
-                            if let Some(event) = event
-                                && tx.send(event).is_err()
-                            {
-                                println!("Channel closed, stopping monitoring");
-                                return;
+                            for event in event.into_iter().chain(threshold_event) {
+                                if tx.send(event.with_kernel_timestamp(timestamp)).is_err() {
+                                    info!("Channel closed, stopping monitoring");
+                                    return;
+                                }
                             }
                         }
-                        Ok(_) => (), // Ignore non-error frames
+                        Ok(_) => read_errors = 0, // Ignore non-error frames
+                        Err(e) if is_device_gone(&e) => {
+                            warn!(
+                                "Interface removed, checking every {:?} for it to reappear",
+                                DEVICE_GONE_RETRY
+                            );
+                            device_gone = true;
+                            break;
+                        }
                         Err(e) => {
-                            println!("{}: error reading from socket: {}", interface.name, e);
+                            read_errors += 1;
+                            if read_errors == 1 {
+                                warn!("Error reading from socket, reopening it: {}", e);
+                            } else if read_errors % READ_ERROR_LOG_EVERY == 0 {
+                                warn!(
+                                    "Error reading from socket, {} times in a row: {}",
+                                    read_errors, e
+                                );
+                            } else {
+                                debug!("Error reading from socket: {}", e);
+                            }
+                            read_failed = true;
                             break;
                         }
                     }
                 }
             }
+            Err(e) if is_device_gone(&e) => {
+                if !device_gone {
+                    warn!(
+                        "Interface not present, checking every {:?} for it to appear",
+                        DEVICE_GONE_RETRY
+                    );
+                    device_gone = true;
+                }
+            }
             Err(e) => {
-                println!("{}: failed to open socket: {}", interface.name, e);
+                open_failures += 1;
+                let delay = options.retry.delay(open_failures);
+                warn!("Failed to open socket: {}. retrying in {:?}...", e, delay);
+                sleep_beating(delay, beat).await;
+                continue;
             }
         }
 
-        // Wait before retrying if the socket failed
-        println!(
-            "{}: failed to open or read from socket for monitoring. retrying in 5 seconds...",
-            interface.name
-        );
-        tokio::time::sleep(Duration::from_secs(5)).await;
+        if device_gone {
+            sleep_beating(DEVICE_GONE_RETRY, beat).await;
+        } else if read_failed {
+            // Short, but keeps a persistently failing socket from spinning
+            tokio::time::sleep(READ_ERROR_DELAY).await;
+        }
     }
 }
 
+/// Sleep for `duration`, reporting in with `beat` at least every [`BEAT_INTERVAL`] meanwhile
+async fn sleep_beating(duration: Duration, beat: impl Fn()) {
+    let deadline = Instant::now() + duration;
+    loop {
+        beat();
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        tokio::time::sleep((deadline - now).min(BEAT_INTERVAL)).await;
+    }
+}
+
+/// Whether an I/O error means the interface no longer exists
+fn is_device_gone(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(nix::libc::ENODEV) | Some(nix::libc::ENXIO)
+    )
+}
+
 /// Log CAN error events with detailed analysis
-fn log_can_error(interface: &CanInterfaceInfo, frame: &CanErrorFrame) {
-    println!(
-        "CAN ERROR on {}: ID=0x{:03X}, DLC={}, Data={:02X?}",
-        interface.name,
+fn log_can_error(frame: &CanErrorFrame) {
+    debug!(
+        "CAN ERROR: ID=0x{:03X}, DLC={}, Data={:02X?}",
         frame.raw_id(),
         frame.len(),
         frame.data()
     );
 
     // Additional error frame analysis based on CAN error frame format
+    let counters = ErrorCounters::from_error_frame(frame);
     use socketcan::errors::CanError::*;
     match frame.into_error() {
-        TransmitTimeout => println!("  -> TX timeout (bus-off recovery in progress)"),
-        LostArbitration(_) => println!("  -> Lost arbitration"),
-        ControllerProblem(_) => println!("  -> Controller problems"),
-        ProtocolViolation {
-            vtype: _,
-            location: _,
-        } => println!("  -> Protocol violations"),
-        TransceiverError => println!("  -> Transceiver status"),
-        NoAck => println!("  -> No acknowledgment on transmission"),
-        BusOff => println!("  -> Bus off"),
-        BusError => println!("  -> Bus error"),
-        Restarted => println!("  -> Bus restarted"),
-        Unknown(0x204) => println!("  -> Error counters"),
-        _ => println!("  -> Other error condition"),
+        TransmitTimeout => debug!("  -> TX timeout (bus-off recovery in progress)"),
+        LostArbitration(0) => debug!("  -> Lost arbitration"),
+        LostArbitration(bit) => debug!("  -> Lost arbitration in bit {}", bit),
+        ControllerProblem(problem) => debug!("  -> Controller problem: {}", problem),
+        ProtocolViolation { vtype, location } => {
+            debug!("  -> Protocol violation: {} at {}", vtype, location)
+        }
+        TransceiverError => debug!("  -> Transceiver status"),
+        NoAck => debug!("  -> No acknowledgment on transmission"),
+        BusOff => debug!("  -> Bus off"),
+        BusError => debug!("  -> Bus error"),
+        Restarted => debug!("  -> Bus restarted"),
+        // Described below
+        _ if counters.is_some() => (),
+        _ => debug!("  -> Other error condition"),
+    }
+    if let Some(counters) = counters {
+        debug!("  -> Error counters: TX={} RX={}", counters.tx, counters.rx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::fake::FakeBackend,
+        events::BusEventType,
+        testing::{EventSink, can0},
+    };
+    use tokio::task::JoinHandle;
+
+    /// CAN_ERR_BUSOFF from linux/can/error.h
+    const CAN_ERR_BUSOFF: u32 = 0x0040;
+    /// CAN_ERR_RESTARTED from linux/can/error.h
+    const CAN_ERR_RESTARTED: u32 = 0x0100;
+    /// CAN_ERR_ACK from linux/can/error.h
+    const CAN_ERR_ACK: u32 = 0x0020;
+    /// CAN_ERR_CRTL | CAN_ERR_CNT from linux/can/error.h
+    const CAN_ERR_CRTL_CNT: u32 = 0x0204;
+
+    fn error_frame(class: u32) -> CanErrorFrame {
+        CanErrorFrame::new_error(class, &[]).unwrap()
+    }
+
+    /// Monitor error frames of can0 on `backend` until the returned task is aborted
+    fn watch_errors(
+        backend: &FakeBackend,
+        options: ErrorMonitorOptions,
+    ) -> (EventSink, JoinHandle<()>) {
+        let (tx, sink) = EventSink::channel();
+        let backend = backend.clone();
+        let monitor = tokio::spawn(async move {
+            monitor_interface_errors_with(&backend, tx, can0(), options).await;
+        });
+        (sink, monitor)
+    }
+
+    fn counter(count: usize) -> ThresholdCounter {
+        ThresholdCounter::new(ErrorThreshold {
+            classes: vec![ErrorClass::NoAck, ErrorClass::BusError],
+            count,
+            window: Duration::from_secs(1),
+        })
+    }
+
+    #[test]
+    fn threshold_slides_its_window_and_counts_each_class() {
+        let start = Instant::now();
+        let mut counter = counter(3);
+        assert!(!counter.record(&CanError::NoAck, start));
+        assert!(!counter.record(&CanError::BusError, start));
+        assert!(!counter.record(&CanError::NoAck, start + Duration::from_millis(900)));
+        // The first frame has left the window
+        assert!(!counter.record(&CanError::NoAck, start + Duration::from_millis(1500)));
+        assert!(counter.record(&CanError::NoAck, start + Duration::from_millis(1600)));
+        // Starts over after the event
+        assert!(!counter.record(&CanError::NoAck, start + Duration::from_millis(1700)));
+        assert!(!counter.record(&CanError::BusOff, start + Duration::from_millis(1700)));
+    }
+
+    #[test]
+    fn log_limiter_logs_one_frame_per_interval() {
+        let start = Instant::now();
+        let mut limiter = LogLimiter::new(Duration::from_secs(1));
+        assert!(limiter.allow(start));
+        assert!(!limiter.allow(start + Duration::from_millis(500)));
+        assert!(!limiter.allow(start + Duration::from_millis(999)));
+        limiter.flush(start + Duration::from_millis(999));
+        assert_eq!(limiter.suppressed, 2);
+
+        limiter.flush(start + Duration::from_secs(1));
+        assert_eq!(limiter.suppressed, 0);
+        assert!(limiter.allow(start + Duration::from_secs(1)));
+        assert!(!limiter.allow(start + Duration::from_millis(1500)));
+
+        // Zero logs every frame
+        let mut unlimited = LogLimiter::new(Duration::ZERO);
+        assert!(unlimited.allow(start));
+        assert!(unlimited.allow(start));
+        assert_eq!(unlimited.suppressed, 0);
+    }
+
+    #[test]
+    fn counter_reports_are_limited_to_one_per_interval() {
+        let frame = CanErrorFrame::new_error(0, &[0, 0, 0, 0, 0, 0, 100, 0]).unwrap();
+        let counters = |tx| ErrorCounters { tx, rx: 0 };
+        let start = Instant::now();
+        let mut reports = CounterReports::default();
+
+        assert!(reports.update(counters(8), frame, start).is_some());
+        // Unchanged counters are never reported, changed ones wait for the interval
+        assert!(reports.update(counters(8), frame, start).is_none());
+        assert!(reports.update(counters(16), frame, start).is_none());
+        assert!(reports.update(counters(24), frame, start).is_none());
+        assert!(reports.due(start + Duration::from_millis(500)).is_none());
+        let (reported, _) = reports.due(start + COUNTERS_REPORT_INTERVAL).unwrap();
+        assert_eq!(reported, counters(24));
+        assert!(reports.due(start + 2 * COUNTERS_REPORT_INTERVAL).is_none());
+
+        // Counters carried by another event aren't reported again
+        reports.carried(counters(32));
+        assert!(
+            reports
+                .update(counters(32), frame, start + 3 * COUNTERS_REPORT_INTERVAL)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn exponential_retry_doubles_up_to_max() {
+        let retry = RetryBackoff::Exponential {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        let delays: Vec<_> = (1..=6).map(|failures| retry.delay(failures)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        assert_eq!(retry.delay(u32::MAX), Duration::from_secs(1));
+        assert_eq!(RetryBackoff::default().delay(10), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn error_frames_map_to_bus_events() {
+        let backend = FakeBackend::new();
+        let (mut sink, monitor) = watch_errors(&backend, ErrorMonitorOptions::default());
+
+        backend.push_frame("can0", error_frame(CAN_ERR_ACK));
+        backend.push_frame("can0", error_frame(CAN_ERR_BUSOFF));
+        backend.push_frame("can0", error_frame(CAN_ERR_RESTARTED));
+
+        sink.expect_bus_off(&can0(), Duration::from_secs(1)).await;
+        sink.expect_restart(&can0(), Duration::from_secs(1)).await;
+        sink.assert_no_events(Duration::from_millis(100)).await;
+
+        monitor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn error_counter_frames_are_decoded() {
+        let backend = FakeBackend::new();
+        let (mut sink, monitor) = watch_errors(&backend, ErrorMonitorOptions::default());
+
+        let counters =
+            |tx, rx| CanErrorFrame::new_error(CAN_ERR_CRTL_CNT, &[0, 0, 0, 0, 0, 0, tx, rx]);
+        backend.push_frame("can0", counters(96, 130).unwrap());
+        // Unchanged, not reported again
+        backend.push_frame("can0", counters(96, 130).unwrap());
+        backend.push_frame("can0", counters(97, 130).unwrap());
+
+        let event = sink
+            .expect(
+                BusEventType::CountersReported,
+                &can0(),
+                Duration::from_secs(1),
+            )
+            .await;
+        assert_eq!(
+            event.error_counters,
+            Some(ErrorCounters { tx: 96, rx: 130 })
+        );
+        // Held back for a second after the first report
+        let event = sink
+            .expect(
+                BusEventType::CountersReported,
+                &can0(),
+                COUNTERS_REPORT_INTERVAL + BEAT_INTERVAL,
+            )
+            .await;
+        assert_eq!(
+            event.error_counters,
+            Some(ErrorCounters { tx: 97, rx: 130 })
+        );
+        sink.assert_no_events(Duration::from_millis(100)).await;
+
+        monitor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_error_frames_reach_threshold() {
+        let backend = FakeBackend::new();
+        let options = ErrorMonitorOptions {
+            threshold: Some(ErrorThreshold {
+                classes: vec![ErrorClass::NoAck],
+                count: 3,
+                window: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        };
+        let (mut sink, monitor) = watch_errors(&backend, options);
+
+        backend.push_frame("can0", error_frame(CAN_ERR_ACK));
+        backend.push_frame("can0", error_frame(CAN_ERR_ACK));
+        backend.push_frame("can0", error_frame(CAN_ERR_BUSOFF));
+        backend.push_frame("can0", error_frame(CAN_ERR_ACK));
+
+        sink.expect_bus_off(&can0(), Duration::from_secs(1)).await;
+        sink.expect(
+            BusEventType::ErrorThreshold,
+            &can0(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(sink.drain().is_empty());
+
+        monitor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fd_interfaces_get_an_fd_socket() {
+        let backend = FakeBackend::new();
+        backend.set_fd(1, true);
+        let (mut sink, monitor) = watch_errors(&backend, ErrorMonitorOptions::default());
+
+        backend.push_frame("can0", error_frame(CAN_ERR_BUSOFF));
+        sink.expect_bus_off(&can0(), Duration::from_secs(1)).await;
+        assert_eq!(backend.fd_opens(), vec!["can0".to_string()]);
+
+        monitor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sustained_error_passive_is_reported_as_passive_dwell() {
+        let backend = FakeBackend::new();
+        let options = ErrorMonitorOptions {
+            passive_restart_after: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        backend.set_state(1, CanState::ErrorPassive);
+        let (mut sink, monitor) = watch_errors(&backend, options);
+
+        // CAN_ERR_CRTL with CAN_ERR_CRTL_RX_PASSIVE
+        backend.push_frame(
+            "can0",
+            CanErrorFrame::new_error(0x0004, &[0, 0x10]).unwrap(),
+        );
+        sink.expect(BusEventType::ErrorPassive, &can0(), Duration::from_secs(1))
+            .await;
+        let dwell = sink
+            .expect(BusEventType::PassiveDwell, &can0(), 2 * BEAT_INTERVAL)
+            .await;
+        assert_eq!(dwell.event_source.name(), "passive_dwell");
+
+        monitor.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn monitor_reports_in_and_goes_stale_once_stopped() {
+        let backend = FakeBackend::new();
+        let liveness = Liveness::new();
+        let options = ErrorMonitorOptions {
+            liveness: Some(liveness.clone()),
+            ..Default::default()
+        };
+
+        let (_sink, monitor) = watch_errors(&backend, options);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let task = Task::ErrorFrames("can0".to_string());
+        assert!(liveness.age(&task).is_some());
+        assert!(liveness.stale(Duration::from_secs(5)).is_empty());
+
+        monitor.abort();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(liveness.stale(Duration::from_millis(50))[0].0, task);
     }
 }
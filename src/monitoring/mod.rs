@@ -2,6 +2,13 @@
 
 pub mod error_frame;
 pub mod netlink;
+pub mod poll;
+pub mod sysfs;
 
-pub use error_frame::monitor_interface_errors;
-pub use netlink::monitor_netlink;
+pub use error_frame::{
+    ErrorClass, ErrorMonitorOptions, ErrorThreshold, RetryBackoff, monitor_interface_errors,
+    monitor_interface_errors_with,
+};
+pub use netlink::{monitor_netlink, monitor_netlink_groups};
+pub use poll::{monitor_poll, monitor_poll_with};
+pub use sysfs::monitor_sysfs;
@@ -1,100 +1,557 @@
 //! Netlink-based CAN interface monitoring
+//!
+//! CAN drivers don't send a dedicated notification on controller state changes. Instead, going
+//! bus-off takes the carrier down (`can_bus_off()` calls `netif_carrier_off()`), and restarting or
+//! bringing the interface up or down changes carrier or operstate. Each of those triggers an
+//! `RTM_NEWLINK` broadcast on `RTNLGRP_LINK` carrying the current `IFLA_LINKINFO`, which is where
+//! the CAN state is read from. That single group is therefore sufficient on every kernel with
+//! `IFLA_CAN_STATE` (2.6.31+). Transitions that don't touch the carrier, such as entering
+//! ERROR_WARNING or ERROR_PASSIVE, are never broadcast on their own, so those mostly come from
+//! error frames; they are still reported here when a link message happens to carry them.
+//!
+//! The same messages carry the netdevice flags. An interface that is up (`IFF_UP`) but not
+//! running (`IFF_RUNNING`) while its controller isn't bus-off has lost carrier for another
+//! reason, such as a USB adapter being unplugged, which is reported as
+//! [`CarrierLost`](crate::BusEventType::CarrierLost) rather than left to look like silence.
+//!
+//! When link changes arrive faster than they are read, the kernel drops them and reports ENOBUFS.
+//! The state of every monitored interface is then read afresh, so no transition is missed for
+//! good.
+//!
+//! The socket times out every [`BEAT_INTERVAL`] on a quiet bus, so the listener can report to
+//! [`Liveness`] that it is still running.
+//!
+//! Joining `RTNLGRP_LINK` can be denied in restricted containers. Bus-off is then only detected
+//! from error frames, which is loudly warned about rather than silently accepted.
 
 use crate::{
-    events::{BusEvent, BusEventSource},
-    interface::CanInterfaceInfo,
+    backend::PERMISSION_HINT,
+    events::{BusEvent, BusEventSource, ErrorCounters},
+    interface::{CanInterfaceInfo, SharedInterfaces, StateCache},
+    liveness::{BEAT_INTERVAL, Liveness, Task},
 };
-use nix::libc::{ARPHRD_CAN, RTNLGRP_LINK};
+use nix::libc::{ARPHRD_CAN, ENOBUFS, RTNLGRP_LINK};
 use socketcan::{InterfaceCanParams, nl::CanState};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+/// How long to wait before reconnecting after the netlink socket failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Receive buffer requested for the netlink socket, in bytes
+const RECEIVE_BUFFER: usize = 1024 * 1024;
+
+/// Netlink multicast groups subscribed to by default
+pub const DEFAULT_GROUPS: &[u32] = &[RTNLGRP_LINK];
+
+/// Every link the kernel knows of, CAN or not, read with a single RTNL dump request
+pub(crate) fn dump_links() -> io::Result<Vec<neli::rtnl::Ifinfomsg>> {
+    use neli::{
+        consts::{
+            nl::{NlTypeWrapper, NlmF, NlmFFlags},
+            rtnl::{Arphrd, IffFlags, RtAddrFamily, Rtm},
+            socket::NlFamily,
+        },
+        nl::{NlPayload, Nlmsghdr},
+        rtnl::Ifinfomsg,
+        socket::NlSocketHandle,
+        types::RtBuffer,
+    };
+
+    let mut socket = NlSocketHandle::connect(NlFamily::Route, None, &[])?;
+    let request = Ifinfomsg::new(
+        RtAddrFamily::Unspecified,
+        Arphrd::from(0u16),
+        0,
+        IffFlags::empty(),
+        IffFlags::empty(),
+        RtBuffer::new(),
+    );
+    socket
+        .send(Nlmsghdr::new(
+            None,
+            Rtm::Getlink,
+            NlmFFlags::new(&[NlmF::Request, NlmF::Dump]),
+            None,
+            None,
+            NlPayload::Payload(request),
+        ))
+        .map_err(crate::backend::nl_error)?;
+
+    let mut links = Vec::new();
+    for msg in socket.iter::<NlTypeWrapper, Ifinfomsg>(false) {
+        let msg = msg.map_err(crate::backend::nl_error)?;
+        if let NlPayload::Payload(link) = msg.nl_payload {
+            links.push(link);
+        }
+    }
+    Ok(links)
+}
+
+/// Controller state of every CAN interface, by interface index, read with a single netlink dump
+///
+/// Cheaper than reading each interface's state on its own when there are many. Interfaces whose
+/// driver doesn't report a state are left out.
+pub fn dump_states() -> io::Result<HashMap<u32, CanState>> {
+    use neli::consts::rtnl::Ifla;
+
+    Ok(dump_links()?
+        .into_iter()
+        .filter(|link| u16::from(link.ifi_type) == ARPHRD_CAN)
+        .filter_map(|link| {
+            let state = link
+                .rtattrs
+                .get_attr_handle()
+                .get_attribute(Ifla::Linkinfo)
+                .and_then(|attr| InterfaceCanParams::try_from(attr).ok())?
+                .state?;
+            Some((link.ifi_index as u32, state))
+        })
+        .collect())
+}
 
 /// Runs the blocking netlink monitoring loop
+///
+/// Returns [`io::ErrorKind::PermissionDenied`] if multicast subscription isn't allowed.
 pub fn monitor_netlink(
     tx: mpsc::UnboundedSender<BusEvent>,
     interfaces: Vec<CanInterfaceInfo>,
     verbose: bool,
-) {
+) -> io::Result<()> {
+    monitor_netlink_groups(tx, interfaces, verbose, DEFAULT_GROUPS)
+}
+
+/// Runs the blocking netlink monitoring loop subscribed to the given multicast groups
+///
+/// Only link messages are interpreted; messages from other groups that don't parse as link info
+/// are skipped. If the socket fails, it is reopened and resubscribed to `groups` after a delay.
+/// Returns once the event channel is closed, or with an error if subscribing isn't permitted.
+pub fn monitor_netlink_groups(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: Vec<CanInterfaceInfo>,
+    verbose: bool,
+    groups: &[u32],
+) -> io::Result<()> {
+    monitor_netlink_shared(
+        tx,
+        Arc::new(RwLock::new(interfaces)),
+        StateCache::default(),
+        verbose,
+        groups,
+        None,
+    )
+}
+
+/// Runs the blocking netlink monitoring loop for a set of interfaces that can change while it
+/// runs, recording every state reported in `cache` and reporting in to `liveness`
+pub(crate) fn monitor_netlink_shared(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: SharedInterfaces,
+    cache: StateCache,
+    verbose: bool,
+    groups: &[u32],
+    liveness: Option<Liveness>,
+) -> io::Result<()> {
     use neli::{
         consts::{
-            rtnl::{Ifla, Rtm},
+            rtnl::{Iff, Ifla, Rtm},
             socket::NlFamily,
         },
+        err::NlError,
         rtnl::Ifinfomsg,
         socket,
     };
 
-    let interfaces = {
-        let mut interfaces: Vec<u32> = interfaces.into_iter().map(|i| i.idx).collect();
-        interfaces.sort();
-        interfaces
+    let mut tracker = StateTracker {
+        states: HashMap::new(),
+        carrier_lost: HashSet::new(),
+        cache,
+        verbose,
     };
 
-    let mut s = match socket::NlSocketHandle::connect(NlFamily::Route, Some(0), &[RTNLGRP_LINK]) {
-        Ok(socket) => socket,
-        Err(e) => {
-            println!("Failed to create netlink socket: {:?}", e);
-            return;
+    let beat = || {
+        if let Some(liveness) = &liveness {
+            liveness.beat(&Task::Netlink);
+        }
+    };
+    // Still reporting in while waiting, returning false once the channel has closed
+    let wait_to_reconnect = || {
+        let deadline = Instant::now() + RECONNECT_DELAY;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            beat();
+            if tx.is_closed() {
+                info!("Channel closed, stopping netlink monitoring");
+                return false;
+            }
+            std::thread::sleep(left.min(BEAT_INTERVAL));
         }
+        true
     };
 
-    println!("Started netlink monitoring for CAN interfaces");
-
-    for next in s.iter::<Rtm, Ifinfomsg>(true) {
-        match next {
-            Ok(msg) => {
-                if let Ok(msg_payload) = msg.get_payload() {
-                    // Only process CAN interfaces
-                    if u16::from(msg_payload.ifi_type) == ARPHRD_CAN
-                        && interfaces
-                            .binary_search(&(msg_payload.ifi_index as u32))
-                            .is_ok()
-                    {
-                        let handle = msg_payload.rtattrs.get_attr_handle();
-                        let idx = msg_payload.ifi_index as u32;
-                        let name = handle
-                            .get_attr_payload_as_with_len::<String>(Ifla::Ifname)
-                            .unwrap_or_else(|_| "Unknown".to_string());
+    loop {
+        beat();
+        if tx.is_closed() {
+            info!("Channel closed, stopping netlink monitoring");
+            return Ok(());
+        }
 
-                        let state = handle
-                            .get_attribute(Ifla::Linkinfo)
-                            .and_then(|attr| InterfaceCanParams::try_from(attr).ok()?.state);
+        let mut s = match socket::NlSocketHandle::connect(NlFamily::Route, Some(0), groups) {
+            Ok(socket) => socket,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                warn!(
+                    "Netlink link notifications are not permitted ({}). Netlink-based detection is unavailable, only error frames will be used to detect bus-off. {}",
+                    e, PERMISSION_HINT
+                );
+                if let Some(liveness) = &liveness {
+                    liveness.forget(&Task::Netlink);
+                }
+                return Err(e);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to create netlink socket: {}. retrying in {:?}...",
+                    e, RECONNECT_DELAY
+                );
+                if !wait_to_reconnect() {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        match set_receive_buffer(&s) {
+            Ok(bytes) => debug!("Netlink receive buffer is {} bytes", bytes),
+            Err(e) => debug!("Failed to enlarge the netlink receive buffer: {}", e),
+        }
+        if let Err(e) = set_receive_timeout(&s) {
+            warn!(
+                "Failed to set a netlink receive timeout, liveness is only reported on link changes: {}",
+                e
+            );
+        }
 
-                        let interface = CanInterfaceInfo { idx, name };
+        info!("Started netlink monitoring for CAN interfaces");
 
-                        if verbose {
-                            println!(
-                                "Netlink: Interface {} (idx={}) state: {:?}",
-                                interface.name, interface.idx, state
-                            );
-                        }
+        for next in s.iter::<Rtm, Ifinfomsg>(true) {
+            beat();
+            match next {
+                Ok(msg) => {
+                    if let Ok(msg_payload) = msg.get_payload() {
+                        let idx = msg_payload.ifi_index as u32;
+                        // Only process the CAN interfaces being monitored. Events carry the
+                        // interface as it is known rather than the name in the message, which
+                        // may be missing, or new if the interface was renamed meanwhile.
+                        let known = interfaces
+                            .read()
+                            .unwrap()
+                            .iter()
+                            .find(|interface| interface.idx == idx)
+                            .cloned();
+                        if u16::from(msg_payload.ifi_type) == ARPHRD_CAN
+                            && let Some(interface) = known
+                        {
+                            let handle = msg_payload.rtattrs.get_attr_handle();
+                            let params = handle
+                                .get_attribute(Ifla::Linkinfo)
+                                .and_then(|attr| InterfaceCanParams::try_from(attr).ok());
+                            let state = params.as_ref().and_then(|params| params.state);
+                            // Only present if the driver can read them from the controller
+                            let counters =
+                                params
+                                    .and_then(|params| params.berr_counter)
+                                    .map(|counter| ErrorCounters {
+                                        tx: u32::from(counter.txerr),
+                                        rx: u32::from(counter.rxerr),
+                                    });
+
+                            let up = msg_payload.ifi_flags.contains(&Iff::Up);
+                            let running = msg_payload.ifi_flags.contains(&Iff::Running);
 
-                        let event = match state {
-                            Some(CanState::BusOff) => Some(BusEvent::bus_off(
-                                interface,
-                                BusEventSource::StateUpdate(CanState::BusOff),
-                            )),
-                            Some(CanState::Stopped) => Some(BusEvent::stopped(
-                                interface,
-                                BusEventSource::StateUpdate(CanState::Stopped),
-                            )),
-                            // We don't trust netlink to deliver restarted messages correctly
-                            _ => None,
-                        };
-
-                        if let Some(event) = event
+                            let carrier = tracker.update_carrier(&interface, up, running, state);
+                            let event = tracker.update(interface, state, counters);
+                            for event in carrier.into_iter().chain(event) {
+                                if tx.send(event).is_err() {
+                                    info!("Channel closed, stopping netlink monitoring");
+                                    return Ok(());
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(NlError::De(e)) => {
+                    if verbose {
+                        debug!("Netlink: skipping message that isn't link info: {}", e);
+                    }
+                }
+                // Nothing happened within the receive timeout
+                Err(e) if is_timeout(&e) => {
+                    if tx.is_closed() {
+                        info!("Channel closed, stopping netlink monitoring");
+                        return Ok(());
+                    }
+                }
+                Err(e) if is_overrun(&e) => {
+                    warn!(
+                        "Netlink receive buffer overrun, link changes were lost. Re-reading interface states"
+                    );
+                    let interfaces = interfaces.read().unwrap().clone();
+                    for interface in interfaces {
+                        let state = interface.current_state();
+                        if let Some(event) = tracker.update(interface, state, None)
                             && tx.send(event).is_err()
                         {
-                            println!("Channel closed, stopping netlink monitoring");
-                            break;
+                            info!("Channel closed, stopping netlink monitoring");
+                            return Ok(());
                         }
                     }
                 }
+                Err(e) => {
+                    error!("Netlink error: {:?}", e);
+                    break;
+                }
             }
-            Err(e) => {
-                println!("Netlink error: {:?}", e);
-                break;
+        }
+
+        // Anything that happened while disconnected is missed, error frames cover the gap
+        warn!(
+            "Netlink monitoring interrupted, reconnecting in {:?}...",
+            RECONNECT_DELAY
+        );
+        if !wait_to_reconnect() {
+            return Ok(());
+        }
+    }
+}
+
+/// Turns the states reported for each interface into events
+struct StateTracker {
+    /// Last state seen for each interface, to tell recoveries from steady ERROR_ACTIVE reports
+    states: HashMap<u32, CanState>,
+    /// Interfaces that lost carrier while not bus-off
+    carrier_lost: HashSet<u32>,
+    cache: StateCache,
+    verbose: bool,
+}
+
+impl StateTracker {
+    /// Record the state reported for an interface, returning the event it amounts to, if any
+    fn update(
+        &mut self,
+        interface: CanInterfaceInfo,
+        state: Option<CanState>,
+        counters: Option<ErrorCounters>,
+    ) -> Option<BusEvent> {
+        let previous = state.and_then(|state| {
+            self.cache.set(interface.idx, state);
+            self.states.insert(interface.idx, state)
+        });
+
+        if self.verbose {
+            interface.span().in_scope(|| match counters {
+                Some(counters) => debug!(
+                    "Netlink: state: {:?}, tx errors: {}, rx errors: {}",
+                    state, counters.tx, counters.rx
+                ),
+                None => debug!("Netlink: state: {:?}", state),
+            });
+        }
+
+        let event = match state {
+            Some(CanState::BusOff) => Some(BusEvent::bus_off(
+                interface,
+                BusEventSource::StateUpdate(CanState::BusOff),
+            )),
+            Some(CanState::Stopped) => Some(BusEvent::stopped(
+                interface,
+                BusEventSource::StateUpdate(CanState::Stopped),
+            )),
+            // Degraded states are only reported on entry, link messages repeat the current
+            // state for unrelated changes
+            Some(CanState::ErrorWarning) if previous != Some(CanState::ErrorWarning) => {
+                Some(BusEvent::error_warning(
+                    interface,
+                    BusEventSource::StateUpdate(CanState::ErrorWarning),
+                ))
             }
+            Some(CanState::ErrorPassive) if previous != Some(CanState::ErrorPassive) => {
+                Some(BusEvent::error_passive(
+                    interface,
+                    BusEventSource::StateUpdate(CanState::ErrorPassive),
+                ))
+            }
+            Some(CanState::ErrorActive) if previous.is_some_and(|p| p != CanState::ErrorActive) => {
+                Some(BusEvent::recovered(
+                    interface,
+                    BusEventSource::StateUpdate(CanState::ErrorActive),
+                ))
+            }
+            // We don't trust netlink to deliver restarted messages correctly
+            Some(
+                CanState::ErrorActive
+                | CanState::ErrorWarning
+                | CanState::ErrorPassive
+                | CanState::Sleeping,
+            )
+            | None => None,
+        };
+        event.map(|event| event.with_error_counters(counters))
+    }
+
+    /// Record the flags reported for an interface, returning a carrier event if it lost or
+    /// regained carrier
+    ///
+    /// Bus-off and stopping also take the carrier down, so only losses in other states count.
+    /// Taking the interface down forgets a loss without reporting it.
+    fn update_carrier(
+        &mut self,
+        interface: &CanInterfaceInfo,
+        up: bool,
+        running: bool,
+        state: Option<CanState>,
+    ) -> Option<BusEvent> {
+        let source = BusEventSource::LinkFlags { up, running };
+        if !up {
+            self.carrier_lost.remove(&interface.idx);
+            None
+        } else if running {
+            self.carrier_lost
+                .remove(&interface.idx)
+                .then(|| BusEvent::carrier_restored(interface.clone(), source))
+        } else if !matches!(state, Some(CanState::BusOff | CanState::Stopped))
+            && self.carrier_lost.insert(interface.idx)
+        {
+            Some(BusEvent::carrier_lost(interface.clone(), source))
+        } else {
+            None
         }
     }
-    println!("Netlink monitoring thread finished");
+}
+
+/// Enlarge the socket's receive buffer, so bursts of link changes don't overrun it, returning
+/// its size
+///
+/// The kernel caps the size at `net.core.rmem_max`.
+fn set_receive_buffer(socket: &neli::socket::NlSocketHandle) -> io::Result<usize> {
+    use nix::sys::socket::{getsockopt, setsockopt, sockopt::RcvBuf};
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    // SAFETY: the descriptor is owned by `socket`, which outlives this borrow
+    let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+    setsockopt(&fd, RcvBuf, &RECEIVE_BUFFER)?;
+    Ok(getsockopt(&fd, RcvBuf)?)
+}
+
+/// Make receiving time out after [`BEAT_INTERVAL`], so a quiet listener still reports in
+fn set_receive_timeout(socket: &neli::socket::NlSocketHandle) -> io::Result<()> {
+    use nix::sys::{
+        socket::{setsockopt, sockopt::ReceiveTimeout},
+        time::TimeVal,
+    };
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    // SAFETY: the descriptor is owned by `socket`, which outlives this borrow
+    let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+    let timeout = TimeVal::new(
+        BEAT_INTERVAL.as_secs() as _,
+        BEAT_INTERVAL.subsec_micros() as _,
+    );
+    Ok(setsockopt(&fd, ReceiveTimeout, &timeout)?)
+}
+
+/// Whether a netlink error is only the receive timeout expiring
+fn is_timeout(e: &neli::err::NlError) -> bool {
+    use neli::err::{NlError, WrappedError};
+
+    matches!(
+        e,
+        NlError::Wrapped(WrappedError::IOError(e))
+            if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+/// Whether a netlink error means the kernel dropped messages because the receive buffer was full
+fn is_overrun(e: &neli::err::NlError) -> bool {
+    use neli::err::{NlError, WrappedError};
+
+    matches!(
+        e,
+        NlError::Wrapped(WrappedError::IOError(e)) if e.raw_os_error() == Some(ENOBUFS)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::BusEventType, testing::can0};
+
+    fn tracker() -> StateTracker {
+        StateTracker {
+            states: HashMap::new(),
+            carrier_lost: HashSet::new(),
+            cache: StateCache::new(),
+            verbose: false,
+        }
+    }
+
+    #[test]
+    fn carrier_loss_is_reported_once_and_restored() {
+        let mut tracker = tracker();
+        let active = Some(CanState::ErrorActive);
+
+        let lost = tracker
+            .update_carrier(&can0(), true, false, active)
+            .unwrap();
+        assert_eq!(lost.event_type, BusEventType::CarrierLost);
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, false, active)
+                .is_none()
+        );
+
+        let restored = tracker.update_carrier(&can0(), true, true, active).unwrap();
+        assert_eq!(restored.event_type, BusEventType::CarrierRestored);
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, true, active)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn carrier_loss_from_bus_off_or_going_down_is_not_reported() {
+        let mut tracker = tracker();
+        let bus_off = Some(CanState::BusOff);
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, false, bus_off)
+                .is_none()
+        );
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, true, bus_off)
+                .is_none()
+        );
+
+        let active = Some(CanState::ErrorActive);
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, false, active)
+                .is_some()
+        );
+        assert!(
+            tracker
+                .update_carrier(&can0(), false, false, active)
+                .is_none()
+        );
+        assert!(
+            tracker
+                .update_carrier(&can0(), true, true, active)
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,250 @@
+//! Polling-based CAN interface state monitoring
+//!
+//! A fallback for environments where netlink link notifications aren't available, such as
+//! restricted containers. The state of every interface is read at a fixed interval and events are
+//! emitted on transitions into bus-off, stopped or the error warning and passive states, and back
+//! to error-active, like [`monitor_netlink`](super::monitor_netlink) does. Anything shorter than
+//! the interval can be missed, so error frames remain the primary source of bus-off events.
+
+use crate::backend::{CanControl, SocketCanBackend};
+use crate::events::{BusEvent, BusEventSource};
+use crate::interface::{CanInterfaceInfo, SharedInterfaces, StateCache};
+use socketcan::nl::CanState;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info};
+
+/// Poll the state of `interfaces` every `interval`, emitting events on state transitions
+///
+/// Runs until the event channel is closed.
+pub async fn monitor_poll(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: Vec<CanInterfaceInfo>,
+    interval: Duration,
+    verbose: bool,
+) {
+    monitor_poll_with(&SocketCanBackend, tx, interfaces, interval, verbose).await
+}
+
+/// Poll interface states through the given backend
+pub async fn monitor_poll_with<C: CanControl>(
+    control: &C,
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: Vec<CanInterfaceInfo>,
+    interval: Duration,
+    verbose: bool,
+) {
+    monitor_poll_shared(
+        control,
+        tx,
+        Arc::new(RwLock::new(interfaces)),
+        StateCache::default(),
+        interval,
+        verbose,
+    )
+    .await
+}
+
+/// Poll a set of interfaces that can change while polling runs
+///
+/// The set is read again on every tick. Every state read is recorded in `cache`.
+pub(crate) async fn monitor_poll_shared<C: CanControl>(
+    control: &C,
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: SharedInterfaces,
+    cache: StateCache,
+    interval: Duration,
+    verbose: bool,
+) {
+    info!("Started polling CAN interface states every {:?}", interval);
+    poll_states(
+        |interface| control.state(interface.idx),
+        BusEventSource::Poll,
+        tx,
+        interfaces,
+        cache,
+        interval,
+        verbose,
+    )
+    .await
+}
+
+/// Read the state of a changing set of interfaces with `read_state` every `interval`, emitting
+/// events from `source` on state transitions
+///
+/// A state read as `None` is unknown, and the next one known counts as a transition from it.
+pub(crate) async fn poll_states(
+    read_state: impl Fn(&CanInterfaceInfo) -> io::Result<Option<CanState>>,
+    source: fn(CanState) -> BusEventSource,
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: SharedInterfaces,
+    cache: StateCache,
+    interval: Duration,
+    verbose: bool,
+) {
+    // Last state seen for each interface, so only transitions produce events
+    let mut states: HashMap<u32, Option<CanState>> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        if tx.is_closed() {
+            info!("Channel closed, stopping state polling");
+            return;
+        }
+
+        let interfaces = interfaces.read().unwrap().clone();
+        states.retain(|idx, _| interfaces.iter().any(|i| i.idx == *idx));
+        for interface in &interfaces {
+            let state = match read_state(interface) {
+                Ok(Some(state)) => state,
+                Ok(None) => {
+                    if let Some(known) = states.get_mut(&interface.idx) {
+                        *known = None;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    if verbose {
+                        interface.span().in_scope(|| {
+                            debug!("Poll: failed to read state: {}", e);
+                        });
+                    }
+                    continue;
+                }
+            };
+
+            cache.set(interface.idx, state);
+            let previous = states.insert(interface.idx, Some(state));
+            if previous == Some(Some(state)) {
+                continue;
+            }
+            if verbose {
+                interface.span().in_scope(|| {
+                    debug!("Poll: state: {:?}", state);
+                });
+            }
+
+            let event = match state {
+                CanState::BusOff => Some(BusEvent::bus_off(
+                    interface.clone(),
+                    source(CanState::BusOff),
+                )),
+                CanState::Stopped => Some(BusEvent::stopped(
+                    interface.clone(),
+                    source(CanState::Stopped),
+                )),
+                CanState::ErrorWarning => Some(BusEvent::error_warning(
+                    interface.clone(),
+                    source(CanState::ErrorWarning),
+                )),
+                CanState::ErrorPassive => Some(BusEvent::error_passive(
+                    interface.clone(),
+                    source(CanState::ErrorPassive),
+                )),
+                CanState::ErrorActive if previous.is_some() => Some(BusEvent::recovered(
+                    interface.clone(),
+                    source(CanState::ErrorActive),
+                )),
+                _ => None,
+            };
+
+            if let Some(event) = event
+                && tx.send(event).is_err()
+            {
+                info!("Channel closed, stopping state polling");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::fake::FakeBackend,
+        events::BusEventType,
+        testing::{EventSink, can0},
+    };
+    use std::{collections::VecDeque, sync::Mutex};
+    use tokio::task::JoinHandle;
+
+    const INTERVAL: Duration = Duration::from_millis(5);
+
+    /// Poll can0 on `backend`, which starts out error-active, until the returned task is aborted
+    fn poll(backend: &FakeBackend) -> (EventSink, JoinHandle<()>) {
+        let (tx, sink) = EventSink::channel();
+        backend.set_state(1, CanState::ErrorActive);
+        let backend = backend.clone();
+        let poller = tokio::spawn(async move {
+            monitor_poll_with(&backend, tx, vec![can0()], INTERVAL, false).await;
+        });
+        (sink, poller)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn states_known_again_after_being_unknown_are_transitions() {
+        let reads = Mutex::new(VecDeque::from([
+            Some(CanState::ErrorActive),
+            None,
+            Some(CanState::ErrorActive),
+        ]));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let interfaces = Arc::new(RwLock::new(vec![can0()]));
+        let poll = poll_states(
+            |_| Ok(reads.lock().unwrap().pop_front().flatten()),
+            BusEventSource::Sysfs,
+            tx,
+            interfaces,
+            StateCache::default(),
+            Duration::from_secs(1),
+            false,
+        );
+        let _ = tokio::time::timeout(Duration::from_secs(5), poll).await;
+
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.event_type, BusEventType::Recovered);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polling_reports_bus_off_and_recovery() {
+        let backend = FakeBackend::new();
+        let (mut sink, poller) = poll(&backend);
+
+        tokio::time::sleep(4 * INTERVAL).await;
+        backend.set_state(1, CanState::BusOff);
+        sink.expect_bus_off(&can0(), Duration::from_secs(1)).await;
+
+        backend.set_state(1, CanState::ErrorActive);
+        sink.expect(BusEventType::Recovered, &can0(), Duration::from_secs(1))
+            .await;
+
+        poller.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polling_reports_degraded_states() {
+        let backend = FakeBackend::new();
+        let (mut sink, poller) = poll(&backend);
+
+        tokio::time::sleep(4 * INTERVAL).await;
+        backend.set_state(1, CanState::ErrorWarning);
+        sink.expect(BusEventType::ErrorWarning, &can0(), Duration::from_secs(1))
+            .await;
+
+        backend.set_state(1, CanState::ErrorPassive);
+        let passive = sink
+            .expect(BusEventType::ErrorPassive, &can0(), Duration::from_secs(1))
+            .await;
+        assert!(passive.is_degraded());
+
+        poller.abort();
+    }
+}
@@ -0,0 +1,85 @@
+//! Sysfs-based CAN interface state monitoring
+//!
+//! A last resort for minimal kernels and containers where RTNL netlink doesn't work well enough
+//! to be subscribed to or queried. sysfs doesn't expose the controller state, but it does expose
+//! what the kernel does on the way to it: an interface that isn't up (`IFF_UP` in `flags`) is
+//! stopped. One that is up without carrier may have gone bus-off, since `can_bus_off()` takes the
+//! carrier down, but so does unplugging a USB adapter, so its state is unknown until the carrier
+//! returns. Bus-off is then only seen in error frames, like ERROR_WARNING and ERROR_PASSIVE;
+//! anything else is reported as error-active. The states are polled like [`super::poll`] does,
+//! with events from [`BusEventSource::Sysfs`].
+
+use crate::events::{BusEvent, BusEventSource};
+use crate::interface::{CanInterfaceInfo, SYSFS_NET, SharedInterfaces, StateCache};
+use nix::libc::IFF_UP;
+use socketcan::nl::CanState;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// State of an interface as far as sysfs shows it, or `None` while it is up without carrier
+///
+/// Fails if the interface doesn't exist or sysfs isn't mounted.
+pub fn read_state(interface: &CanInterfaceInfo) -> io::Result<Option<CanState>> {
+    let dir = Path::new(SYSFS_NET).join(&interface.name);
+    let flags = fs::read_to_string(dir.join("flags"))?;
+    let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if flags & IFF_UP as u32 == 0 {
+        return Ok(Some(CanState::Stopped));
+    }
+    // Only readable while the interface is up. Bus-off and a lost adapter look the same here
+    let carrier = fs::read_to_string(dir.join("carrier"))?;
+    Ok(match carrier.trim() {
+        "0" => None,
+        _ => Some(CanState::ErrorActive),
+    })
+}
+
+/// Poll the state of `interfaces` in sysfs every `interval`, emitting events on state transitions
+///
+/// Runs until the event channel is closed.
+pub async fn monitor_sysfs(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: Vec<CanInterfaceInfo>,
+    interval: Duration,
+    verbose: bool,
+) {
+    monitor_sysfs_shared(
+        tx,
+        Arc::new(RwLock::new(interfaces)),
+        StateCache::default(),
+        interval,
+        verbose,
+    )
+    .await
+}
+
+/// Poll a set of interfaces in sysfs that can change while polling runs, recording every state
+/// read in `cache`
+pub(crate) async fn monitor_sysfs_shared(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interfaces: SharedInterfaces,
+    cache: StateCache,
+    interval: Duration,
+    verbose: bool,
+) {
+    info!(
+        "Started polling CAN interface states in sysfs every {:?}",
+        interval
+    );
+    super::poll::poll_states(
+        read_state,
+        BusEventSource::Sysfs,
+        tx,
+        interfaces,
+        cache,
+        interval,
+        verbose,
+    )
+    .await
+}
@@ -0,0 +1,156 @@
+//! OpenTelemetry export
+//!
+//! Spans and metrics are exported over OTLP/HTTP to a collector. Every bus-off is a `bus_off`
+//! span inside the interface's `can` span, covering the restart, that ends once the interface has
+//! recovered, with the downtime recorded on it. The [`Metrics`] counters, gauges and downtime
+//! histogram are exported as instruments of the same names.
+//!
+//! Exporting happens on background threads, so [`init`] has to be called before the async
+//! runtime is started, and [`shutdown`] before the process exits to flush what is still queued.
+
+use crate::{
+    log::BoxedLayer,
+    metrics::{
+        COUNTERS, DOWNTIME, DOWNTIME_BUCKETS, ERROR_COUNTER_GAUGES, Metrics, PENDING_RESTARTS,
+        STALLED_TASKS,
+    },
+};
+use opentelemetry::{KeyValue, global, metrics::MeterProvider as _, trace::TracerProvider as _};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, metrics::SdkMeterProvider, trace::SdkTracerProvider};
+use std::{
+    io,
+    sync::{Arc, OnceLock},
+};
+use tracing_subscriber::Layer;
+
+/// Name spans and metrics are reported under
+const SERVICE_NAME: &str = "cansentinel";
+
+/// Providers installed by [`init`]
+static PROVIDERS: OnceLock<(SdkTracerProvider, SdkMeterProvider)> = OnceLock::new();
+
+/// Start exporting to the collector at `endpoint`, e.g. `http://localhost:4318`, returning the
+/// layer that exports spans, for [`crate::log::init_with`]
+///
+/// Fails if the exporters can't be created, or if they already have been.
+pub fn init(endpoint: &str) -> io::Result<BoxedLayer> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+    let spans = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint))
+        .build()
+        .map_err(io::Error::other)?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(spans)
+        .with_resource(resource.clone())
+        .build();
+
+    let metrics = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/metrics", endpoint))
+        .build()
+        .map_err(io::Error::other)?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metrics)
+        .with_resource(resource)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+
+    let tracer = tracer_provider.tracer(SERVICE_NAME);
+    PROVIDERS
+        .set((tracer_provider, meter_provider))
+        .map_err(|_| io::Error::other("OpenTelemetry export is already running"))?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+}
+
+/// Export the counters and gauges of `metrics`, which are read whenever metrics are exported,
+/// and the downtimes recorded from now on
+///
+/// Does nothing unless [`init`] succeeded.
+pub fn export_metrics(metrics: Arc<Metrics>) {
+    let Some((_, meter_provider)) = PROVIDERS.get() else {
+        return;
+    };
+    let meter = meter_provider.meter(SERVICE_NAME);
+
+    for (name, help, value) in COUNTERS {
+        let metrics = Arc::clone(&metrics);
+        meter
+            .u64_observable_counter(name)
+            .with_description(help)
+            .with_callback(move |observer| {
+                for (interface, counters) in metrics.snapshot() {
+                    observer.observe(value(&counters), &[KeyValue::new("interface", interface)]);
+                }
+            })
+            .build();
+    }
+
+    for (name, help, value) in ERROR_COUNTER_GAUGES {
+        let metrics = Arc::clone(&metrics);
+        meter
+            .u64_observable_gauge(name)
+            .with_description(help)
+            .with_callback(move |observer| {
+                for (interface, counters) in metrics.snapshot() {
+                    if let Some(error_counters) = &counters.error_counters {
+                        observer.observe(
+                            u64::from(value(error_counters)),
+                            &[KeyValue::new("interface", interface)],
+                        );
+                    }
+                }
+            })
+            .build();
+    }
+
+    let (name, help) = DOWNTIME;
+    let downtime = meter
+        .f64_histogram(name)
+        .with_description(help)
+        .with_unit("s")
+        .with_boundaries(DOWNTIME_BUCKETS.to_vec())
+        .build();
+    metrics.observe_downtime(move |interface, seconds| {
+        downtime.record(
+            seconds,
+            &[KeyValue::new("interface", interface.to_string())],
+        );
+    });
+
+    {
+        let metrics = Arc::clone(&metrics);
+        let (name, help) = PENDING_RESTARTS;
+        meter
+            .u64_observable_gauge(name)
+            .with_description(help)
+            .with_callback(move |observer| observer.observe(metrics.pending_restarts(), &[]))
+            .build();
+    }
+
+    let (name, help) = STALLED_TASKS;
+    meter
+        .u64_observable_gauge(name)
+        .with_description(help)
+        .with_callback(move |observer| observer.observe(metrics.stalled_tasks(), &[]))
+        .build();
+}
+
+/// Export everything still queued and stop exporting
+///
+/// Blocks until the collector has answered or the exporters gave up. Does nothing unless
+/// [`init`] succeeded.
+pub fn shutdown() {
+    let Some((tracer_provider, meter_provider)) = PROVIDERS.get() else {
+        return;
+    };
+    if let Err(e) = tracer_provider.shutdown() {
+        tracing::warn!("Failed to flush exported spans: {}", e);
+    }
+    if let Err(e) = meter_provider.shutdown() {
+        tracing::warn!("Failed to flush exported metrics: {}", e);
+    }
+}
@@ -1,30 +1,727 @@
 //! Restart management for CAN interfaces
 
-use crate::interface::CanInterfaceInfo;
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{sync::RwLock, task::JoinHandle};
+use crate::{
+    backend::{CanControl, PERMISSION_HINT, SocketCanBackend},
+    config::{LinkSettings, RestartMethod},
+    events::{BusEvent, BusEventSource},
+    interface::CanInterfaceInfo,
+    log,
+    metrics::Metrics,
+    state::{self, InterfaceState},
+};
+use socketcan::nl::CanState;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{RwLock, Semaphore, mpsc},
+    task::{JoinHandle, JoinSet},
+    time::Instant,
+};
+use tracing::{Instrument, debug, error, info, warn};
+
+/// Interval between state checks after a restart
+const VERIFY_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a restarted interface has to leave bus-off before the restart counts as failed
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How late a stopped event may still be reported after an interface was brought back up
+const OWN_STOP_SLACK: Duration = Duration::from_secs(1);
+
+/// How long a down/up restart keeps the interface down unless set otherwise
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Policy for adapting restart delays to each interface's recent bus-off history
+///
+/// Every interface carries a score that grows by one on each bus-off incident, halves on each
+/// confirmed recovery, and decays with the configured half-life. The restart delay is scaled by
+/// `2^(score - 1)`, so a single recent bus-off uses the configured delay, chronically failing
+/// interfaces wait longer and long-stable ones restart sooner, within the multiplier bounds.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveDelay {
+    /// Time for a score to decay to half its value
+    pub half_life: Duration,
+    /// Smallest factor applied to the configured delay
+    pub min_multiplier: f64,
+    /// Largest factor applied to the configured delay
+    pub max_multiplier: f64,
+}
+
+impl Default for AdaptiveDelay {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(60 * 60),
+            min_multiplier: 0.5,
+            max_multiplier: 8.0,
+        }
+    }
+}
+
+/// Exponential backoff for interfaces that go bus-off again right after being restarted
+///
+/// Each consecutive restart of the same interface doubles its delay, up to `max_delay`. The
+/// count is cleared once the interface has been error-active for `stable_after` following a
+/// restart.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Longest delay backoff may grow to
+    pub max_delay: Duration,
+    /// How long an interface must stay error-active after a restart to reset its backoff
+    pub stable_after: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            max_delay: Duration::from_secs(60),
+            stable_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Stop restarting interfaces that keep going bus-off
+///
+/// More than `max_restarts` restarts of one interface within `window` trips the breaker: no
+/// further restarts are scheduled for it until `cooldown` has passed, if set, or it is reset with
+/// [`RestartManager::reset_circuit_breaker`]. With a confirmation window, only restarts that went
+/// bus-off again within it are counted.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreaker {
+    /// Restarts allowed within the window
+    pub max_restarts: usize,
+    /// Window over which restarts are counted
+    pub window: Duration,
+    /// How long restarts stay suspended once tripped, or until reset if unset
+    pub cooldown: Option<Duration>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            cooldown: Some(Duration::from_secs(10 * 60)),
+        }
+    }
+}
+
+/// Random extra delay, so interfaces that went bus-off together don't all restart at once
+///
+/// A delay picked uniformly between zero and `spread` is added to every restart delay. The random
+/// numbers come from `seed`, so a given seed always produces the same sequence of delays.
+#[derive(Debug, Clone, Copy)]
+pub struct Jitter {
+    /// Largest extra delay
+    pub spread: Duration,
+    /// Seed for the random number generator
+    pub seed: u64,
+}
+
+/// SplitMix64, plenty for spreading out restarts
+#[derive(Debug)]
+struct JitterRng {
+    spread: Duration,
+    state: u64,
+}
+
+impl JitterRng {
+    fn new(policy: Jitter) -> Self {
+        Self {
+            spread: policy.spread,
+            state: policy.seed,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Extra delay for the next restart
+    fn next_delay(&mut self) -> Duration {
+        let spread = self.spread.as_nanos() as u64;
+        if spread == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.next_u64() % (spread + 1))
+    }
+}
+
+/// Recent restarts of a single interface, for the circuit breaker
+#[derive(Debug, Default)]
+struct RestartHistory {
+    /// Restarts scheduled within the window, oldest first
+    recent: VecDeque<Instant>,
+    /// When the breaker tripped, while restarts are suspended
+    tripped_at: Option<Instant>,
+}
+
+/// Consecutive restarts of a single interface
+#[derive(Debug, Clone, Copy, Default)]
+struct RestartAttempts {
+    attempts: u32,
+    /// Incremented on every restart, so a stability check can tell whether another restart
+    /// happened while it was waiting
+    generation: u64,
+}
+
+/// Decaying bus-off score of a single interface
+#[derive(Debug, Clone, Copy)]
+struct Reputation {
+    score: f64,
+    updated: Instant,
+}
+
+impl Reputation {
+    /// Score decayed to `now`
+    fn current(&self, half_life: Duration, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.score * 0.5f64.powf(elapsed / half_life.as_secs_f64().max(f64::EPSILON))
+    }
+}
+
+/// Restarts issued on a single interface, see [`RestartManager::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestartStats {
+    /// Restarts issued, including failed ones
+    pub restarts: u64,
+    /// Restarts that returned an error, or that went bus-off again within the confirmation
+    /// window
+    pub failures: u64,
+    /// When the last restart was issued
+    pub last_restart: Option<SystemTime>,
+}
+
+/// When each interface was last taken down by a restart, to tell the stopped events that causes
+/// apart from someone else stopping it
+#[derive(Debug, Clone, Default)]
+struct OwnStops(Arc<Mutex<HashMap<u32, (Instant, Option<Instant>)>>>);
+
+impl OwnStops {
+    /// Record that `idx` is being taken down, until the returned guard is dropped
+    fn begin(&self, idx: u32) -> OwnStop {
+        self.0.lock().unwrap().insert(idx, (Instant::now(), None));
+        OwnStop {
+            stops: self.clone(),
+            idx,
+        }
+    }
+
+    /// Whether a stopped event on `idx` detected at `at` was caused by a restart
+    fn caused(&self, idx: u32, at: Instant) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&idx)
+            .is_some_and(|(down, up)| at >= *down && up.is_none_or(|up| at <= up + OWN_STOP_SLACK))
+    }
+}
+
+/// An interface taken down by a restart, see [`OwnStops::begin`]
+struct OwnStop {
+    stops: OwnStops,
+    idx: u32,
+}
+
+impl Drop for OwnStop {
+    fn drop(&mut self) {
+        if let Some((_, up)) = self.stops.0.lock().unwrap().get_mut(&self.idx) {
+            *up = Some(Instant::now());
+        }
+    }
+}
+
+/// A restart waiting out its delay
+#[derive(Debug)]
+struct PendingRestart {
+    task: JoinHandle<()>,
+    /// When the delay runs out
+    due: Instant,
+}
 
 /// Manages pending restart tasks for CAN interfaces
 #[derive(Debug)]
 pub struct RestartManager {
-    /// Map of interface index to pending restart task
-    pending_tasks: Arc<RwLock<HashMap<u32, JoinHandle<()>>>>,
+    /// Pending restart task of each interface
+    pending_tasks: Arc<RwLock<HashMap<CanInterfaceInfo, PendingRestart>>>,
+    /// Map of interface index to consecutive restart attempts
+    attempts: Arc<Mutex<HashMap<u32, RestartAttempts>>>,
+    /// Backoff policy, if enabled
+    backoff: Option<Backoff>,
+    /// Performs the actual restarts
+    control: Arc<dyn CanControl>,
+    /// Number of restarts issued since creation
+    total_restarts: Arc<AtomicU64>,
+    /// Map of interface index to restarts issued
+    stats: Arc<Mutex<HashMap<u32, RestartStats>>>,
+    /// Adaptive delay policy, if enabled
+    adaptive: Option<AdaptiveDelay>,
+    /// Map of interface index to bus-off reputation
+    reputations: Mutex<HashMap<u32, Reputation>>,
+    /// Restart counters and pending gauge, if enabled
+    metrics: Option<Arc<Metrics>>,
+    /// Receives events generated by the manager itself
+    event_tx: Option<mpsc::WeakUnboundedSender<BusEvent>>,
+    /// Circuit breaker policy, if enabled
+    breaker: Option<CircuitBreaker>,
+    /// Map of interface index to recent restarts, for the circuit breaker
+    restart_history: Arc<Mutex<HashMap<u32, RestartHistory>>>,
+    /// Bring up interfaces that are stopped instead of restarting them
+    restart_stopped: bool,
+    /// Map of interface index to link settings re-applied on restart
+    link_settings: Arc<Mutex<HashMap<u32, LinkSettings>>>,
+    /// Map of interface index to how it is restarted, if not with the restart request
+    restart_methods: Arc<Mutex<HashMap<u32, RestartMethod>>>,
+    /// Interfaces whose error counters are reset after a restart
+    reset_counters: Arc<Mutex<HashSet<u32>>>,
+    /// How long a down/up restart keeps the interface down
+    settle_time: Duration,
+    /// Shortest time between two restarts of the same interface, if set
+    min_interval: Option<Duration>,
+    /// How long bus-offs are ignored after a restart is issued, if set
+    mute_window: Option<Duration>,
+    /// Map of interface index to when it was last restarted, for the minimum interval and the
+    /// mute window
+    last_restarts: Arc<Mutex<HashMap<u32, Instant>>>,
+    /// Limits how many restarts may be in progress at the same time, if set
+    restart_limiter: Option<Arc<Semaphore>>,
+    /// Adds random extra delay to restarts, if enabled
+    jitter: Option<Mutex<JitterRng>>,
+    /// How long a restarted interface must stay out of bus-off for the restart to count, if set
+    confirm_window: Option<Duration>,
+    /// Log restarts instead of issuing them
+    dry_run: bool,
+    /// Confirmation and backoff reset checks started after restarts
+    watchers: Arc<Mutex<JoinSet<()>>>,
+    /// Interfaces taken down by restarts
+    own_stops: OwnStops,
 }
 
 impl RestartManager {
     /// Create a new restart manager
     pub fn new() -> Self {
+        Self::with_control(Arc::new(SocketCanBackend))
+    }
+
+    /// Create a new restart manager that restarts interfaces through `control`
+    pub fn with_control(control: Arc<dyn CanControl>) -> Self {
         Self {
             pending_tasks: Arc::new(RwLock::new(HashMap::new())),
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+            backoff: None,
+            control,
+            total_restarts: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            adaptive: None,
+            reputations: Mutex::new(HashMap::new()),
+            metrics: None,
+            event_tx: None,
+            breaker: None,
+            restart_history: Arc::new(Mutex::new(HashMap::new())),
+            restart_stopped: false,
+            link_settings: Arc::new(Mutex::new(HashMap::new())),
+            restart_methods: Arc::new(Mutex::new(HashMap::new())),
+            reset_counters: Arc::new(Mutex::new(HashSet::new())),
+            settle_time: DEFAULT_SETTLE_TIME,
+            min_interval: None,
+            mute_window: None,
+            last_restarts: Arc::new(Mutex::new(HashMap::new())),
+            restart_limiter: None,
+            jitter: None,
+            confirm_window: None,
+            dry_run: false,
+            watchers: Arc::new(Mutex::new(JoinSet::new())),
+            own_stops: OwnStops::default(),
         }
     }
 
+    /// Scale restart delays by each interface's bus-off reputation
+    pub fn with_adaptive_delay(mut self, policy: AdaptiveDelay) -> Self {
+        self.adaptive = Some(policy);
+        self
+    }
+
+    /// Grow restart delays exponentially for interfaces that keep going bus-off
+    pub fn with_backoff(mut self, policy: Backoff) -> Self {
+        self.backoff = Some(policy);
+        self
+    }
+
+    /// Count restarts and track pending restarts in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Send events generated by the manager to `tx`
+    ///
+    /// A bus-off event is sent when an interface is still bus-off after its restart, so the
+    /// receiver can schedule another one, and a gave-up event when the circuit breaker trips.
+    /// Without this both are only logged.
+    pub fn with_event_sender(mut self, tx: mpsc::WeakUnboundedSender<BusEvent>) -> Self {
+        self.event_tx = Some(tx);
+        self
+    }
+
+    /// Suspend restarts of interfaces that go bus-off too often
+    pub fn with_circuit_breaker(mut self, policy: CircuitBreaker) -> Self {
+        self.breaker = Some(policy);
+        self
+    }
+
+    /// Bring up interfaces found stopped (administratively down) when their restart is due,
+    /// instead of issuing a restart that can't succeed
+    pub fn with_restart_stopped(mut self) -> Self {
+        self.restart_stopped = true;
+        self
+    }
+
+    /// Add a random extra delay to every restart
+    pub fn with_jitter(mut self, policy: Jitter) -> Self {
+        self.jitter = Some(Mutex::new(JitterRng::new(policy)));
+        self
+    }
+
+    /// Allow at most `limit` restarts in progress at the same time
+    ///
+    /// A restart is in progress from the moment its delay runs out until the interface has been
+    /// verified to recover, so restarts due together after a shared fault go out a few at a time.
+    /// The rest wait their turn and can still be cancelled meanwhile. Unlimited by default.
+    pub fn with_max_concurrent_restarts(mut self, limit: usize) -> Self {
+        self.restart_limiter = Some(Arc::new(Semaphore::new(limit.max(1))));
+        self
+    }
+
+    /// Only count a restart as successful once the interface has stayed out of bus-off for
+    /// `window`
+    ///
+    /// A bus-off within the window counts as a failed restart in the statistics and metrics. A
+    /// restart that holds clears the interface's restarts from the circuit breaker, so it only
+    /// trips on restarts that keep failing. The backoff is unaffected, since a bus-off within
+    /// the window also schedules the next, longer, restart.
+    pub fn with_confirmation_window(mut self, window: Duration) -> Self {
+        self.confirm_window = Some(window);
+        self
+    }
+
+    /// Log what would be done when a restart is due, without touching the interface
+    ///
+    /// Everything else, from scheduling to the statistics and metrics, behaves as if the restart
+    /// had succeeded. As the interface is never restarted, restarts aren't verified or confirmed.
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Keep interfaces restarted with [`RestartMethod::DownUp`] down this long before bringing
+    /// them back up, [`DEFAULT_SETTLE_TIME`] if not set
+    pub fn with_settle_time(mut self, settle: Duration) -> Self {
+        self.settle_time = settle;
+        self
+    }
+
+    /// Never restart the same interface more often than once per `interval`
+    ///
+    /// A restart that would come sooner after the previous one is delayed until the interval
+    /// has passed, whatever the backoff and other policies say. Unlike the circuit breaker, this
+    /// never gives up.
+    pub fn with_min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some(interval);
+        self
+    }
+
+    /// Mute an interface for `window` after each restart is issued, see
+    /// [`RestartManager::muted_until`]
+    ///
+    /// A controller coming back online can report bus-off and stopped again in a burst before it
+    /// settles, which shouldn't count as a fresh bus-off.
+    pub fn with_mute_window(mut self, window: Duration) -> Self {
+        self.mute_window = Some(window);
+        self
+    }
+
+    /// When the mute window after the interface's last restart ends, while it hasn't yet
+    pub fn muted_until(&self, idx: u32) -> Option<Instant> {
+        let window = self.mute_window?;
+        let last = self.last_restarts.lock().unwrap().get(&idx).copied()?;
+        Some(last + window).filter(|until| *until > Instant::now())
+    }
+
+    /// Controller state of the interface with index `idx`, read through the manager's
+    /// [`CanControl`], if it could be read
+    pub fn current_state(&self, idx: u32) -> Option<CanState> {
+        self.control.state(idx).ok().flatten()
+    }
+
+    /// Controller state of each of `interfaces` that could be read, by interface index, see
+    /// [`CanControl::states`]
+    pub fn current_states(&self, interfaces: &[CanInterfaceInfo]) -> HashMap<u32, CanState> {
+        let idxs: Vec<u32> = interfaces.iter().map(|interface| interface.idx).collect();
+        self.control.states(&idxs)
+    }
+
+    /// Whether a stopped event on the interface with index `idx`, detected at `at`, came from a
+    /// restart taking the interface down
+    pub fn caused_stop(&self, idx: u32, at: Instant) -> bool {
+        self.own_stops.caused(idx, at)
+    }
+
+    /// Whether restarts are only logged, see [`RestartManager::with_dry_run`]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Whether stopped interfaces are brought back up, see [`RestartManager::with_restart_stopped`]
+    pub fn restarts_stopped(&self) -> bool {
+        self.restart_stopped
+    }
+
+    /// Set the link settings re-applied whenever an interface is restarted, or clear them
+    ///
+    /// Takes effect for restarts that are already pending too.
+    pub fn set_link_settings(&self, idx: u32, settings: Option<LinkSettings>) {
+        let mut link_settings = self.link_settings.lock().unwrap();
+        match settings.filter(|settings| !settings.is_empty()) {
+            Some(settings) => link_settings.insert(idx, settings),
+            None => link_settings.remove(&idx),
+        };
+    }
+
+    /// Set how an interface is restarted
+    ///
+    /// Takes effect for restarts that are already pending too.
+    pub fn set_restart_method(&self, idx: u32, method: RestartMethod) {
+        let mut restart_methods = self.restart_methods.lock().unwrap();
+        match method {
+            RestartMethod::Ioctl => restart_methods.remove(&idx),
+            method => restart_methods.insert(idx, method),
+        };
+    }
+
+    /// Set whether an interface's error counters are reset after it is restarted
+    ///
+    /// Takes effect for restarts that are already pending too.
+    pub fn set_reset_counters(&self, idx: u32, enabled: bool) {
+        let mut reset_counters = self.reset_counters.lock().unwrap();
+        if enabled {
+            reset_counters.insert(idx);
+        } else {
+            reset_counters.remove(&idx);
+        }
+    }
+
+    /// Whether the circuit breaker has suspended restarts of an interface
+    pub fn is_tripped(&self, idx: u32) -> bool {
+        let Some(policy) = self.breaker else {
+            return false;
+        };
+        let history = self.restart_history.lock().unwrap();
+        history
+            .get(&idx)
+            .and_then(|h| h.tripped_at)
+            .is_some_and(|tripped_at| !cooldown_over(policy, tripped_at, Instant::now()))
+    }
+
+    /// Indexes of every interface whose restarts are suspended by the circuit breaker
+    pub fn tripped(&self) -> Vec<u32> {
+        let Some(policy) = self.breaker else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let history = self.restart_history.lock().unwrap();
+        let mut tripped: Vec<u32> = history
+            .iter()
+            .filter(|(_, h)| {
+                h.tripped_at
+                    .is_some_and(|tripped_at| !cooldown_over(policy, tripped_at, now))
+            })
+            .map(|(&idx, _)| idx)
+            .collect();
+        tripped.sort();
+        tripped
+    }
+
+    /// Resume restarts of an interface suspended by the circuit breaker, returning whether it
+    /// was tripped
+    ///
+    /// An interface that is still bus-off needs a fresh bus-off to be restarted.
+    pub fn reset_circuit_breaker(&self, idx: u32) -> bool {
+        let tripped = self.is_tripped(idx);
+        self.restart_history.lock().unwrap().remove(&idx);
+        if tripped {
+            info!("Circuit breaker reset, resuming restarts");
+        }
+        tripped
+    }
+
+    /// Record a restart with the circuit breaker, returning whether it may go ahead
+    fn admit_restart(&self, interface: &CanInterfaceInfo) -> bool {
+        let Some(policy) = self.breaker else {
+            return true;
+        };
+        let now = Instant::now();
+        let mut history = self.restart_history.lock().unwrap();
+        let entry = history.entry(interface.idx).or_default();
+
+        if let Some(tripped_at) = entry.tripped_at {
+            if !cooldown_over(policy, tripped_at, now) {
+                debug!("Restarts suspended by circuit breaker, not scheduling");
+                return false;
+            }
+            info!("Circuit breaker cooldown over, resuming restarts");
+            *entry = RestartHistory::default();
+        }
+
+        while entry
+            .recent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) > policy.window)
+        {
+            entry.recent.pop_front();
+        }
+        if entry.recent.len() >= policy.max_restarts {
+            entry.recent.clear();
+            entry.tripped_at = Some(now);
+            match policy.cooldown {
+                Some(cooldown) => error!(
+                    "Giving up: {} restarts within {:?}, suspending restarts for {:?}",
+                    policy.max_restarts, policy.window, cooldown
+                ),
+                None => error!(
+                    "Giving up: {} restarts within {:?}, suspending restarts until reset",
+                    policy.max_restarts, policy.window
+                ),
+            }
+            if let Some(tx) = self.event_tx.as_ref().and_then(|tx| tx.upgrade()) {
+                let _ = tx.send(BusEvent::gave_up(interface.clone()));
+            }
+            return false;
+        }
+        // With a confirmation window only restarts that failed it count, see RestartConfirmation
+        if self.confirm_window.is_none() {
+            entry.recent.push_back(now);
+        }
+        true
+    }
+
+    /// Number of consecutive restarts scheduled for an interface since it was last stable
+    pub fn backoff_attempts(&self, idx: u32) -> u32 {
+        self.attempts
+            .lock()
+            .unwrap()
+            .get(&idx)
+            .map_or(0, |a| a.attempts)
+    }
+
+    /// Delay the next restart of an interface would use given the configured `base` delay,
+    /// with backoff applied
+    pub fn backoff_delay(&self, idx: u32, base: Duration) -> Duration {
+        backoff_delay(self.backoff, self.backoff_attempts(idx) + 1, base)
+    }
+
+    /// Current decayed bus-off score of an interface, if adaptive delays are enabled
+    pub fn adaptive_score(&self, idx: u32) -> Option<f64> {
+        let policy = self.adaptive?;
+        let reputations = self.reputations.lock().unwrap();
+        Some(
+            reputations
+                .get(&idx)
+                .map_or(0.0, |r| r.current(policy.half_life, Instant::now())),
+        )
+    }
+
+    /// Delay that would currently be used for an interface given the configured `base` delay
+    pub fn adaptive_delay(&self, idx: u32, base: Duration) -> Duration {
+        let Some(policy) = self.adaptive else {
+            return base;
+        };
+        let score = self.adaptive_score(idx).unwrap_or(0.0);
+        let multiplier = 2f64
+            .powf(score - 1.0)
+            .clamp(policy.min_multiplier, policy.max_multiplier);
+        base.mul_f64(multiplier)
+    }
+
+    /// Record that an interface recovered, improving its reputation
+    pub fn record_healthy(&self, interface: &CanInterfaceInfo) {
+        self.adjust_score(interface.idx, |score| score / 2.0);
+    }
+
+    fn adjust_score(&self, idx: u32, f: impl FnOnce(f64) -> f64) {
+        let Some(policy) = self.adaptive else {
+            return;
+        };
+        let now = Instant::now();
+        let mut reputations = self.reputations.lock().unwrap();
+        let score = reputations
+            .get(&idx)
+            .map_or(0.0, |r| r.current(policy.half_life, now));
+        reputations.insert(
+            idx,
+            Reputation {
+                score: f(score),
+                updated: now,
+            },
+        );
+    }
+
+    /// Stretch `delay` so the restart comes at least the minimum interval after the last one
+    fn spaced_delay(&self, idx: u32, delay: Duration) -> Duration {
+        let Some(min_interval) = self.min_interval else {
+            return delay;
+        };
+        let Some(last) = self.last_restarts.lock().unwrap().get(&idx).copied() else {
+            return delay;
+        };
+        let earliest = (last + min_interval).saturating_duration_since(Instant::now());
+        if earliest <= delay {
+            return delay;
+        }
+        info!(
+            "Last restarted {:?} ago, waiting for the minimum interval of {:?}",
+            last.elapsed(),
+            min_interval
+        );
+        earliest
+    }
+
     /// Schedule a delayed restart for a bus-off interface
+    ///
+    /// Log messages don't name the interface; they are emitted in the caller's span, which the
+    /// restart task carries over. [`Monitor`](crate::Monitor) calls this inside the interface's
+    /// `can` span.
     pub async fn schedule_restart(&self, interface: CanInterfaceInfo, delay: Duration) {
+        self.schedule(interface, delay, RestartTrigger::BusOff)
+            .await;
+    }
+
+    /// Schedule a delayed restart for an interface that stayed error-passive for too long
+    ///
+    /// The kernel only restarts bus-off interfaces on request, so one still error-passive when
+    /// the restart is due is taken down and back up instead. Otherwise as
+    /// [`RestartManager::schedule_restart`].
+    pub async fn schedule_passive_restart(&self, interface: CanInterfaceInfo, delay: Duration) {
+        self.schedule(interface, delay, RestartTrigger::PassiveDwell)
+            .await;
+    }
+
+    async fn schedule(
+        &self,
+        interface: CanInterfaceInfo,
+        delay: Duration,
+        trigger: RestartTrigger,
+    ) {
         // Only schedule if there isn't already a pending restart for this interface
         {
             let pending_tasks = self.pending_tasks.read().await;
-            if pending_tasks.contains_key(&interface.idx) {
+            if pending_tasks.contains_key(&interface) {
                 return;
             }
         }
@@ -32,45 +729,362 @@ impl RestartManager {
         let mut pending_tasks = self.pending_tasks.write().await;
 
         // Check again in case another thread added a task between the locks
-        if pending_tasks.contains_key(&interface.idx) {
+        if pending_tasks.contains_key(&interface) {
+            return;
+        }
+        if !self.admit_restart(&interface) {
             return;
         }
-        println!(
-            "{}: bus_off, scheduling restart in {:?}",
-            interface.name, delay
-        );
+
+        // Score the incident before picking the delay, so it counts against this restart too
+        self.adjust_score(interface.idx, |score| score + 1.0);
+        let delay = self.adaptive_delay(interface.idx, delay);
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let entry = attempts.entry(interface.idx).or_default();
+            entry.attempts = entry.attempts.saturating_add(1);
+            entry.attempts
+        };
+        let delay = backoff_delay(self.backoff, attempt, delay);
+        let delay = match &self.jitter {
+            Some(jitter) => delay + jitter.lock().unwrap().next_delay(),
+            None => delay,
+        };
+        let delay = self.spaced_delay(interface.idx, delay);
+        if self.backoff.is_some() && attempt > 1 {
+            info!(
+                target: log::RESTART,
+                "{}, restart attempt {}, scheduling restart in {:?}",
+                trigger.name(),
+                attempt,
+                delay
+            );
+        } else {
+            info!(
+                target: log::RESTART,
+                "{}, scheduling restart in {:?}",
+                trigger.name(),
+                delay
+            );
+        }
 
         let pending_tasks_arc = Arc::clone(&self.pending_tasks);
+        let control = Arc::clone(&self.control);
+        let total_restarts = Arc::clone(&self.total_restarts);
+        let stats = Arc::clone(&self.stats);
+        let attempts = Arc::clone(&self.attempts);
+        let backoff = self.backoff;
+        let metrics = self.metrics.clone();
+        let event_tx = self.event_tx.clone();
+        let restart_stopped = self.restart_stopped;
+        let link_settings = Arc::clone(&self.link_settings);
+        let restart_methods = Arc::clone(&self.restart_methods);
+        let reset_counters = Arc::clone(&self.reset_counters);
+        let settle_time = self.settle_time;
+        let restart_limiter = self.restart_limiter.clone();
+        let confirm_window = self.confirm_window;
+        let restart_history = Arc::clone(&self.restart_history);
+        let last_restarts = Arc::clone(&self.last_restarts);
+        let dry_run = self.dry_run;
+        let watchers = Arc::clone(&self.watchers);
+        let own_stops = self.own_stops.clone();
 
-        // Store the interface index before moving interface into the task
-        let interface_idx = interface.idx;
+        // Keep the key before moving interface into the task
+        let key = interface.clone();
 
-        let task = tokio::spawn(async move {
-            tokio::time::sleep(delay).await;
+        let task = tokio::spawn(
+            async move {
+                tokio::time::sleep(delay).await;
 
-            // Performing the restart must be atomic with removing from the pending list.
-            // Hold lock until restart is done.
-            let mut pending_tasks = pending_tasks_arc.write().await;
-            pending_tasks.remove(&interface.idx);
+                // Held until the restart has been verified. Still pending while waiting for it.
+                let _permit = match &restart_limiter {
+                    Some(limiter) => {
+                        if limiter.available_permits() == 0 {
+                            info!("Restart due, waiting for other restarts to finish");
+                        }
+                        limiter.acquire().await.ok()
+                    }
+                    None => None,
+                };
 
-            do_restart(interface);
-        });
+                // Stays pending until issued, so no other restart is scheduled meanwhile and
+                // shutdown still waits for it. The lock isn't held while restarting, so a slow
+                // restart doesn't hold up other interfaces.
+                total_restarts.fetch_add(1, Ordering::Relaxed);
+                last_restarts
+                    .lock()
+                    .unwrap()
+                    .insert(interface.idx, Instant::now());
+                let plan = RestartPlan::read(
+                    interface.idx,
+                    &link_settings,
+                    &restart_methods,
+                    &reset_counters,
+                    settle_time,
+                    dry_run,
+                )
+                .for_trigger(trigger);
+                let restarted = do_restart(
+                    control.as_ref(),
+                    &interface,
+                    metrics.as_deref(),
+                    restart_stopped,
+                    plan,
+                    &own_stops,
+                )
+                .await;
+                record_stats(&stats, interface.idx, restarted);
+                {
+                    let mut pending_tasks = pending_tasks_arc.write().await;
+                    pending_tasks.remove(&interface);
+                    update_pending_gauge(metrics.as_deref(), &pending_tasks);
+                }
+                if !restarted || dry_run {
+                    return;
+                }
+
+                if let Some(state) = verify_restart(control.as_ref(), &interface).await {
+                    warn!(
+                        "Restart issued but interface still {:?} after {:?}",
+                        state, VERIFY_TIMEOUT
+                    );
+                    if state == CanState::BusOff
+                        && let Some(tx) = event_tx.as_ref().and_then(|tx| tx.upgrade())
+                    {
+                        let _ = tx.send(BusEvent::bus_off(
+                            interface,
+                            BusEventSource::Poll(CanState::BusOff),
+                        ));
+                    }
+                    return;
+                }
+
+                let generation = {
+                    let mut attempts = attempts.lock().unwrap();
+                    let entry = attempts.entry(interface.idx).or_default();
+                    entry.generation += 1;
+                    entry.generation
+                };
+                // Both checked separately so shutdown doesn't wait out their windows
+                let mut watchers = watchers.lock().unwrap();
+                while watchers.try_join_next().is_some() {}
+                if let Some(window) = confirm_window {
+                    let confirmation = RestartConfirmation {
+                        control: Arc::clone(&control),
+                        attempts: Arc::clone(&attempts),
+                        restart_history,
+                        stats,
+                        metrics,
+                        interface: interface.clone(),
+                        generation,
+                        window,
+                    };
+                    watchers.spawn(confirmation.run().in_current_span());
+                }
+                if let Some(backoff) = backoff {
+                    watchers.spawn(
+                        reset_when_stable(control, attempts, interface, generation, backoff)
+                            .in_current_span(),
+                    );
+                }
+            }
+            .in_current_span(),
+        );
 
-        pending_tasks.insert(interface_idx, task);
+        pending_tasks.insert(
+            key,
+            PendingRestart {
+                task,
+                due: Instant::now() + delay,
+            },
+        );
+        update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
     }
 
     /// Cancel any pending restart for an interface
     pub async fn cancel_restart(&self, interface: &CanInterfaceInfo) {
-        if let Some(task) = self.pending_tasks.write().await.remove(&interface.idx) {
-            task.abort();
-            println!("{}: cancelled pending restart", interface.name);
+        let mut pending_tasks = self.pending_tasks.write().await;
+        if let Some(pending) = pending_tasks.remove(interface) {
+            pending.task.abort();
+            info!("Cancelled pending restart");
+        }
+        update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
+    }
+
+    /// Restart an interface right away, regardless of its state and of the restart policies
+    ///
+    /// Any pending restart is cancelled first. Returns whether the restart succeeded.
+    pub async fn restart_now(&self, interface: &CanInterfaceInfo) -> bool {
+        {
+            let mut pending_tasks = self.pending_tasks.write().await;
+            if let Some(pending) = pending_tasks.remove(interface) {
+                pending.task.abort();
+            }
+            update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
+        }
+
+        self.total_restarts.fetch_add(1, Ordering::Relaxed);
+        self.last_restarts
+            .lock()
+            .unwrap()
+            .insert(interface.idx, Instant::now());
+        let plan = RestartPlan::read(
+            interface.idx,
+            &self.link_settings,
+            &self.restart_methods,
+            &self.reset_counters,
+            self.settle_time,
+            self.dry_run,
+        );
+        let restarted = do_restart(
+            self.control.as_ref(),
+            interface,
+            self.metrics.as_deref(),
+            self.restart_stopped,
+            plan,
+            &self.own_stops,
+        )
+        .await;
+        record_stats(&self.stats, interface.idx, restarted);
+        restarted
+    }
+
+    /// Cancel every pending restart, returning how many were cancelled
+    pub async fn cancel_all(&self) -> usize {
+        let mut pending_tasks = self.pending_tasks.write().await;
+        let cancelled = pending_tasks.len();
+        for (_, pending) in pending_tasks.drain() {
+            pending.task.abort();
+        }
+        update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
+        cancelled
+    }
+
+    /// Wait for every pending restart to complete, then stop confirming restarts
+    ///
+    /// Pending restarts are taken over by the caller, so any scheduled while this is waiting are
+    /// not waited for.
+    pub async fn shutdown(&self) {
+        for task in self.take_pending().await {
+            let _ = task.await;
+        }
+        self.watchers.lock().unwrap().abort_all();
+    }
+
+    /// Wait up to `grace` for pending restarts to complete, cancel the rest and return how many
+    /// were cancelled
+    ///
+    /// Restarts still being confirmed are left unconfirmed.
+    pub async fn shutdown_timeout(&self, grace: Duration) -> usize {
+        let deadline = Instant::now() + grace;
+        let mut cancelled = 0;
+        for mut task in self.take_pending().await {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                task.abort();
+                cancelled += 1;
+            }
         }
+        self.watchers.lock().unwrap().abort_all();
+        cancelled
+    }
+
+    async fn take_pending(&self) -> Vec<JoinHandle<()>> {
+        let mut pending_tasks = self.pending_tasks.write().await;
+        let tasks = pending_tasks
+            .drain()
+            .map(|(_, pending)| pending.task)
+            .collect();
+        update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
+        tasks
+    }
+
+    /// Whether a restart is pending for an interface
+    pub async fn is_pending(&self, idx: u32) -> bool {
+        self.pending_tasks
+            .read()
+            .await
+            .keys()
+            .any(|interface| interface.idx == idx)
+    }
+
+    /// Time left until the pending restart of an interface, if one is pending
+    pub async fn restart_due_in(&self, idx: u32) -> Option<Duration> {
+        self.pending_tasks
+            .read()
+            .await
+            .iter()
+            .find(|(interface, _)| interface.idx == idx)
+            .map(|(_, pending)| pending.due.saturating_duration_since(Instant::now()))
     }
 
     /// Get the number of pending restart tasks
     pub async fn pending_count(&self) -> usize {
         self.pending_tasks.read().await.len()
     }
+
+    /// Get the number of restarts issued so far
+    pub fn total_restarts(&self) -> u64 {
+        self.total_restarts.load(Ordering::Relaxed)
+    }
+
+    /// Restarts issued on an interface, if any have been
+    pub fn stats(&self, idx: u32) -> Option<RestartStats> {
+        self.stats.lock().unwrap().get(&idx).copied()
+    }
+
+    /// Restarts issued on every interface that has been restarted, by interface index
+    pub fn all_stats(&self) -> HashMap<u32, RestartStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Restart counts and circuit breaker state of an interface, for saving across runs
+    pub fn saved_state(&self, idx: u32) -> InterfaceState {
+        let stats = self.stats(idx).unwrap_or_default();
+        let history = self.restart_history.lock().unwrap();
+        let history = history.get(&idx);
+        InterfaceState {
+            restarts: stats.restarts,
+            failures: stats.failures,
+            last_restart: stats.last_restart.map(|time| {
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+            }),
+            recent_restarts: history
+                .map(|h| h.recent.iter().copied().map(state::to_unix).collect())
+                .unwrap_or_default(),
+            tripped_at: history.and_then(|h| h.tripped_at).map(state::to_unix),
+        }
+    }
+
+    /// Carry on from the state of an interface saved by a previous run
+    ///
+    /// Replaces its restart counts and circuit breaker state. Should be called before any
+    /// restarts of the interface are scheduled.
+    pub fn restore_state(&self, idx: u32, saved: &InterfaceState) {
+        self.stats.lock().unwrap().insert(
+            idx,
+            RestartStats {
+                restarts: saved.restarts,
+                failures: saved.failures,
+                last_restart: saved
+                    .last_restart
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .map(|since_epoch| SystemTime::UNIX_EPOCH + since_epoch),
+            },
+        );
+        self.restart_history.lock().unwrap().insert(
+            idx,
+            RestartHistory {
+                recent: saved
+                    .recent_restarts
+                    .iter()
+                    .filter_map(|&secs| state::from_unix(secs))
+                    .collect(),
+                tripped_at: saved.tripped_at.and_then(state::from_unix),
+            },
+        );
+    }
 }
 
 impl Default for RestartManager {
@@ -79,15 +1093,1086 @@ impl Default for RestartManager {
     }
 }
 
-/// Performs the actual restart for a CAN interface
-fn do_restart(interface: CanInterfaceInfo) {
-    use socketcan::CanInterface;
+/// Pending restarts are aborted, so none goes ahead once its manager is gone. Call
+/// [`RestartManager::shutdown`] first to let them finish instead.
+impl Drop for RestartManager {
+    fn drop(&mut self) {
+        self.watchers.lock().unwrap().abort_all();
+        // Only contended for a moment, by a restart task updating the list
+        let Ok(mut pending_tasks) = self.pending_tasks.try_write() else {
+            return;
+        };
+        let aborted = pending_tasks.len();
+        for (_, pending) in pending_tasks.drain() {
+            pending.task.abort();
+        }
+        if aborted > 0 {
+            debug!(
+                "Restart manager dropped, aborted {} pending restarts",
+                aborted
+            );
+            update_pending_gauge(self.metrics.as_deref(), &pending_tasks);
+        }
+    }
+}
+
+/// What a restart was scheduled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RestartTrigger {
+    /// The interface went bus-off, or was restarted on request
+    BusOff,
+    /// The interface stayed error-passive for too long
+    PassiveDwell,
+}
+
+impl RestartTrigger {
+    fn name(self) -> &'static str {
+        match self {
+            Self::BusOff => "bus_off",
+            Self::PassiveDwell => "passive_dwell",
+        }
+    }
+}
+
+/// How an interface is to be restarted, as configured when the restart is due
+#[derive(Debug, Clone, Copy)]
+struct RestartPlan {
+    /// Link settings re-applied instead of restarting, unless applying them fails
+    settings: Option<LinkSettings>,
+    /// How the interface is restarted otherwise
+    method: RestartMethod,
+    /// Reset the error counters after a successful restart if they are still raised
+    reset_counters: bool,
+    /// How long [`RestartMethod::DownUp`] keeps the interface down
+    settle_time: Duration,
+    /// Only log what would be done
+    dry_run: bool,
+    /// What the restart is for
+    trigger: RestartTrigger,
+}
+
+impl RestartPlan {
+    /// Plan for the interface with index `idx` from its per-interface settings
+    fn read(
+        idx: u32,
+        link_settings: &Mutex<HashMap<u32, LinkSettings>>,
+        restart_methods: &Mutex<HashMap<u32, RestartMethod>>,
+        reset_counters: &Mutex<HashSet<u32>>,
+        settle_time: Duration,
+        dry_run: bool,
+    ) -> Self {
+        Self {
+            settings: link_settings.lock().unwrap().get(&idx).copied(),
+            method: restart_methods
+                .lock()
+                .unwrap()
+                .get(&idx)
+                .copied()
+                .unwrap_or_default(),
+            reset_counters: reset_counters.lock().unwrap().contains(&idx),
+            settle_time,
+            dry_run,
+            trigger: RestartTrigger::BusOff,
+        }
+    }
+
+    fn for_trigger(self, trigger: RestartTrigger) -> Self {
+        Self { trigger, ..self }
+    }
+}
+
+/// Performs the actual restart for a CAN interface as planned, returning whether it succeeded
+///
+/// With `restart_stopped`, an interface that is stopped is brought up instead.
+async fn do_restart(
+    control: &dyn CanControl,
+    interface: &CanInterfaceInfo,
+    metrics: Option<&Metrics>,
+    restart_stopped: bool,
+    plan: RestartPlan,
+    own_stops: &OwnStops,
+) -> bool {
+    let RestartPlan {
+        settings,
+        method,
+        reset_counters,
+        settle_time,
+        dry_run,
+        trigger,
+    } = plan;
+    let state = control.state(interface.idx);
+    let stopped = restart_stopped && matches!(state, Ok(Some(CanState::Stopped)));
+    // The kernel refuses the restart request unless the interface is bus-off
+    let method = match state {
+        Ok(Some(CanState::ErrorPassive))
+            if trigger == RestartTrigger::PassiveDwell && method == RestartMethod::Ioctl =>
+        {
+            info!("Interface is error-passive rather than bus-off, cycling it instead");
+            RestartMethod::DownUp
+        }
+        _ => method,
+    };
+    let reapplied = match settings {
+        Some(settings) if !dry_run && !stopped => {
+            Some(reconfigure(control, interface, settings, own_stops))
+        }
+        _ => None,
+    };
+    let result = if dry_run {
+        match (settings, method) {
+            _ if stopped => info!("Dry run, would bring the interface up now"),
+            (Some(settings), _) => info!("Dry run, would re-apply {} and restart now", settings),
+            (None, RestartMethod::DownUp) => {
+                info!("Dry run, would take the interface down and back up now")
+            }
+            (None, RestartMethod::Ioctl) => info!("Dry run, would restart the interface now"),
+        }
+        Ok(())
+    } else if stopped {
+        info!("Interface is stopped, bringing it up");
+        control.bring_up(interface.idx)
+    } else if reapplied == Some(true) {
+        Ok(())
+    } else if reapplied == Some(false)
+        && matches!(
+            control.state(interface.idx),
+            Ok(Some(
+                CanState::ErrorActive | CanState::ErrorWarning | CanState::ErrorPassive
+            ))
+        )
+    {
+        // The kernel would refuse a restart request now that it isn't bus-off anymore
+        Err(std::io::Error::other(
+            "interface came back up without the link settings",
+        ))
+    } else if method == RestartMethod::DownUp {
+        info!(
+            "Restarting interface by taking it down for {:?}",
+            settle_time
+        );
+        cycle(control, interface, settle_time, own_stops).await
+    } else {
+        info!("Restarting interface");
+        control.restart(interface.idx)
+    };
+
+    let mut restarted = match result {
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            error!("Restart failed: {}. {}", e, PERMISSION_HINT);
+            false
+        }
+        Err(e) => {
+            error!("Restart failed: {}", e);
+            false
+        }
+    };
+    if restarted && reapplied == Some(false) {
+        error!("Restarted without the link settings, counting the restart as failed");
+        restarted = false;
+    }
+    if restarted && reset_counters {
+        if dry_run {
+            info!("Dry run, would reset the error counters if they are still raised");
+        } else if let Err(e) =
+            reset_error_counters(control, interface, settle_time, own_stops).await
+        {
+            error!("Restart failed while resetting the error counters: {}", e);
+            restarted = false;
+        }
+    }
+    if let Some(metrics) = metrics {
+        metrics.record_restart(interface, restarted);
+    }
+    restarted
+}
+
+/// Reset the error counters of a restarted interface if they are still raised
+///
+/// Unless the driver can reset them directly, the interface is [cycled](cycle) for
+/// `settle_time`. Fails only if that fails, which may leave the interface down; anything else is
+/// only logged.
+async fn reset_error_counters(
+    control: &dyn CanControl,
+    interface: &CanInterfaceInfo,
+    settle_time: Duration,
+    own_stops: &OwnStops,
+) -> std::io::Result<()> {
+    let counters = match control.error_counters(interface.idx) {
+        Ok(Some(counters)) => counters,
+        Ok(None) => {
+            info!("Driver doesn't report error counters, not resetting them");
+            return Ok(());
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+            info!("Reading error counters is not supported, not resetting them");
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("Failed to read error counters, not resetting them: {}", e);
+            return Ok(());
+        }
+    };
+    if counters.tx == 0 && counters.rx == 0 {
+        debug!("Error counters already clear after restart");
+        return Ok(());
+    }
+    match control.reset_error_counters(interface.idx) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+            info!(
+                "Resetting error counters by taking the interface down for {:?}",
+                settle_time
+            );
+            cycle(control, interface, settle_time, own_stops).await?;
+        }
+        Err(e) => {
+            warn!("Failed to reset error counters: {}", e);
+            return Ok(());
+        }
+    }
+    match control.error_counters(interface.idx) {
+        Ok(Some(after)) if after.tx != 0 || after.rx != 0 => warn!(
+            "Error counters still at TX={} RX={} after resetting them",
+            after.tx, after.rx
+        ),
+        _ => info!(
+            "Reset error counters from TX={} RX={}",
+            counters.tx, counters.rx
+        ),
+    }
+    Ok(())
+}
+
+/// Take an interface down, wait `settle_time` and bring it back up
+///
+/// The interface is brought back up even if this is cancelled while it is down.
+async fn cycle(
+    control: &dyn CanControl,
+    interface: &CanInterfaceInfo,
+    settle_time: Duration,
+    own_stops: &OwnStops,
+) -> std::io::Result<()> {
+    let _down = own_stops.begin(interface.idx);
+    control.bring_down(interface.idx)?;
+    let down = KeptDown {
+        control,
+        idx: interface.idx,
+    };
+    tokio::time::sleep(settle_time).await;
+    down.bring_up()
+}
+
+/// An interface taken down by [`cycle`], brought back up when dropped
+struct KeptDown<'a> {
+    control: &'a dyn CanControl,
+    idx: u32,
+}
+
+impl KeptDown<'_> {
+    /// Bring the interface back up now, returning whether that worked
+    fn bring_up(self) -> std::io::Result<()> {
+        let result = self.control.bring_up(self.idx);
+        // Not again on drop
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl Drop for KeptDown<'_> {
+    fn drop(&mut self) {
+        warn!("Restart cancelled while the interface was down, bringing it back up");
+        if let Err(e) = self.control.bring_up(self.idx) {
+            error!("Failed to bring the interface back up: {}", e);
+        }
+    }
+}
+
+/// Count a restart towards an interface's statistics
+fn record_stats(stats: &Mutex<HashMap<u32, RestartStats>>, idx: u32, restarted: bool) {
+    let mut stats = stats.lock().unwrap();
+    let entry = stats.entry(idx).or_default();
+    entry.restarts += 1;
+    if !restarted {
+        entry.failures += 1;
+    }
+    entry.last_restart = Some(SystemTime::now());
+}
+
+/// Re-apply link settings, returning whether the interface came back up with them
+fn reconfigure(
+    control: &dyn CanControl,
+    interface: &CanInterfaceInfo,
+    settings: LinkSettings,
+    own_stops: &OwnStops,
+) -> bool {
+    info!(
+        "Re-applying {} and bringing the interface back up",
+        settings
+    );
+    let _down = own_stops.begin(interface.idx);
+    match control.reconfigure(interface.idx, settings) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to re-apply {}: {}", settings, e);
+            false
+        }
+    }
+}
+
+/// Whether restarts suspended at `tripped_at` may resume at `now`
+fn cooldown_over(policy: CircuitBreaker, tripped_at: Instant, now: Instant) -> bool {
+    policy
+        .cooldown
+        .is_some_and(|cooldown| now.duration_since(tripped_at) >= cooldown)
+}
+
+/// Poll a restarted interface until it leaves bus-off, returning the state it is stuck in if it
+/// doesn't within [`VERIFY_TIMEOUT`]
+///
+/// Interfaces whose state can't be read are assumed to have recovered.
+async fn verify_restart(
+    control: &dyn CanControl,
+    interface: &CanInterfaceInfo,
+) -> Option<CanState> {
+    let deadline = Instant::now() + VERIFY_TIMEOUT;
+    loop {
+        let state = match control.state(interface.idx) {
+            Ok(Some(state @ (CanState::BusOff | CanState::Stopped))) => state,
+            _ => return None,
+        };
+        if Instant::now() >= deadline {
+            return Some(state);
+        }
+        tokio::time::sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+fn update_pending_gauge(
+    metrics: Option<&Metrics>,
+    pending_tasks: &HashMap<CanInterfaceInfo, PendingRestart>,
+) {
+    if let Some(metrics) = metrics {
+        metrics.set_pending_restarts(pending_tasks.len());
+    }
+}
+
+/// Restart delay for the given attempt: `base * 2^(attempt - 1)`, capped at the policy maximum
+fn backoff_delay(backoff: Option<Backoff>, attempt: u32, base: Duration) -> Duration {
+    let Some(backoff) = backoff else {
+        return base;
+    };
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    // The cap never shortens the configured delay itself
+    base.saturating_mul(factor).min(backoff.max_delay).max(base)
+}
+
+/// Clear an interface's backoff once it has stayed error-active for the stable window
+async fn reset_when_stable(
+    control: Arc<dyn CanControl>,
+    attempts: Arc<Mutex<HashMap<u32, RestartAttempts>>>,
+    interface: CanInterfaceInfo,
+    generation: u64,
+    backoff: Backoff,
+) {
+    tokio::time::sleep(backoff.stable_after).await;
+    if !matches!(
+        control.state(interface.idx),
+        Ok(Some(CanState::ErrorActive))
+    ) {
+        return;
+    }
+    let mut attempts = attempts.lock().unwrap();
+    // Only if it hasn't been restarted again in the meantime
+    if attempts
+        .get(&interface.idx)
+        .is_some_and(|a| a.generation == generation && a.attempts > 0)
+    {
+        debug!(
+            "Stable for {:?}, resetting restart backoff",
+            backoff.stable_after
+        );
+        attempts.remove(&interface.idx);
+    }
+}
+
+/// A restart being watched for the confirmation window, see [`RestartConfirmation::run`]
+struct RestartConfirmation {
+    control: Arc<dyn CanControl>,
+    attempts: Arc<Mutex<HashMap<u32, RestartAttempts>>>,
+    restart_history: Arc<Mutex<HashMap<u32, RestartHistory>>>,
+    stats: Arc<Mutex<HashMap<u32, RestartStats>>>,
+    metrics: Option<Arc<Metrics>>,
+    interface: CanInterfaceInfo,
+    /// Restart generation being confirmed
+    generation: u64,
+    window: Duration,
+}
+
+impl RestartConfirmation {
+    /// Count the restart as failed if the interface goes bus-off again within the window, and
+    /// clear it from the circuit breaker if it doesn't
+    ///
+    /// A failed restart counts towards the circuit breaker and keeps the backoff from being
+    /// reset by this restart's stability check.
+    async fn run(self) {
+        let Self {
+            control,
+            attempts,
+            restart_history,
+            stats,
+            metrics,
+            interface,
+            generation,
+            window,
+        } = self;
+        let deadline = Instant::now() + window;
+        while Instant::now() < deadline {
+            if matches!(control.state(interface.idx), Ok(Some(CanState::BusOff))) {
+                warn!(
+                    "Bus-off again within {:?} of restart, counting it as failed",
+                    window
+                );
+                if let Some(entry) = stats.lock().unwrap().get_mut(&interface.idx) {
+                    entry.failures += 1;
+                }
+                if let Some(metrics) = &metrics {
+                    metrics.record_failed_restart(&interface);
+                }
+                if let Some(entry) = attempts.lock().unwrap().get_mut(&interface.idx)
+                    && entry.generation == generation
+                {
+                    entry.generation += 1;
+                }
+                restart_history
+                    .lock()
+                    .unwrap()
+                    .entry(interface.idx)
+                    .or_default()
+                    .recent
+                    .push_back(Instant::now());
+                return;
+            }
+            tokio::time::sleep(VERIFY_INTERVAL.min(window)).await;
+        }
+
+        // Only if it hasn't been restarted again in the meantime
+        let current = attempts
+            .lock()
+            .unwrap()
+            .get(&interface.idx)
+            .map(|a| a.generation);
+        if current != Some(generation) {
+            return;
+        }
+        debug!("Out of bus-off for {:?}, restart confirmed", window);
+        if let Some(history) = restart_history.lock().unwrap().get_mut(&interface.idx)
+            && history.tripped_at.is_none()
+        {
+            history.recent.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::fake::FakeBackend,
+        events::{BusEventType, ErrorCounters},
+        state::SavedState,
+        testing::{EventSink, can0, can1},
+    };
+
+    #[test]
+    fn jitter_draws_a_new_delay_each_time() {
+        let policy = Jitter {
+            spread: Duration::from_secs(10),
+            seed: 42,
+        };
+        let mut rng = JitterRng::new(policy);
+        let first = rng.next_delay();
+        assert_eq!(first, Duration::from_nanos(1_387_329_660));
+        assert_ne!(rng.next_delay(), first);
+        assert_eq!(JitterRng::new(policy).next_delay(), first);
+
+        let mut none = JitterRng::new(Jitter {
+            spread: Duration::ZERO,
+            seed: 42,
+        });
+        assert_eq!(none.next_delay(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_caused_by_a_cycle_are_told_apart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::ZERO);
+        manager.set_restart_method(1, RestartMethod::DownUp);
+        backend.set_state(1, CanState::BusOff);
+        let before = Instant::now();
+
+        assert!(manager.restart_now(&can0()).await);
+        assert_eq!(backend.bring_downs(), vec![1]);
+        assert!(manager.caused_stop(1, Instant::now()));
+        assert!(!manager.caused_stop(1, before - Duration::from_millis(1)));
+        assert!(!manager.caused_stop(1, Instant::now() + 2 * OWN_STOP_SLACK));
+        assert!(!manager.caused_stop(2, Instant::now()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_settings_are_not_followed_by_a_restart_request() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+        manager.set_link_settings(
+            1,
+            Some(LinkSettings {
+                bitrate: Some(500_000),
+                restart_ms: None,
+            }),
+        );
+        backend.set_rejects_settings(1, true);
+        backend.set_state(1, CanState::BusOff);
+
+        assert!(!manager.restart_now(&can0()).await);
+        assert_eq!(backend.reconfigured().len(), 1);
+        assert!(backend.restarts().is_empty());
+        assert!(manager.caused_stop(1, Instant::now()));
+        assert_eq!(manager.stats(1).unwrap().failures, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bus_off_within_the_confirmation_window_counts_as_a_failed_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_confirmation_window(Duration::from_millis(200))
+            .with_backoff(Backoff {
+                max_delay: Duration::from_secs(1),
+                stable_after: Duration::from_millis(300),
+            })
+            .with_circuit_breaker(CircuitBreaker {
+                max_restarts: 2,
+                window: Duration::from_secs(60),
+                cooldown: None,
+            });
+
+        // Each restart comes back up and goes bus-off again right away, which is only noticed by
+        // the confirmation. It recovers by itself before the backoff's stability check.
+        for _ in 0..2 {
+            manager.schedule_restart(can0(), Duration::ZERO).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            backend.set_state(1, CanState::BusOff);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            backend.set_state(1, CanState::ErrorActive);
+        }
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(manager.stats(1).unwrap().failures, 2);
+        assert_eq!(manager.backoff_attempts(1), 2);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        assert!(manager.is_tripped(1));
+        assert_eq!(backend.restarts(), vec![1, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cycles_hold_up_neither_other_interfaces_nor_leave_the_interface_down() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::from_secs(60));
+        manager.set_restart_method(1, RestartMethod::DownUp);
+        backend.set_state(1, CanState::BusOff);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.bring_downs(), vec![1]);
+
+        // Still settling, which doesn't keep restarts of other interfaces waiting
+        backend.set_state(2, CanState::BusOff);
+        tokio::time::timeout(Duration::from_secs(1), manager.restart_now(&can1()))
+            .await
+            .expect("restart held up by another interface settling");
+        assert_eq!(backend.restarts(), vec![2]);
+
+        // Cancelled while down, it comes back up anyway
+        drop(manager);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.bring_ups(), vec![1]);
+        assert_eq!(backend.state(1).unwrap(), Some(CanState::ErrorActive));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_denied_by_the_kernel_count_as_failed() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+        backend.set_state(1, CanState::BusOff);
+        backend.set_denied(1, true);
+
+        // What do_restart matches on to add the permission hint
+        let denied = backend.restart(1).unwrap_err();
+        assert_eq!(denied.kind(), std::io::ErrorKind::PermissionDenied);
+
+        assert!(!manager.restart_now(&can0()).await);
+        let stats = manager.stats(1).unwrap();
+        assert_eq!((stats.restarts, stats.failures), (1, 1));
+        assert!(backend.restarts().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_passive_dwell_restarts_cycle_error_passive_interfaces() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::ZERO);
+        backend.set_state(1, CanState::ErrorPassive);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+        assert!(backend.bring_downs().is_empty());
+        assert_eq!(backend.restarts(), vec![1]);
+
+        backend.set_state(1, CanState::ErrorPassive);
+        manager
+            .schedule_passive_restart(can0(), Duration::ZERO)
+            .await;
+        manager.shutdown().await;
+        assert_eq!(backend.bring_downs(), vec![1]);
+        assert_eq!(backend.restarts(), vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_restart_reaches_backend() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        // A second bus-off while the first restart is pending is deduplicated
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+        assert_eq!(manager.pending_count().await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_waits_for_pending_restarts() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+
+        manager
+            .schedule_restart(can0(), Duration::from_millis(20))
+            .await;
+        manager.shutdown().await;
+
+        assert_eq!(backend.restarts(), vec![1]);
+        assert_eq!(manager.total_restarts(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_the_manager_aborts_pending_restarts() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+        manager
+            .schedule_restart(can0(), Duration::from_millis(50))
+            .await;
+
+        drop(manager);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(backend.restarts().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_timeout_cancels_slow_restarts() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+
+        manager
+            .schedule_restart(can0(), Duration::from_secs(60))
+            .await;
+        let cancelled = manager.shutdown_timeout(Duration::from_millis(10)).await;
+
+        assert_eq!(cancelled, 1);
+        assert!(backend.restarts().is_empty());
+        assert_eq!(manager.pending_count().await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_grows_and_resets_once_stable() {
+        let backend = FakeBackend::new();
+        let manager =
+            RestartManager::with_control(Arc::new(backend.clone())).with_backoff(Backoff {
+                max_delay: Duration::from_millis(30),
+                stable_after: Duration::from_millis(100),
+            });
+        let base = Duration::from_millis(10);
+
+        assert_eq!(manager.backoff_delay(1, base), base);
+        manager.schedule_restart(can0(), base).await;
+        assert_eq!(manager.backoff_attempts(1), 1);
+        assert_eq!(manager.backoff_delay(1, base), Duration::from_millis(20));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        manager.schedule_restart(can0(), base).await;
+        assert_eq!(manager.backoff_attempts(1), 2);
+        // Capped at the maximum
+        assert_eq!(manager.backoff_delay(1, base), Duration::from_millis(30));
+
+        // The fake reports error-active after a restart, so the backoff clears after the window
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(backend.restarts(), vec![1, 1]);
+        assert_eq!(manager.backoff_attempts(1), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn metrics_count_restarts() {
+        let backend = FakeBackend::new();
+        let metrics = Arc::new(Metrics::new());
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_metrics(Arc::clone(&metrics));
+
+        manager
+            .schedule_restart(can0(), Duration::from_millis(10))
+            .await;
+        assert!(
+            metrics
+                .render()
+                .contains("cansentinel_pending_restarts 1\n")
+        );
+
+        manager.shutdown().await;
+        let rendered = metrics.render();
+        assert!(rendered.contains("cansentinel_restart_total{interface=\"can0\"} 1\n"));
+        assert!(rendered.contains("cansentinel_restart_failed_total{interface=\"can0\"} 0\n"));
+        assert!(rendered.contains("cansentinel_pending_restarts 0\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_that_does_not_take_is_retried() {
+        let backend = FakeBackend::new();
+        let (tx, mut sink) = EventSink::channel();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_event_sender(tx.downgrade());
+        backend.set_stuck(1, true);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+
+        sink.expect_bus_off(&can0(), Duration::from_secs(3)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_restarts_are_limited() {
+        let backend = FakeBackend::new();
+        let manager =
+            RestartManager::with_control(Arc::new(backend.clone())).with_max_concurrent_restarts(1);
+        // Verifying can0 takes the full timeout, holding the only permit meanwhile
+        backend.set_stuck(1, true);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        manager.schedule_restart(can1(), Duration::ZERO).await;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+        assert!(manager.is_pending(2).await);
+
+        tokio::time::sleep(VERIFY_TIMEOUT).await;
+        assert_eq!(backend.restarts(), vec![1, 2]);
+        assert!(!manager.is_pending(2).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jitter_is_deterministic_for_a_seed() {
+        let jitter = Jitter {
+            spread: Duration::from_secs(10),
+            seed: 42,
+        };
+        let mut due = Vec::new();
+        for _ in 0..2 {
+            let manager =
+                RestartManager::with_control(Arc::new(FakeBackend::new())).with_jitter(jitter);
+            manager
+                .schedule_restart(can0(), Duration::from_secs(1))
+                .await;
+            due.push(manager.restart_due_in(1).await.unwrap());
+            manager.cancel_all().await;
+        }
+
+        // The first delay drawn for seed 42 within 10s
+        let expected = Duration::from_secs(1) + Duration::from_nanos(1_387_329_660);
+        assert_eq!(due, vec![expected, expected]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_counts_only_once_confirmed() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_confirmation_window(Duration::from_millis(200))
+            .with_circuit_breaker(CircuitBreaker {
+                max_restarts: 1,
+                window: Duration::from_secs(60),
+                cooldown: None,
+            });
+
+        // Held for the window, so the breaker forgets it
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.restarts(), vec![1, 1]);
+        assert_eq!(manager.stats(1).unwrap().failures, 0);
+
+        backend.set_state(1, CanState::BusOff);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(manager.stats(1).unwrap().failures, 1);
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(manager.is_tripped(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dry_run_never_touches_the_interface() {
+        let backend = FakeBackend::new();
+        backend.set_state(1, CanState::BusOff);
+        let manager = RestartManager::with_control(Arc::new(backend.clone())).with_dry_run();
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+        assert!(manager.restart_now(&can0()).await);
+
+        assert!(backend.restarts().is_empty());
+        assert_eq!(manager.stats(1).unwrap().restarts, 2);
+        assert_eq!(manager.total_restarts(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn circuit_breaker_gives_up_until_reset() {
+        let backend = FakeBackend::new();
+        let (tx, mut sink) = EventSink::channel();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_event_sender(tx.downgrade())
+            .with_circuit_breaker(CircuitBreaker {
+                max_restarts: 2,
+                window: Duration::from_secs(60),
+                cooldown: None,
+            });
+
+        for _ in 0..3 {
+            manager.schedule_restart(can0(), Duration::ZERO).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(backend.restarts(), vec![1, 1]);
+        assert!(manager.is_tripped(1));
+        assert_eq!(manager.tripped(), vec![1]);
+        sink.expect(BusEventType::GaveUp, &can0(), Duration::from_secs(1))
+            .await;
+
+        assert!(manager.reset_circuit_breaker(1));
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(backend.restarts(), vec![1, 1, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn saved_state_carries_a_tripped_breaker_over() {
+        let breaker = CircuitBreaker {
+            max_restarts: 1,
+            window: Duration::from_secs(60),
+            cooldown: None,
+        };
+        let manager = RestartManager::with_control(Arc::new(FakeBackend::new()))
+            .with_circuit_breaker(breaker);
+        for _ in 0..2 {
+            manager.schedule_restart(can0(), Duration::ZERO).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(manager.is_tripped(1));
+
+        let path =
+            std::env::temp_dir().join(format!("cansentinel-state-{}.json", std::process::id()));
+        let mut saved = SavedState::default();
+        saved
+            .interfaces
+            .insert("can0".to_string(), manager.saved_state(1));
+        saved.save(&path).unwrap();
+        let loaded = SavedState::load(&path);
+        assert_eq!(loaded, saved);
+
+        // Same interface under a new index, as after a reboot
+        let restarted = RestartManager::with_control(Arc::new(FakeBackend::new()))
+            .with_circuit_breaker(breaker);
+        restarted.restore_state(7, &loaded.interfaces["can0"]);
+        assert!(restarted.is_tripped(7));
+        assert_eq!(restarted.stats(7).unwrap().restarts, 1);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(SavedState::load(&path), SavedState::default());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_now_replaces_pending_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+
+        manager
+            .schedule_restart(can0(), Duration::from_secs(60))
+            .await;
+        assert_eq!(
+            manager.restart_due_in(1).await,
+            Some(Duration::from_secs(60))
+        );
+
+        assert!(manager.restart_now(&can0()).await);
+        assert_eq!(backend.restarts(), vec![1]);
+        assert_eq!(manager.restart_due_in(1).await, None);
+        assert_eq!(manager.pending_count().await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopped_interface_is_brought_up() {
+        let backend = FakeBackend::new();
+        let manager =
+            RestartManager::with_control(Arc::new(backend.clone())).with_restart_stopped();
+        backend.set_state(1, CanState::Stopped);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+
+        assert_eq!(backend.bring_ups(), vec![1]);
+        assert!(backend.restarts().is_empty());
+        assert_eq!(backend.state(1).unwrap(), Some(CanState::ErrorActive));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn link_settings_are_reapplied_on_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+        let settings = LinkSettings {
+            bitrate: Some(500_000),
+            restart_ms: None,
+        };
+        manager.set_link_settings(1, Some(settings));
+        backend.set_state(1, CanState::BusOff);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+
+        assert_eq!(backend.reconfigured(), vec![(1, settings)]);
+        assert!(backend.restarts().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn downup_restart_cycles_the_interface() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::from_millis(10));
+        manager.set_restart_method(1, RestartMethod::DownUp);
+        backend.set_state(1, CanState::BusOff);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+
+        assert_eq!(backend.bring_downs(), vec![1]);
+        assert_eq!(backend.bring_ups(), vec![1]);
+        assert!(backend.restarts().is_empty());
+        assert_eq!(backend.state(1).unwrap(), Some(CanState::ErrorActive));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn raised_error_counters_are_reset_after_a_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::from_millis(10));
+        manager.set_reset_counters(1, true);
+        backend.set_state(1, CanState::BusOff);
+        backend.set_error_counters(1, ErrorCounters { tx: 128, rx: 12 });
+
+        let before = Instant::now();
+        assert!(manager.restart_now(&can0()).await);
+        // Reset by cycling the interface, whose stopped event is the manager's own
+        assert_eq!(backend.bring_downs(), vec![1]);
+        assert_eq!(backend.bring_ups(), vec![1]);
+        assert!(before.elapsed() >= Duration::from_millis(10));
+        assert!(manager.caused_stop(1, Instant::now()));
+        assert_eq!(
+            backend.error_counters(1).unwrap(),
+            Some(ErrorCounters { tx: 0, rx: 0 })
+        );
+
+        // Nothing to reset on a driver that doesn't report them, and the restart still succeeds
+        manager.set_reset_counters(2, true);
+        backend.set_state(2, CanState::BusOff);
+        assert!(manager.restart_now(&can1()).await);
+        assert_eq!(backend.bring_downs(), vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_counter_reset_that_leaves_the_interface_down_fails_the_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_settle_time(Duration::ZERO);
+        manager.set_reset_counters(1, true);
+        backend.set_state(1, CanState::BusOff);
+        backend.set_error_counters(1, ErrorCounters { tx: 128, rx: 12 });
+        backend.set_fails_bring_up(1, true);
+
+        assert!(!manager.restart_now(&can0()).await);
+        assert_eq!(backend.restarts(), vec![1]);
+        assert_eq!(backend.bring_ups(), vec![1]);
+        assert_eq!(manager.stats(1).unwrap().failures, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn min_interval_delays_a_restart_that_comes_too_soon() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_min_interval(Duration::from_secs(60));
+        backend.set_state(1, CanState::BusOff);
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+
+        backend.set_state(1, CanState::BusOff);
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+        assert!(manager.is_pending(1).await);
+
+        manager.cancel_restart(&can0()).await;
+        manager.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interface_is_muted_for_a_while_after_a_restart() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()))
+            .with_mute_window(Duration::from_millis(200));
+        assert!(manager.muted_until(1).is_none());
+
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.restarts(), vec![1]);
+        assert!(manager.muted_until(1).is_some());
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(manager.muted_until(1).is_none());
+        manager.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_stats_are_tracked_per_interface() {
+        let backend = FakeBackend::new();
+        let manager = RestartManager::with_control(Arc::new(backend.clone()));
+        assert_eq!(manager.stats(1), None);
 
-    println!("{}: restarting interface", interface.name);
+        manager.schedule_restart(can0(), Duration::ZERO).await;
+        manager.shutdown().await;
+        manager.restart_now(&can0()).await;
 
-    let iface = CanInterface::open_iface(interface.idx);
-    match iface.restart() {
-        Ok(_) => (),
-        Err(e) => println!("{}: restart failed: {}", interface.name, e),
+        let stats = manager.stats(1).unwrap();
+        assert_eq!(stats.restarts, 2);
+        assert_eq!(stats.failures, 0);
+        assert!(stats.last_restart.is_some());
+        assert_eq!(manager.all_stats().len(), 1);
     }
 }
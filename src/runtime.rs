@@ -0,0 +1,707 @@
+//! Event sources as a stream, for embedding cansentinel's detection without its restart policy
+//!
+//! [`run`] starts netlink and error frame monitoring for the interfaces in a [`Config`] and
+//! yields every [`BusEvent`] observed, leaving it to the caller to decide what to do about them.
+//! [`Monitor`](crate::Monitor) is built on top of this.
+
+use crate::{
+    backend::{CanControl, SocketCanBackend},
+    config::Config,
+    events::{BusEvent, BusEventType},
+    interface::{CanInterfaceInfo, InterfaceError, SharedInterfaces, StateCache},
+    liveness::{Liveness, Task},
+    monitoring::{
+        ErrorMonitorOptions, monitor_interface_errors,
+        netlink::{DEFAULT_GROUPS, monitor_netlink_shared},
+        poll::monitor_poll_shared,
+        sysfs::monitor_sysfs_shared,
+    },
+};
+use futures_core::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+/// Dropped events between warnings, the rest are only logged at debug level
+const DROP_LOG_EVERY: u64 = 100;
+
+/// How often sysfs is polled with [`StateSource::Sysfs`] if no fallback interval is set
+const DEFAULT_SYSFS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options for the event sources started by [`watch`]
+#[derive(Debug, Clone, Default)]
+pub struct SourceOptions {
+    /// Options passed to every error frame monitor
+    pub error_monitor: ErrorMonitorOptions,
+    /// Also poll interface states at this interval, alongside netlink
+    pub poll_interval: Option<Duration>,
+    /// Poll interface states at this interval if netlink link notifications are not permitted,
+    /// and sysfs at this interval with [`StateSource::Sysfs`]
+    pub poll_fallback: Option<Duration>,
+    /// Where link changes and interface states come from
+    pub state_source: StateSource,
+    /// Bound on the events waiting to be received, unbounded if unset
+    pub queue_limit: Option<QueueLimit>,
+}
+
+/// Where link changes and interface states are read from, besides error frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StateSource {
+    /// Netlink link notifications, falling back to polling if they aren't permitted: through
+    /// netlink requests if those work, otherwise sysfs
+    #[default]
+    Netlink,
+    /// Poll sysfs only, for systems where netlink doesn't work at all
+    Sysfs,
+}
+
+impl FromStr for StateSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "netlink" => Ok(Self::Netlink),
+            "sysfs" => Ok(Self::Sysfs),
+            _ => Err(format!(
+                "invalid state source '{s}' (expected netlink or sysfs)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Netlink => f.write_str("netlink"),
+            Self::Sysfs => f.write_str("sysfs"),
+        }
+    }
+}
+
+/// Bound on the events waiting to be received from an [`EventStream`]
+///
+/// If events arrive faster than they are received, for example during an error frame storm, the
+/// queue would otherwise grow without limit. Only error-threshold and counters-reported events are
+/// ever dropped; state transitions such as bus-off and recovery are always kept, going over
+/// `capacity` if nothing else is left to drop. Drops are logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimit {
+    /// Most events kept waiting
+    pub capacity: usize,
+    /// Which event to drop once `capacity` events are waiting
+    pub overflow: OverflowPolicy,
+}
+
+/// Which event to drop when the event queue is full
+///
+/// There is no policy blocking the sources instead: netlink and the error frame sockets would
+/// then overflow their kernel buffers, losing state transitions without a trace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the droppable event that has waited longest, keeping the latest state of things
+    #[default]
+    DropOldest,
+    /// Drop the droppable event that arrived last, keeping the start of a storm
+    DropNewest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Self::DropOldest),
+            "drop-newest" => Ok(Self::DropNewest),
+            _ => Err(format!(
+                "invalid overflow policy '{s}' (expected drop-oldest or drop-newest)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DropOldest => f.write_str("drop-oldest"),
+            Self::DropNewest => f.write_str("drop-newest"),
+        }
+    }
+}
+
+/// Tasks producing bus events for a set of interfaces
+///
+/// Interfaces can be added and removed while the sources run.
+#[derive(Debug)]
+pub struct EventSources {
+    /// Interfaces netlink and polling report on
+    interfaces: SharedInterfaces,
+    /// States reported by netlink and polling
+    states: StateCache,
+    /// Signs of life from netlink and the error frame monitors
+    liveness: Liveness,
+    /// Sender for error frame monitors started later, weak so the stream can still end
+    tx: mpsc::WeakUnboundedSender<BusEvent>,
+    error_monitor: ErrorMonitorOptions,
+    /// Hands events on to the stream, aborting it closes the channel the sources send to
+    relay_task: JoinHandle<()>,
+    /// Blocking thread listening for link notifications, which can't be aborted
+    netlink_thread: Option<JoinHandle<()>>,
+    /// Netlink listener, or the polling it falls back to
+    netlink_task: JoinHandle<()>,
+    poll_task: Option<JoinHandle<()>>,
+    /// Error frame monitor of each interface
+    error_tasks: HashMap<CanInterfaceInfo, JoinHandle<()>>,
+}
+
+impl EventSources {
+    fn tasks(&self) -> impl Iterator<Item = &JoinHandle<()>> {
+        [&self.relay_task, &self.netlink_task]
+            .into_iter()
+            .chain(&self.poll_task)
+            .chain(self.error_tasks.values())
+    }
+
+    /// Interfaces currently watched
+    pub fn interfaces(&self) -> Vec<CanInterfaceInfo> {
+        self.interfaces.read().unwrap().clone()
+    }
+
+    /// Last state reported for each watched interface
+    pub fn states(&self) -> StateCache {
+        self.states.clone()
+    }
+
+    /// Signs of life from the netlink listener and every error frame monitor
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
+
+    /// Interfaces currently watched, kept up to date as they are added and removed
+    pub(crate) fn watched(&self) -> SharedInterfaces {
+        Arc::clone(&self.interfaces)
+    }
+
+    /// Start watching `interface`
+    ///
+    /// Returns false if it is already watched, or if the event stream has been dropped.
+    pub fn add(&mut self, interface: CanInterfaceInfo) -> bool {
+        if self.error_tasks.contains_key(&interface) {
+            return false;
+        }
+        let Some(tx) = self.tx.upgrade() else {
+            return false;
+        };
+        self.interfaces.write().unwrap().push(interface.clone());
+        self.error_tasks.insert(
+            interface.clone(),
+            spawn_error_monitor(tx, interface, self.error_monitor.clone()),
+        );
+        true
+    }
+
+    /// Stop watching the interface with index `idx`, returning it if it was watched
+    pub fn remove(&mut self, idx: u32) -> Option<CanInterfaceInfo> {
+        let mut interfaces = self.interfaces.write().unwrap();
+        let interface = interfaces.remove(interfaces.iter().position(|i| i.idx == idx)?);
+        if let Some(task) = self.error_tasks.remove(&interface) {
+            task.abort();
+        }
+        self.liveness
+            .forget(&Task::ErrorFrames(interface.name.clone()));
+        self.states.forget(idx);
+        Some(interface)
+    }
+
+    /// Follow an interface renamed in place, keeping its state tracking
+    ///
+    /// The error frame monitor is restarted under the new name. Returns `false` if no interface
+    /// with the same index was watched.
+    pub fn rename(&mut self, interface: CanInterfaceInfo) -> bool {
+        let Some(tx) = self.tx.upgrade() else {
+            return false;
+        };
+        let mut interfaces = self.interfaces.write().unwrap();
+        let Some(old) = interfaces.iter_mut().find(|i| i.idx == interface.idx) else {
+            return false;
+        };
+        let old = std::mem::replace(old, interface.clone());
+        if let Some(task) = self.error_tasks.remove(&old) {
+            task.abort();
+        }
+        self.liveness.forget(&Task::ErrorFrames(old.name));
+        self.error_tasks.insert(
+            interface.clone(),
+            spawn_error_monitor(tx, interface, self.error_monitor.clone()),
+        );
+        true
+    }
+
+    /// Whether every error frame monitor is still running
+    ///
+    /// These only stop once the event channel closes, unlike netlink monitoring, which ends
+    /// legitimately when link notifications aren't permitted.
+    pub fn error_monitors_running(&self) -> bool {
+        self.error_tasks.values().all(|task| !task.is_finished())
+    }
+
+    fn abort(&self) {
+        for task in self.tasks() {
+            task.abort();
+        }
+    }
+
+    /// Cancel every source task and wait for them to finish
+    ///
+    /// The netlink listener thread blocks in a receive call and can't be interrupted. It exits
+    /// once its receive times out and it finds the event channel closed, which this waits for.
+    pub async fn stop(self) {
+        self.abort();
+        let tasks = [self.relay_task, self.netlink_task]
+            .into_iter()
+            .chain(self.netlink_thread)
+            .chain(self.poll_task)
+            .chain(self.error_tasks.into_values());
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Stream of every bus event observed on the watched interfaces
+///
+/// The stream ends if every source stops. Dropping it stops the sources.
+#[derive(Debug)]
+pub struct EventStream {
+    events: mpsc::Receiver<BusEvent>,
+    /// Weak sender for re-injecting events without keeping the channel open
+    injector: mpsc::WeakUnboundedSender<BusEvent>,
+    /// Taken by [`EventStream::stop`] and [`EventStream::into_parts`]
+    sources: Option<EventSources>,
+}
+
+impl EventStream {
+    /// Wait for the next event
+    pub async fn recv(&mut self) -> Option<BusEvent> {
+        self.events.recv().await
+    }
+
+    /// Last state reported for each watched interface, see [`StateCache`]
+    pub fn states(&self) -> StateCache {
+        self.sources
+            .as_ref()
+            .map(EventSources::states)
+            .unwrap_or_default()
+    }
+
+    /// Signs of life from the sources, see [`Liveness`]
+    pub fn liveness(&self) -> Liveness {
+        self.sources
+            .as_ref()
+            .map(EventSources::liveness)
+            .unwrap_or_default()
+    }
+
+    /// Stop every source and wait for them to finish
+    pub async fn stop(mut self) {
+        if let Some(sources) = self.sources.take() {
+            sources.stop().await;
+        }
+    }
+
+    /// Split into the event receiver, a weak sender into the same channel, and the sources
+    pub(crate) fn into_parts(
+        mut self,
+    ) -> (
+        mpsc::Receiver<BusEvent>,
+        mpsc::WeakUnboundedSender<BusEvent>,
+        EventSources,
+    ) {
+        let sources = self
+            .sources
+            .take()
+            .expect("sources are present until the stream is consumed");
+        let (_, dummy_rx) = mpsc::channel(1);
+        let events = std::mem::replace(&mut self.events, dummy_rx);
+        (events, self.injector.clone(), sources)
+    }
+}
+
+impl Stream for EventStream {
+    type Item = BusEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        if let Some(sources) = &self.sources {
+            sources.abort();
+        }
+    }
+}
+
+/// Start monitoring the interfaces named in `config` and stream every event observed
+///
+/// Must be called from within a tokio runtime.
+pub fn run(config: &Config) -> Result<EventStream, InterfaceError> {
+    let interfaces = config
+        .interface_names
+        .iter()
+        .map(|name| CanInterfaceInfo::new(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(watch(interfaces, SourceOptions::default()))
+}
+
+/// Start netlink, polling and error frame monitoring for `interfaces`
+///
+/// Netlink and the error frame monitors report their signs of life to
+/// `options.error_monitor.liveness`, or to a new tracker if it is unset. Must be called from
+/// within a tokio runtime.
+pub fn watch(interfaces: Vec<CanInterfaceInfo>, mut options: SourceOptions) -> EventStream {
+    let (tx, queued) = mpsc::unbounded_channel::<BusEvent>();
+    let (relay_tx, events) = mpsc::channel(1);
+    let relay_task = tokio::spawn(relay(queued, relay_tx, options.queue_limit));
+    let watched: SharedInterfaces = Arc::new(RwLock::new(interfaces.clone()));
+    let states = StateCache::new();
+    let liveness = options
+        .error_monitor
+        .liveness
+        .get_or_insert_with(Liveness::new)
+        .clone();
+
+    let verbose = options.error_monitor.verbose;
+    // Spawned apart from the task falling back to polling, so stopping can wait for the thread
+    let (netlink_thread, netlink_result) = if options.state_source == StateSource::Netlink {
+        let netlink_tx = tx.clone();
+        let netlink_interfaces = Arc::clone(&watched);
+        let netlink_states = states.clone();
+        let netlink_liveness = liveness.clone();
+        let (result_tx, result_rx) = oneshot::channel();
+        let thread = tokio::task::spawn_blocking(move || {
+            let _ = result_tx.send(monitor_netlink_shared(
+                netlink_tx,
+                netlink_interfaces,
+                netlink_states,
+                verbose,
+                DEFAULT_GROUPS,
+                Some(netlink_liveness),
+            ));
+        });
+        (Some(thread), Some(result_rx))
+    } else {
+        (None, None)
+    };
+    let netlink_task = {
+        let fallback_tx = tx.clone();
+        let fallback_interfaces = Arc::clone(&watched);
+        let fallback_states = states.clone();
+        let fallback = options
+            .poll_fallback
+            .filter(|_| options.poll_interval.is_none());
+        let sysfs_interval = options.poll_fallback.unwrap_or(DEFAULT_SYSFS_INTERVAL);
+        tokio::spawn(async move {
+            let Some(netlink) = netlink_result else {
+                monitor_sysfs_shared(
+                    fallback_tx,
+                    fallback_interfaces,
+                    fallback_states,
+                    sysfs_interval,
+                    verbose,
+                )
+                .await;
+                return;
+            };
+            // Failures are logged by monitor_netlink, error frame detection carries on regardless
+            if let Ok(Err(e)) = netlink.await
+                && e.kind() == io::ErrorKind::PermissionDenied
+                && let Some(interval) = fallback
+            {
+                // Netlink requests may still work where subscribing doesn't
+                let probe = fallback_interfaces.read().unwrap().first().cloned();
+                let requests_work =
+                    probe.is_none_or(|interface| SocketCanBackend.state(interface.idx).is_ok());
+                if requests_work {
+                    warn!(
+                        "Falling back to polling interface states every {:?}",
+                        interval
+                    );
+                    monitor_poll_shared(
+                        &SocketCanBackend,
+                        fallback_tx,
+                        fallback_interfaces,
+                        fallback_states,
+                        interval,
+                        verbose,
+                    )
+                    .await;
+                } else {
+                    warn!(
+                        "Netlink requests fail as well, falling back to polling interface states in sysfs every {:?}",
+                        interval
+                    );
+                    monitor_sysfs_shared(
+                        fallback_tx,
+                        fallback_interfaces,
+                        fallback_states,
+                        interval,
+                        verbose,
+                    )
+                    .await;
+                }
+            }
+        })
+    };
+
+    let poll_task = options.poll_interval.map(|interval| {
+        let poll_tx = tx.clone();
+        let poll_interfaces = Arc::clone(&watched);
+        let poll_states = states.clone();
+        tokio::spawn(async move {
+            monitor_poll_shared(
+                &SocketCanBackend,
+                poll_tx,
+                poll_interfaces,
+                poll_states,
+                interval,
+                verbose,
+            )
+            .await
+        })
+    });
+
+    let error_tasks = interfaces
+        .into_iter()
+        .map(|interface| {
+            let task =
+                spawn_error_monitor(tx.clone(), interface.clone(), options.error_monitor.clone());
+            (interface, task)
+        })
+        .collect();
+
+    // Only the sources hold senders now, so the stream ends if they all stop
+    let injector = tx.downgrade();
+    drop(tx);
+
+    EventStream {
+        events,
+        injector: injector.clone(),
+        sources: Some(EventSources {
+            interfaces: watched,
+            states,
+            liveness,
+            tx: injector,
+            error_monitor: options.error_monitor,
+            relay_task,
+            netlink_thread,
+            netlink_task,
+            poll_task,
+            error_tasks,
+        }),
+    }
+}
+
+/// Hand events from the sources on to the stream, keeping at most `limit.capacity` waiting
+///
+/// Returns once the sources have all stopped and everything left is handed on, or once the
+/// stream is dropped. Either way dropping `sources` then lets the sources see the channel
+/// closed.
+async fn relay(
+    mut sources: mpsc::UnboundedReceiver<BusEvent>,
+    stream: mpsc::Sender<BusEvent>,
+    limit: Option<QueueLimit>,
+) {
+    let mut queue = VecDeque::new();
+    let mut dropped: u64 = 0;
+    loop {
+        tokio::select! {
+            permit = stream.reserve(), if !queue.is_empty() => {
+                let Ok(permit) = permit else {
+                    return;
+                };
+                permit.send(queue.pop_front().expect("queue is not empty"));
+            }
+            event = sources.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let Some(limit) = limit.filter(|limit| queue.len() >= limit.capacity.max(1)) else {
+                    queue.push_back(event);
+                    continue;
+                };
+                let shed = match limit.overflow {
+                    OverflowPolicy::DropOldest => queue.iter().position(sheddable),
+                    OverflowPolicy::DropNewest if sheddable(&event) => None,
+                    OverflowPolicy::DropNewest => queue.iter().rposition(sheddable),
+                };
+                let event = match shed {
+                    Some(index) => {
+                        queue.push_back(event);
+                        queue.remove(index).expect("index is in the queue")
+                    }
+                    None if sheddable(&event) => event,
+                    None => {
+                        // Nothing left to drop but state transitions, which are always kept
+                        queue.push_back(event);
+                        continue;
+                    }
+                };
+                dropped += 1;
+                if dropped == 1 || dropped % DROP_LOG_EVERY == 0 {
+                    warn!(
+                        "Event queue full ({} events), dropped {:?} on {} ({} dropped so far)",
+                        limit.capacity, event.event_type, event.interface.name, dropped
+                    );
+                } else {
+                    debug!(
+                        "Event queue full, dropped {:?} on {}",
+                        event.event_type, event.interface.name
+                    );
+                }
+            }
+            _ = stream.closed() => return,
+        }
+    }
+    for event in queue {
+        if stream.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Whether `event` may be dropped when the event queue is full
+///
+/// Error thresholds and counter reports come in bulk during a storm and the next one carries the
+/// same news, unlike the state transitions the daemon acts on.
+fn sheddable(event: &BusEvent) -> bool {
+    matches!(
+        event.event_type,
+        BusEventType::ErrorThreshold | BusEventType::CountersReported
+    )
+}
+
+fn spawn_error_monitor(
+    tx: mpsc::UnboundedSender<BusEvent>,
+    interface: CanInterfaceInfo,
+    options: ErrorMonitorOptions,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        monitor_interface_errors(tx, interface, options).await;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{BusEventSource, ErrorCounters};
+    use socketcan::nl::CanState;
+
+    fn interface(idx: u32) -> CanInterfaceInfo {
+        CanInterfaceInfo {
+            idx,
+            name: format!("can{idx}"),
+        }
+    }
+
+    fn bus_off() -> BusEvent {
+        BusEvent::bus_off(interface(0), BusEventSource::StateUpdate(CanState::BusOff))
+    }
+
+    fn recovered() -> BusEvent {
+        BusEvent::recovered(
+            interface(0),
+            BusEventSource::StateUpdate(CanState::ErrorActive),
+        )
+    }
+
+    fn counters(idx: u32) -> BusEvent {
+        BusEvent::counters_reported(
+            interface(idx),
+            BusEventSource::StateUpdate(CanState::ErrorWarning),
+            ErrorCounters { tx: 100, rx: 0 },
+        )
+    }
+
+    /// Relay `events` through a queue of `capacity`, returning what comes out the other end
+    async fn overflow(
+        events: Vec<BusEvent>,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Vec<String> {
+        let (tx, queued) = mpsc::unbounded_channel();
+        // Kept full until everything is queued, so the relay has to apply the limit to all of it
+        let (stream, mut received) = mpsc::channel(1);
+        stream.try_send(bus_off()).unwrap();
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+        let relay = tokio::spawn(relay(
+            queued,
+            stream,
+            Some(QueueLimit { capacity, overflow }),
+        ));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        received.recv().await.unwrap();
+        let mut out = Vec::new();
+        while let Some(event) = received.recv().await {
+            out.push(format!("{:?} {}", event.event_type, event.interface.name));
+        }
+        relay.await.unwrap();
+        out
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_oldest_only_sheds_error_frame_events() {
+        let events = vec![
+            bus_off(),
+            counters(1),
+            counters(2),
+            counters(3),
+            recovered(),
+        ];
+        assert_eq!(
+            overflow(events, 3, OverflowPolicy::DropOldest).await,
+            ["BusOff can0", "CountersReported can3", "Recovered can0"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_newest_only_sheds_error_frame_events() {
+        let events = vec![
+            bus_off(),
+            counters(1),
+            counters(2),
+            counters(3),
+            recovered(),
+        ];
+        assert_eq!(
+            overflow(events, 3, OverflowPolicy::DropNewest).await,
+            ["BusOff can0", "CountersReported can1", "Recovered can0"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn state_transitions_go_over_the_limit() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let events = vec![bus_off(), recovered(), counters(1), bus_off()];
+            assert_eq!(
+                overflow(events, 1, policy).await,
+                ["BusOff can0", "Recovered can0", "BusOff can0"]
+            );
+        }
+    }
+}
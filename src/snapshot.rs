@@ -0,0 +1,216 @@
+//! Point-in-time interface diagnostics
+
+use crate::{
+    interface::{CanInterfaceInfo, SYSFS_NET},
+    monitoring::netlink::dump_links,
+};
+use socketcan::{CanInterface, InterfaceCanParams, nl::CanState};
+use std::{fmt, io, path::Path};
+
+/// Link statistics from `/sys/class/net/<name>/statistics`
+#[derive(Debug, Clone, Default)]
+pub struct LinkStats {
+    /// Frames received
+    pub rx_packets: Option<u64>,
+    /// Frames transmitted
+    pub tx_packets: Option<u64>,
+    /// Receive errors
+    pub rx_errors: Option<u64>,
+    /// Transmit errors
+    pub tx_errors: Option<u64>,
+    /// Received frames dropped
+    pub rx_dropped: Option<u64>,
+    /// Transmit frames dropped
+    pub tx_dropped: Option<u64>,
+}
+
+impl LinkStats {
+    /// Read the current link statistics of an interface
+    pub fn read(name: &str) -> Self {
+        let dir = Path::new(SYSFS_NET).join(name).join("statistics");
+        let read = |stat: &str| -> Option<u64> {
+            std::fs::read_to_string(dir.join(stat))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        Self {
+            rx_packets: read("rx_packets"),
+            tx_packets: read("tx_packets"),
+            rx_errors: read("rx_errors"),
+            tx_errors: read("tx_errors"),
+            rx_dropped: read("rx_dropped"),
+            tx_dropped: read("tx_dropped"),
+        }
+    }
+}
+
+/// Controller parameters and link statistics captured at one instant
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceSnapshot {
+    /// Controller state
+    pub state: Option<CanState>,
+    /// Transmit error counter
+    pub tx_error_counter: Option<u16>,
+    /// Receive error counter
+    pub rx_error_counter: Option<u16>,
+    /// Kernel automatic restart delay, 0 when disabled
+    pub restart_ms: Option<u32>,
+    /// Configured bitrate
+    pub bitrate: Option<u32>,
+    /// Link statistics
+    pub stats: LinkStats,
+}
+
+impl InterfaceSnapshot {
+    /// Capture the current netlink CAN parameters and link statistics of an interface
+    ///
+    /// Fields that can't be read are left empty rather than failing the whole snapshot.
+    pub fn capture(interface: &CanInterfaceInfo) -> Self {
+        let mut snapshot = Self {
+            stats: LinkStats::read(&interface.name),
+            ..Default::default()
+        };
+        if let Ok(details) = CanInterface::open_iface(interface.idx).details() {
+            snapshot.set_params(details.can);
+        }
+        snapshot
+    }
+
+    /// Capture every CAN interface present, in index order, with a single netlink dump
+    ///
+    /// Cheaper than capturing each interface on its own, which takes a netlink request each.
+    pub fn capture_all() -> io::Result<Vec<(CanInterfaceInfo, Self)>> {
+        use neli::consts::rtnl::Ifla;
+
+        let mut snapshots: Vec<_> = dump_links()?
+            .iter()
+            .filter_map(|link| {
+                let interface = CanInterfaceInfo::from_link(link)?;
+                let mut snapshot = Self {
+                    stats: LinkStats::read(&interface.name),
+                    ..Default::default()
+                };
+                if let Some(params) = link
+                    .rtattrs
+                    .get_attr_handle()
+                    .get_attribute(Ifla::Linkinfo)
+                    .and_then(|attr| InterfaceCanParams::try_from(attr).ok())
+                {
+                    snapshot.set_params(params);
+                }
+                Some((interface, snapshot))
+            })
+            .collect();
+        snapshots.sort_by_key(|(interface, _)| interface.idx);
+        Ok(snapshots)
+    }
+
+    fn set_params(&mut self, params: InterfaceCanParams) {
+        self.state = params.state;
+        self.tx_error_counter = params.berr_counter.map(|c| c.txerr);
+        self.rx_error_counter = params.berr_counter.map(|c| c.rxerr);
+        self.restart_ms = params.restart_ms;
+        self.bitrate = params.bit_timing.map(|t| t.bitrate);
+    }
+}
+
+/// Table of the name, index, state, bitrate and restart-ms of each interface in `snapshots`,
+/// as `--list-interfaces` prints it
+pub fn interface_table(snapshots: &[(CanInterfaceInfo, InterfaceSnapshot)]) -> String {
+    let width = snapshots
+        .iter()
+        .map(|(interface, _)| interface.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("NAME".len());
+    let mut table = format!(
+        "{:<width$}  {:>5}  {:<12}  {:>8}  {:>10}\n",
+        "NAME", "INDEX", "STATE", "BITRATE", "RESTART-MS"
+    );
+    for (interface, snapshot) in snapshots {
+        table.push_str(&format!(
+            "{:<width$}  {:>5}  {:<12}  {:>8}  {:>10}\n",
+            interface.name,
+            interface.idx,
+            Opt(snapshot.state.map(|state| format!("{:?}", state))),
+            Opt(snapshot.bitrate),
+            Opt(snapshot.restart_ms)
+        ));
+    }
+    table
+}
+
+/// Formats an optional value, using `?` when it's missing
+struct Opt<T>(Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Opt<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => f.pad("?"),
+        }
+    }
+}
+
+impl fmt::Display for InterfaceSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            Some(state) => write!(f, "state={:?}", state)?,
+            None => f.write_str("state=?")?,
+        }
+        write!(
+            f,
+            " txerr={} rxerr={} restart_ms={} bitrate={} rx_packets={} tx_packets={} rx_errors={} tx_errors={} rx_dropped={} tx_dropped={}",
+            Opt(self.tx_error_counter),
+            Opt(self.rx_error_counter),
+            Opt(self.restart_ms),
+            Opt(self.bitrate),
+            Opt(self.stats.rx_packets),
+            Opt(self.stats.tx_packets),
+            Opt(self.stats.rx_errors),
+            Opt(self.stats.tx_errors),
+            Opt(self.stats.rx_dropped),
+            Opt(self.stats.tx_dropped),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_aligns_known_and_unknown_values() {
+        let snapshots = [
+            (
+                CanInterfaceInfo {
+                    idx: 3,
+                    name: "can0".to_string(),
+                },
+                InterfaceSnapshot {
+                    state: Some(CanState::ErrorActive),
+                    bitrate: Some(500_000),
+                    restart_ms: Some(0),
+                    ..Default::default()
+                },
+            ),
+            (
+                CanInterfaceInfo {
+                    idx: 12,
+                    name: "vcan_long".to_string(),
+                },
+                InterfaceSnapshot::default(),
+            ),
+        ];
+        assert_eq!(
+            interface_table(&snapshots),
+            "\
+NAME       INDEX  STATE          BITRATE  RESTART-MS
+can0           3  ErrorActive     500000           0
+vcan_long     12  ?                    ?           ?
+"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Restart statistics saved across runs
+//!
+//! With a state file, each interface's restart counts and circuit breaker state are written to it
+//! periodically and on shutdown, and read back at startup, so a chronically bad bus is still
+//! known as one after cansentinel itself restarts. Interfaces are keyed by name, since indexes
+//! can change across reboots. A missing or unreadable file starts fresh.
+
+use crate::{RestartManager, interface::CanInterfaceInfo};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// How often the state file is written while monitoring
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Saved state of a single interface
+///
+/// Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterfaceState {
+    /// Restarts issued, including failed ones
+    pub restarts: u64,
+    /// Restarts that failed
+    pub failures: u64,
+    /// When the last restart was issued
+    pub last_restart: Option<f64>,
+    /// Restarts counted by the circuit breaker, oldest first
+    pub recent_restarts: Vec<f64>,
+    /// When the circuit breaker tripped, if restarts are suspended
+    pub tripped_at: Option<f64>,
+}
+
+/// Contents of a state file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    /// State of each interface, by name
+    pub interfaces: BTreeMap<String, InterfaceState>,
+}
+
+impl SavedState {
+    /// Read a state file, or start fresh if it is missing or can't be parsed
+    pub fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No state file at {}, starting fresh", path.display());
+                return Self::default();
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to read state file {}, starting fresh: {}",
+                    path.display(),
+                    e
+                );
+                return Self::default();
+            }
+        };
+        match serde_json::from_str(&contents) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(
+                    "State file {} is corrupt, starting fresh: {}",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Write the state to `path`, replacing it atomically
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let contents = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// A state file in use by a [`Monitor`](crate::Monitor)
+///
+/// Interfaces that aren't monitored keep their saved state, so it is still there if they come
+/// back.
+#[derive(Debug)]
+pub(crate) struct StateFile {
+    path: PathBuf,
+    saved: Mutex<SavedState>,
+}
+
+impl StateFile {
+    pub(crate) fn open(path: PathBuf) -> Self {
+        let saved = SavedState::load(&path);
+        Self {
+            path,
+            saved: Mutex::new(saved),
+        }
+    }
+
+    /// Restore the saved state of `interface`, if there is any
+    pub(crate) fn restore(&self, restart_manager: &RestartManager, interface: &CanInterfaceInfo) {
+        if let Some(state) = self.saved.lock().unwrap().interfaces.get(&interface.name) {
+            debug!(
+                "{}: restored {} restarts, {} failed",
+                interface.name, state.restarts, state.failures
+            );
+            restart_manager.restore_state(interface.idx, state);
+        }
+    }
+
+    /// Write the current state of `interfaces`, warning if that fails
+    pub(crate) fn save(&self, restart_manager: &RestartManager, interfaces: &[CanInterfaceInfo]) {
+        let mut saved = self.saved.lock().unwrap();
+        for interface in interfaces {
+            saved.interfaces.insert(
+                interface.name.clone(),
+                restart_manager.saved_state(interface.idx),
+            );
+        }
+        if let Err(e) = saved.save(&self.path) {
+            warn!("Failed to write state file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Seconds since the Unix epoch of `at`
+pub(crate) fn to_unix(at: Instant) -> f64 {
+    let ago = Instant::now().saturating_duration_since(at);
+    (SystemTime::now() - ago)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Monotonic time of `secs` since the Unix epoch, if it isn't too far in the past to represent
+///
+/// Times in the future, after the wall clock was set back, count as now.
+pub(crate) fn from_unix(secs: f64) -> Option<Instant> {
+    let at = SystemTime::UNIX_EPOCH + Duration::try_from_secs_f64(secs).ok()?;
+    let ago = SystemTime::now().duration_since(at).unwrap_or_default();
+    Instant::now().checked_sub(ago)
+}
@@ -0,0 +1,127 @@
+//! Helpers for writing tests against the event flow
+//!
+//! [`EventSink`] collects the events a monitor sends and turns the usual
+//! `timeout(..., rx.recv())` dance into one-line assertions. Pair it with
+//! [`FakeBackend`](crate::backend::fake::FakeBackend) to exercise monitoring and restarts without
+//! CAN hardware. [`can0`] and [`can1`] are the interfaces the tests of this crate use.
+
+use crate::{BusEvent, BusEventType, CanInterfaceInfo};
+use std::time::Duration;
+use tokio::{sync::mpsc, time::timeout};
+
+/// In-memory receiver of bus events with assertion helpers
+///
+/// The assertions panic with a description of what was expected and what arrived instead, so
+/// they are meant for tests only.
+#[derive(Debug)]
+pub struct EventSink {
+    events: mpsc::UnboundedReceiver<BusEvent>,
+}
+
+impl EventSink {
+    /// Create a sink and the sender to hand to the code under test
+    pub fn channel() -> (mpsc::UnboundedSender<BusEvent>, Self) {
+        let (tx, events) = mpsc::unbounded_channel();
+        (tx, Self { events })
+    }
+
+    /// Wait up to `wait` for the next event, `None` if none arrived or every sender is gone
+    pub async fn next(&mut self, wait: Duration) -> Option<BusEvent> {
+        timeout(wait, self.events.recv()).await.ok().flatten()
+    }
+
+    /// Wait up to `wait` for the next event and check that it is `event_type` on `interface`
+    ///
+    /// # Panics
+    ///
+    /// If no event arrives in time, or the next event is a different one.
+    pub async fn expect(
+        &mut self,
+        event_type: BusEventType,
+        interface: &CanInterfaceInfo,
+        wait: Duration,
+    ) -> BusEvent {
+        let Some(event) = self.next(wait).await else {
+            panic!(
+                "expected {:?} on {} within {:?}, got nothing",
+                event_type, interface.name, wait
+            );
+        };
+        assert!(
+            event.event_type == event_type && event.interface.idx == interface.idx,
+            "expected {:?} on {}, got {:?} on {} from {}",
+            event_type,
+            interface.name,
+            event.event_type,
+            event.interface.name,
+            event.event_source.name()
+        );
+        event
+    }
+
+    /// Wait up to `wait` for a bus-off on `interface`, see [`EventSink::expect`]
+    pub async fn expect_bus_off(
+        &mut self,
+        interface: &CanInterfaceInfo,
+        wait: Duration,
+    ) -> BusEvent {
+        self.expect(BusEventType::BusOff, interface, wait).await
+    }
+
+    /// Wait up to `wait` for a restart on `interface`, see [`EventSink::expect`]
+    pub async fn expect_restart(
+        &mut self,
+        interface: &CanInterfaceInfo,
+        wait: Duration,
+    ) -> BusEvent {
+        self.expect(BusEventType::Restart, interface, wait).await
+    }
+
+    /// Check that no event arrives for `duration`
+    ///
+    /// # Panics
+    ///
+    /// If an event arrives within `duration`.
+    pub async fn assert_no_events(&mut self, duration: Duration) {
+        if let Ok(Some(event)) = timeout(duration, self.events.recv()).await {
+            panic!(
+                "expected no events for {:?}, got {:?} on {} from {}",
+                duration,
+                event.event_type,
+                event.interface.name,
+                event.event_source.name()
+            );
+        }
+    }
+
+    /// Take every event already received without waiting
+    pub fn drain(&mut self) -> Vec<BusEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+}
+
+impl From<mpsc::UnboundedReceiver<BusEvent>> for EventSink {
+    fn from(events: mpsc::UnboundedReceiver<BusEvent>) -> Self {
+        Self { events }
+    }
+}
+
+/// Interface `can0` at index 1
+pub fn can0() -> CanInterfaceInfo {
+    CanInterfaceInfo {
+        idx: 1,
+        name: "can0".to_string(),
+    }
+}
+
+/// Interface `can1` at index 2
+pub fn can1() -> CanInterfaceInfo {
+    CanInterfaceInfo {
+        idx: 2,
+        name: "can1".to_string(),
+    }
+}
@@ -0,0 +1,110 @@
+//! Tests against a virtual CAN interface
+//!
+//! These need a vcan interface that is up, `vcan0` unless `CANSENTINEL_VCAN` names another one.
+//! Creating it needs root (or CAP_NET_ADMIN), running the tests doesn't:
+//!
+//! ```sh
+//! sudo modprobe vcan
+//! sudo ip link add vcan0 type vcan
+//! sudo ip link set vcan0 up
+//! ```
+//!
+//! Each test passes without checking anything if the interface doesn't exist. vcan echoes every
+//! frame, error frames included, to the other sockets on the interface, which is how errors are
+//! injected here. It has no controller state, so netlink and polling never report bus-off on it.
+
+use cansentinel::{
+    BusEvent, BusEventType, CanInterfaceInfo,
+    events::BusEventSource,
+    monitoring::{ErrorMonitorOptions, monitor_interface_errors},
+};
+use socketcan::{CanErrorFrame, CanSocket, Socket};
+use std::time::Duration;
+use tokio::{sync::mpsc, time::timeout};
+
+/// CAN_ERR_BUSOFF from linux/can/error.h
+const CAN_ERR_BUSOFF: u32 = 0x0040;
+/// CAN_ERR_RESTARTED from linux/can/error.h
+const CAN_ERR_RESTARTED: u32 = 0x0100;
+
+/// The vcan interface to test against, if it exists
+fn vcan() -> Option<CanInterfaceInfo> {
+    let name = std::env::var("CANSENTINEL_VCAN").unwrap_or_else(|_| "vcan0".to_string());
+    match CanInterfaceInfo::new(&name) {
+        Ok(interface) => Some(interface),
+        Err(e) => {
+            eprintln!("Skipping, {} is not available: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Send `class` as an error frame on `interface` until `rx` yields an event
+///
+/// Resent because the monitor may not have opened its socket yet when the first one goes out.
+async fn inject(
+    interface: &CanInterfaceInfo,
+    class: u32,
+    rx: &mut mpsc::UnboundedReceiver<BusEvent>,
+) -> BusEvent {
+    let socket = CanSocket::open(&interface.name).unwrap();
+    let frame = CanErrorFrame::new_error(class, &[]).unwrap();
+    for _ in 0..20 {
+        socket.write_frame(&frame).unwrap();
+        if let Ok(event) = timeout(Duration::from_millis(100), rx.recv()).await {
+            return event.unwrap();
+        }
+    }
+    panic!("no event for error class {:#x}", class);
+}
+
+#[tokio::test]
+async fn error_frames_on_vcan_map_to_bus_events() {
+    let Some(interface) = vcan() else {
+        return;
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let monitor = tokio::spawn(monitor_interface_errors(
+        tx,
+        interface.clone(),
+        ErrorMonitorOptions::default(),
+    ));
+
+    let bus_off = inject(&interface, CAN_ERR_BUSOFF, &mut rx).await;
+    assert_eq!(bus_off.event_type, BusEventType::BusOff);
+    assert_eq!(bus_off.interface.idx, interface.idx);
+    assert!(matches!(
+        bus_off.event_source,
+        BusEventSource::ErrorFrame(_)
+    ));
+
+    // Later copies of the bus-off frame may still be queued
+    let restart = loop {
+        let event = inject(&interface, CAN_ERR_RESTARTED, &mut rx).await;
+        if event.event_type != BusEventType::BusOff {
+            break event;
+        }
+    };
+    assert_eq!(restart.event_type, BusEventType::Restart);
+
+    monitor.abort();
+}
+
+#[test]
+fn from_index_finds_the_interface_by_index() {
+    let Some(interface) = vcan() else {
+        return;
+    };
+    assert_eq!(CanInterfaceInfo::from_index(interface.idx), Ok(interface));
+}
+
+#[test]
+fn sysfs_reports_an_up_interface_as_error_active() {
+    let Some(interface) = vcan() else {
+        return;
+    };
+    assert_eq!(
+        cansentinel::monitoring::sysfs::read_state(&interface).unwrap(),
+        Some(socketcan::nl::CanState::ErrorActive)
+    );
+}